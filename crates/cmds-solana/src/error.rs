@@ -48,7 +48,11 @@ pub enum Error {
     RecipientAddressNotFunded,
     #[error("specified account: {0} isn't a token account")]
     NotTokenAccount(solana_sdk::pubkey::Pubkey),
-    #[error("insufficient solana balance, needed={needed}; have={balance};")]
+    #[error(
+        "insufficient solana balance, needed {} SOL; have {} SOL",
+        flow_lib::solana::lamports_to_sol(*.needed),
+        flow_lib::solana::lamports_to_sol(*.balance)
+    )]
     InsufficientSolanaBalance { needed: u64, balance: u64 },
     #[error("failed to snapshot mints: {0}")]
     ErrorSnapshottingMints(String),
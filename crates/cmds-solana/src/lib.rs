@@ -18,6 +18,7 @@ pub mod mint_token;
 pub mod nft;
 // pub mod proxy_authority;
 pub mod request_airdrop;
+pub mod send_prebuilt_transaction;
 pub mod transfer_sol;
 pub mod transfer_token;
 pub mod utils;
@@ -0,0 +1,74 @@
+use crate::prelude::*;
+use solana_sdk::transaction::VersionedTransaction;
+
+const NAME: &str = "send_prebuilt_transaction";
+
+const DEFINITION: &str = flow_lib::node_definition!("send_prebuilt_transaction.json");
+
+flow_lib::submit!(CommandDescription::new(NAME, |_| build()));
+
+fn build() -> BuildResult {
+    static CACHE: BuilderCache =
+        BuilderCache::new(|| CmdBuilder::new(DEFINITION)?.check_name(NAME));
+    Ok(CACHE.clone()?.build(run))
+}
+
+#[serde_as]
+#[derive(Deserialize, Debug)]
+pub struct Input {
+    /// Base64-encoded transaction, e.g. `swapTransaction` of Jupiter's swap API.
+    transaction: String,
+    /// Keypairs or adapter wallets to sign with, signatures of other signers must already
+    /// be in the transaction.
+    #[serde_as(as = "Vec<AsKeypair>")]
+    #[serde(default)]
+    signers: Vec<Keypair>,
+}
+
+#[serde_as]
+#[derive(Serialize, Debug)]
+pub struct Output {
+    #[serde_as(as = "AsSignature")]
+    signature: Signature,
+}
+
+fn decode(transaction: &str) -> Result<VersionedTransaction, CommandError> {
+    let bytes = base64::decode(transaction.trim())?;
+    Ok(bincode::deserialize(&bytes)?)
+}
+
+async fn run(mut ctx: Context, input: Input) -> Result<Output, CommandError> {
+    let tx = decode(&input.transaction)?;
+    let signature = ctx
+        .execute_transaction(tx, input.signers, <_>::default())
+        .await?;
+    Ok(Output { signature })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::{hash::Hash, message::Message, system_instruction::transfer};
+
+    #[test]
+    fn test_build() {
+        build().unwrap();
+    }
+
+    #[test]
+    fn test_decode() {
+        let payer = Pubkey::new_unique();
+        let message = Message::new_with_blockhash(
+            &[transfer(&payer, &Pubkey::new_unique(), 1)],
+            Some(&payer),
+            &Hash::new_unique(),
+        );
+        let tx = VersionedTransaction {
+            signatures: vec![Signature::default()],
+            message: solana_sdk::message::VersionedMessage::Legacy(message),
+        };
+        let encoded = base64::encode(bincode::serialize(&tx).unwrap());
+        assert_eq!(decode(&encoded).unwrap(), tx);
+        assert!(decode("not base64").is_err());
+    }
+}
@@ -8,9 +8,7 @@ use rust_decimal::{
     prelude::{MathematicalOps, ToPrimitive},
     Decimal,
 };
-use solana_program::{
-    hash::Hash, instruction::Instruction, message::Message, native_token::LAMPORTS_PER_SOL,
-};
+use solana_program::{hash::Hash, instruction::Instruction, message::Message};
 use solana_sdk::{signature::Presigner, transaction::Transaction};
 use std::{collections::BTreeSet, time::Duration};
 use value::Error as ValueError;
@@ -39,9 +37,7 @@ pub fn sol_to_lamports(amount: Decimal) -> crate::Result<u64> {
     if amount < Decimal::ZERO {
         return Err(ValueError::Custom("amount is negative".into()).into());
     }
-    amount
-        .checked_mul(Decimal::from(LAMPORTS_PER_SOL))
-        .and_then(|d| d.floor().to_u64())
+    flow_lib::solana::sol_to_lamports(amount)
         .ok_or_else(|| ValueError::Custom("value overflow".into()).into())
 }
 
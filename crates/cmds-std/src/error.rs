@@ -21,6 +21,8 @@ pub enum Error {
     WorkerStopped,
     #[error("time-out waiting for signature")]
     SignatureTimeout,
+    #[error("query timed out after {0:?}")]
+    QueryTimeout(std::time::Duration),
     #[error("an error occured while running rhai expression: {0}")]
     RhaiExecutionError(String),
     #[error("value not found in field \"{0}\"")]
@@ -0,0 +1,39 @@
+use super::{collection_value, FilterFragment, FilterOutput};
+use flow_lib::command::prelude::*;
+
+const NAME: &str = "postgrest_builder_contained_in";
+
+#[derive(Deserialize, Debug)]
+struct Input {
+    #[serde(default)]
+    query: Option<postgrest::Query>,
+    column: String,
+    values: Value,
+}
+
+async fn run(_: Context, input: Input) -> Result<FilterOutput, CommandError> {
+    Ok(FilterOutput::new(
+        input.query,
+        FilterFragment::literal(input.column, "cd", collection_value(&input.values)?),
+    ))
+}
+
+fn build() -> BuildResult {
+    Ok(CmdBuilder::new(flow_lib::node_definition!(
+        "postgrest/builder_contained_in.json"
+    ))?
+    .check_name(NAME)?
+    .build(run))
+}
+
+flow_lib::submit!(CommandDescription::new(NAME, |_| build()));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build() {
+        build().unwrap();
+    }
+}
@@ -0,0 +1,70 @@
+use super::{collection_value, FilterFragment, FilterOutput};
+use flow_lib::command::prelude::*;
+
+const NAME: &str = "postgrest_builder_contains";
+
+#[derive(Deserialize, Debug)]
+struct Input {
+    #[serde(default)]
+    query: Option<postgrest::Query>,
+    column: String,
+    values: Value,
+}
+
+async fn run(_: Context, input: Input) -> Result<FilterOutput, CommandError> {
+    Ok(FilterOutput::new(
+        input.query,
+        FilterFragment::literal(input.column, "cs", collection_value(&input.values)?),
+    ))
+}
+
+fn build() -> BuildResult {
+    Ok(CmdBuilder::new(flow_lib::node_definition!(
+        "postgrest/builder_contains.json"
+    ))?
+    .check_name(NAME)?
+    .build(run))
+}
+
+flow_lib::submit!(CommandDescription::new(NAME, |_| build()));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::postgrest::tests::{new_query, query_pairs};
+
+    #[test]
+    fn test_build() {
+        build().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        async fn t(values: Value) -> String {
+            let output = run(
+                Context::default(),
+                Input {
+                    query: Some(new_query()),
+                    column: "tags".to_owned(),
+                    values,
+                },
+            )
+            .await
+            .unwrap();
+            query_pairs(output.query.unwrap()).pop().unwrap().1
+        }
+        assert_eq!(
+            t(Value::Array(vec![
+                Value::from("rust"),
+                Value::from("hello world"),
+                Value::from("a,b"),
+            ]))
+            .await,
+            r#"cs.{rust,"hello world","a,b"}"#
+        );
+        assert_eq!(
+            t(Value::Map(value::map! { "k" => "v" })).await,
+            r#"cs.{"k":"v"}"#
+        );
+    }
+}
@@ -0,0 +1,81 @@
+use super::{marker, set_header, set_prefer};
+use flow_lib::command::prelude::*;
+
+const NAME: &str = "postgrest_builder_count";
+
+/// https://postgrest.org/en/stable/references/api/pagination_count.html
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Count {
+    #[default]
+    Exact,
+    Planned,
+    Estimated,
+}
+
+impl Count {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Count::Exact => "exact",
+            Count::Planned => "planned",
+            Count::Estimated => "estimated",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct Input {
+    query: postgrest::Query,
+    #[serde(default)]
+    count: Count,
+}
+
+#[derive(Serialize, Debug)]
+struct Output {
+    query: postgrest::Query,
+}
+
+/// Count rows with a `HEAD` request,
+/// the result will be in `total_count` output of `execute_query`.
+async fn run(_: Context, input: Input) -> Result<Output, CommandError> {
+    let mut query = input.query;
+    set_prefer(&mut query, "count", input.count.as_str());
+    set_header(&mut query, marker::HEAD, "true".to_owned());
+    Ok(Output { query })
+}
+
+fn build() -> BuildResult {
+    Ok(
+        CmdBuilder::new(flow_lib::node_definition!("postgrest/builder_count.json"))?
+            .check_name(NAME)?
+            .build(run),
+    )
+}
+
+flow_lib::submit!(CommandDescription::new(NAME, |_| build()));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::postgrest::{get_header, get_prefer, tests::new_query};
+
+    #[test]
+    fn test_build() {
+        build().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let output = run(
+            Context::default(),
+            Input {
+                query: new_query(),
+                count: Count::Planned,
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(get_prefer(&output.query, "count"), Some("planned"));
+        assert!(get_header(&output.query, marker::HEAD).is_some());
+    }
+}
@@ -0,0 +1,119 @@
+use super::{marker, set_header, set_rollback, ReturnMode};
+use flow_lib::command::prelude::*;
+
+const NAME: &str = "postgrest_builder_delete";
+
+fn default_require_filter() -> bool {
+    true
+}
+
+#[derive(Deserialize, Debug)]
+struct Input {
+    query: postgrest::Query,
+    /// Make `execute_query` refuse to run if no filter is applied,
+    /// to prevent accidentally deleting the whole table.
+    #[serde(default = "default_require_filter")]
+    require_filter: bool,
+    #[serde(default)]
+    returning: ReturnMode,
+    /// Roll back the write after running it, see [`set_rollback`].
+    #[serde(default)]
+    rollback: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct Output {
+    query: postgrest::Query,
+}
+
+async fn run(ctx: Context, input: Input) -> Result<Output, CommandError> {
+    let mut query: postgrest::Query = postgrest::Builder::from_query(input.query, ctx.http)
+        .delete()
+        .into();
+    input.returning.apply(&mut query);
+    if input.rollback {
+        set_rollback(&mut query);
+    }
+    if input.require_filter {
+        set_header(&mut query, marker::REQUIRE_FILTER, "true".to_owned());
+    }
+    Ok(Output { query })
+}
+
+fn build() -> BuildResult {
+    Ok(
+        CmdBuilder::new(flow_lib::node_definition!("postgrest/builder_delete.json"))?
+            .check_name(NAME)?
+            .build(run),
+    )
+}
+
+flow_lib::submit!(CommandDescription::new(NAME, |_| build()));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build() {
+        build().unwrap();
+    }
+
+    /// Answer every request with 3 deleted rows, returns the URL of a table.
+    fn mock_table() -> String {
+        use std::io::{BufRead, BufReader, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/rest/v1/table", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut reader = BufReader::new(stream.unwrap());
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap_or(0) > 0 && line != "\r\n" {
+                    line.clear();
+                }
+                let resp = "HTTP/1.1 204 No Content\r\ncontent-range: */3\r\n\
+                            connection: close\r\n\r\n";
+                reader.get_mut().write_all(resp.as_bytes()).unwrap();
+            }
+        });
+        url
+    }
+
+    async fn delete(
+        url: &str,
+        require_filter: bool,
+        filtered: bool,
+    ) -> Result<ValueSet, CommandError> {
+        let mut builder =
+            postgrest::Builder::new(url, None, <_>::default(), reqwest::Client::new());
+        if filtered {
+            builder = builder.eq("id", "1");
+        }
+        let input = Input {
+            query: builder.into(),
+            require_filter,
+            returning: ReturnMode::Minimal,
+            rollback: false,
+        };
+        let query = run(Context::default(), input).await?.query;
+        let inputs = value::to_map(&serde_json::json!({ "query": query }))?;
+        crate::postgrest::execute_query::build()?
+            .run(Context::default(), inputs)
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_require_filter() {
+        let url = mock_table();
+        let error = delete(&url, true, false).await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "refusing to DELETE without filters, set `require_filter` to false to allow"
+        );
+        let output = delete(&url, false, false).await.unwrap();
+        assert_eq!(output["affected_rows"], Value::U64(3));
+        let output = delete(&url, true, true).await.unwrap();
+        assert_eq!(output["affected_rows"], Value::U64(3));
+    }
+}
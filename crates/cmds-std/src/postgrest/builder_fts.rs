@@ -0,0 +1,99 @@
+use super::{FilterFragment, FilterOutput};
+use flow_lib::command::prelude::*;
+
+const NAME: &str = "postgrest_builder_fts";
+
+/// Which `to_tsquery` function PostgREST will use.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Mode {
+    /// `to_tsquery`
+    #[default]
+    Fts,
+    /// `plainto_tsquery`
+    Plfts,
+    /// `phraseto_tsquery`
+    Phfts,
+    /// `websearch_to_tsquery`
+    Wfts,
+}
+
+impl Mode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Mode::Fts => "fts",
+            Mode::Plfts => "plfts",
+            Mode::Phfts => "phfts",
+            Mode::Wfts => "wfts",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct Input {
+    #[serde(default)]
+    query: Option<postgrest::Query>,
+    column: String,
+    /// Search text, it will be URL-encoded when the request is built.
+    text: String,
+    #[serde(default)]
+    config: Option<String>,
+    #[serde(default)]
+    mode: Mode,
+}
+
+async fn run(_: Context, input: Input) -> Result<FilterOutput, CommandError> {
+    let operator = match input.config {
+        Some(config) => format!("{}({})", input.mode.as_str(), config),
+        None => input.mode.as_str().to_owned(),
+    };
+    Ok(FilterOutput::new(
+        input.query,
+        FilterFragment::new(input.column, &operator, input.text),
+    ))
+}
+
+fn build() -> BuildResult {
+    Ok(
+        CmdBuilder::new(flow_lib::node_definition!("postgrest/builder_fts.json"))?
+            .check_name(NAME)?
+            .build(run),
+    )
+}
+
+flow_lib::submit!(CommandDescription::new(NAME, |_| build()));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::postgrest::tests::{new_query, query_pairs};
+
+    #[test]
+    fn test_build() {
+        build().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        async fn t(config: Option<&str>, mode: Mode) -> String {
+            let output = run(
+                Context::default(),
+                Input {
+                    query: Some(new_query()),
+                    column: "body".to_owned(),
+                    text: "the query".to_owned(),
+                    config: config.map(str::to_owned),
+                    mode,
+                },
+            )
+            .await
+            .unwrap();
+            query_pairs(output.query.unwrap()).pop().unwrap().1
+        }
+        assert_eq!(
+            t(Some("english"), Mode::Fts).await,
+            "fts(english).the query"
+        );
+        assert_eq!(t(None, Mode::Wfts).await, "wfts.the query");
+    }
+}
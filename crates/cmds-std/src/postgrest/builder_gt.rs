@@ -0,0 +1,58 @@
+use super::{filter_value, FilterFragment, FilterOutput};
+use flow_lib::command::prelude::*;
+
+const NAME: &str = "postgrest_builder_gt";
+
+#[derive(Deserialize, Debug)]
+struct Input {
+    #[serde(default)]
+    query: Option<postgrest::Query>,
+    column: String,
+    value: Value,
+}
+
+async fn run(_: Context, input: Input) -> Result<FilterOutput, CommandError> {
+    Ok(FilterOutput::new(
+        input.query,
+        FilterFragment::new(input.column, "gt", filter_value(&input.value)?),
+    ))
+}
+
+fn build() -> BuildResult {
+    Ok(
+        CmdBuilder::new(flow_lib::node_definition!("postgrest/builder_gt.json"))?
+            .check_name(NAME)?
+            .build(run),
+    )
+}
+
+flow_lib::submit!(CommandDescription::new(NAME, |_| build()));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::postgrest::tests::{new_query, query_pairs};
+
+    #[test]
+    fn test_build() {
+        build().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let output = run(
+            Context::default(),
+            Input {
+                query: Some(new_query()),
+                column: "id".to_owned(),
+                value: Value::U64(5),
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            query_pairs(output.query.unwrap()),
+            [("id".to_owned(), "gt.5".to_owned())]
+        );
+    }
+}
@@ -0,0 +1,39 @@
+use super::{filter_value, FilterFragment, FilterOutput};
+use flow_lib::command::prelude::*;
+
+const NAME: &str = "postgrest_builder_gte";
+
+#[derive(Deserialize, Debug)]
+struct Input {
+    #[serde(default)]
+    query: Option<postgrest::Query>,
+    column: String,
+    value: Value,
+}
+
+async fn run(_: Context, input: Input) -> Result<FilterOutput, CommandError> {
+    Ok(FilterOutput::new(
+        input.query,
+        FilterFragment::new(input.column, "gte", filter_value(&input.value)?),
+    ))
+}
+
+fn build() -> BuildResult {
+    Ok(
+        CmdBuilder::new(flow_lib::node_definition!("postgrest/builder_gte.json"))?
+            .check_name(NAME)?
+            .build(run),
+    )
+}
+
+flow_lib::submit!(CommandDescription::new(NAME, |_| build()));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build() {
+        build().unwrap();
+    }
+}
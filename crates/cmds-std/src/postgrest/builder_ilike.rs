@@ -0,0 +1,63 @@
+use super::{FilterFragment, FilterOutput};
+use flow_lib::command::prelude::*;
+
+const NAME: &str = "postgrest_builder_ilike";
+
+#[derive(Deserialize, Debug)]
+struct Input {
+    #[serde(default)]
+    query: Option<postgrest::Query>,
+    column: String,
+    /// `*` is PostgREST's URL-safe wildcard and matches any sequence of characters.
+    ///
+    /// The pattern is sent as-is: `%` and `_` keep their SQL LIKE meaning and can be
+    /// escaped with `\`, reserved URL characters are percent-encoded when the request
+    /// is built.
+    pattern: String,
+}
+
+async fn run(_: Context, input: Input) -> Result<FilterOutput, CommandError> {
+    Ok(FilterOutput::new(
+        input.query,
+        FilterFragment::new(input.column, "ilike", input.pattern),
+    ))
+}
+
+fn build() -> BuildResult {
+    Ok(
+        CmdBuilder::new(flow_lib::node_definition!("postgrest/builder_ilike.json"))?
+            .check_name(NAME)?
+            .build(run),
+    )
+}
+
+flow_lib::submit!(CommandDescription::new(NAME, |_| build()));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::postgrest::tests::{new_query, query_pairs};
+
+    #[test]
+    fn test_build() {
+        build().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let output = run(
+            Context::default(),
+            Input {
+                query: Some(new_query()),
+                column: "name".to_owned(),
+                pattern: "*john%".to_owned(),
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            query_pairs(output.query.unwrap()),
+            [("name".to_owned(), "ilike.*john%".to_owned())]
+        );
+    }
+}
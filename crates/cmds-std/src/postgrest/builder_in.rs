@@ -0,0 +1,78 @@
+use super::{list_element, FilterFragment, FilterOutput};
+use flow_lib::command::prelude::*;
+
+const NAME: &str = "postgrest_builder_in";
+
+#[derive(Deserialize, Debug)]
+struct Input {
+    #[serde(default)]
+    query: Option<postgrest::Query>,
+    column: String,
+    values: Vec<Value>,
+}
+
+async fn run(_: Context, input: Input) -> Result<FilterOutput, CommandError> {
+    // an empty list is still valid, `in.()` matches nothing
+    let values = input
+        .values
+        .iter()
+        .map(list_element)
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(FilterOutput::new(
+        input.query,
+        FilterFragment::literal(input.column, "in", format!("({})", values.join(","))),
+    ))
+}
+
+fn build() -> BuildResult {
+    Ok(
+        CmdBuilder::new(flow_lib::node_definition!("postgrest/builder_in.json"))?
+            .check_name(NAME)?
+            .build(run),
+    )
+}
+
+flow_lib::submit!(CommandDescription::new(NAME, |_| build()));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::postgrest::tests::{new_query, query_pairs};
+
+    #[test]
+    fn test_build() {
+        build().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        async fn t(values: Vec<Value>) -> String {
+            let output = run(
+                Context::default(),
+                Input {
+                    query: Some(new_query()),
+                    column: "name".to_owned(),
+                    values,
+                },
+            )
+            .await
+            .unwrap();
+            query_pairs(output.query.unwrap()).pop().unwrap().1
+        }
+        assert_eq!(
+            t(vec![
+                Value::from("a"),
+                Value::from("b,c"),
+                Value::from("(d)"),
+                Value::from(5u64)
+            ])
+            .await,
+            r#"in.(a,"b,c","(d)",5)"#
+        );
+        assert_eq!(
+            t(vec![Value::from(r#"say "hi""#)]).await,
+            r#"in.("say \"hi\"")"#
+        );
+        assert_eq!(t(Vec::new()).await, "in.()");
+    }
+}
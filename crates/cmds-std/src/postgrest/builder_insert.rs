@@ -1,11 +1,32 @@
+use super::{
+    builder_select::{apply_returning_select, Columns},
+    set_idempotency_key, set_prefer, set_rollback, ReturnMode, DEFAULT_IDEMPOTENCY_COLUMN,
+};
+use anyhow::{anyhow, bail};
 use flow_lib::command::prelude::*;
+use std::collections::BTreeSet;
 
 const NAME: &str = "postgrest_builder_insert";
 
 #[derive(Deserialize, Debug)]
 struct Input {
     query: postgrest::Query,
+    /// A row object, or an array of rows for bulk insert.
     body: JsonValue,
+    #[serde(default)]
+    returning: ReturnMode,
+    /// Columns of returned rows, see [`apply_returning_select`].
+    #[serde(default)]
+    select: Option<Columns>,
+    /// Roll back the write after running it, see [`set_rollback`].
+    #[serde(default)]
+    rollback: bool,
+    /// Insert rows only once for this key, see [`set_idempotency_key`].
+    #[serde(default)]
+    idempotency_key: Option<String>,
+    /// Default to `idempotency_key`.
+    #[serde(default)]
+    idempotency_column: Option<String>,
 }
 
 #[derive(Serialize, Debug)]
@@ -13,12 +34,60 @@ struct Output {
     query: postgrest::Query,
 }
 
-async fn run(ctx: Context, input: Input) -> Result<Output, CommandError> {
-    Ok(Output {
-        query: postgrest::Builder::from_query(input.query, ctx.http)
-            .insert(serde_json::to_string(&input.body)?)
-            .into(),
-    })
+/// PostgREST requires all rows of a bulk insert to have the same keys.
+fn check_rows(rows: &[JsonValue]) -> Result<(), CommandError> {
+    let keys = |index: usize, row: &JsonValue| {
+        row.as_object()
+            .map(|row| row.keys().map(String::as_str).collect::<BTreeSet<_>>())
+            .ok_or_else(|| anyhow!("row {} is not an object", index))
+    };
+    let Some(first) = rows.first() else {
+        return Ok(());
+    };
+    let expected = keys(0, first)?;
+    for (index, row) in rows.iter().enumerate().skip(1) {
+        let keys = keys(index, row)?;
+        if keys != expected {
+            let missing = expected.difference(&keys).collect::<Vec<_>>();
+            let unexpected = keys.difference(&expected).collect::<Vec<_>>();
+            bail!(
+                "row {} has different keys than row 0, missing: {:?}, unexpected: {:?}",
+                index,
+                missing,
+                unexpected
+            );
+        }
+    }
+    Ok(())
+}
+
+async fn run(ctx: Context, mut input: Input) -> Result<Output, CommandError> {
+    if let JsonValue::Array(rows) = &input.body {
+        check_rows(rows)?;
+    }
+    let builder = postgrest::Builder::from_query(input.query, ctx.http);
+    let mut query: postgrest::Query = match input.idempotency_key.filter(|k| !k.is_empty()) {
+        Some(key) => {
+            let column = input
+                .idempotency_column
+                .as_deref()
+                .unwrap_or(DEFAULT_IDEMPOTENCY_COLUMN);
+            set_idempotency_key(&mut input.body, column, &key)?;
+            let mut query: postgrest::Query = builder
+                .upsert(serde_json::to_string(&input.body)?)
+                .on_conflict(column)
+                .into();
+            set_prefer(&mut query, "resolution", "ignore-duplicates");
+            query
+        }
+        None => builder.insert(serde_json::to_string(&input.body)?).into(),
+    };
+    input.returning.apply(&mut query);
+    apply_returning_select(&mut query, input.returning, input.select.as_ref())?;
+    if input.rollback {
+        set_rollback(&mut query);
+    }
+    Ok(Output { query })
 }
 
 fn build() -> BuildResult {
@@ -34,9 +103,207 @@ flow_lib::submit!(CommandDescription::new(NAME, |_| build()));
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::postgrest::{
+        get_prefer,
+        tests::{new_query, query_pairs},
+    };
 
     #[test]
     fn test_build() {
         build().unwrap();
     }
+
+    #[tokio::test]
+    async fn test_bulk_insert() {
+        let rows = (0..500)
+            .map(|i| serde_json::json!({ "id": i, "name": format!("row {}", i) }))
+            .collect::<Vec<_>>();
+        let output = run(
+            Context::default(),
+            Input {
+                query: new_query(),
+                body: JsonValue::Array(rows.clone()),
+                returning: ReturnMode::Minimal,
+                select: None,
+                rollback: false,
+                idempotency_key: None,
+                idempotency_column: None,
+            },
+        )
+        .await
+        .unwrap();
+        let body = output.query.body.unwrap();
+        assert_eq!(
+            serde_json::from_str::<JsonValue>(&body).unwrap(),
+            JsonValue::Array(rows)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_idempotency_key() {
+        let input = || Input {
+            query: new_query(),
+            body: serde_json::json!({ "name": "a" }),
+            returning: ReturnMode::Minimal,
+            select: None,
+            rollback: false,
+            idempotency_key: Some("run-1".to_owned()),
+            idempotency_column: None,
+        };
+        let first = run(Context::default(), input()).await.unwrap().query;
+        assert_eq!(get_prefer(&first, "resolution"), Some("ignore-duplicates"));
+        assert_eq!(
+            serde_json::from_str::<JsonValue>(first.body.as_deref().unwrap()).unwrap(),
+            serde_json::json!({ "name": "a", "idempotency_key": "run-1" })
+        );
+
+        // executing again sends the same row, which is ignored by the unique constraint
+        let second = run(Context::default(), input()).await.unwrap().query;
+        assert_eq!(second.body, first.body);
+        assert_eq!(
+            query_pairs(first),
+            [("on_conflict".to_owned(), "idempotency_key".to_owned())]
+        );
+        assert_eq!(
+            query_pairs(second),
+            [("on_conflict".to_owned(), "idempotency_key".to_owned())]
+        );
+    }
+
+    /// Insert rows with a new `idempotency_key` and ignore the others, like a unique
+    /// constraint. Returns the URL of a table and the path, `Prefer` header and number of
+    /// inserted rows of each request.
+    fn mock_unique_table() -> (String, std::sync::mpsc::Receiver<(String, String, usize)>) {
+        use std::io::{BufRead, BufReader, Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/rest/v1/table", listener.local_addr().unwrap());
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let mut keys = BTreeSet::new();
+            for stream in listener.incoming() {
+                let mut reader = BufReader::new(stream.unwrap());
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let path = line.split(' ').nth(1).unwrap_or_default().to_owned();
+                let (mut len, mut prefer) = (0, String::new());
+                line.clear();
+                while reader.read_line(&mut line).unwrap_or(0) > 0 && line != "\r\n" {
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            len = value.trim().parse().unwrap();
+                        } else if name.eq_ignore_ascii_case("prefer") {
+                            prefer = value.trim().to_owned();
+                        }
+                    }
+                    line.clear();
+                }
+                let mut body = vec![0; len];
+                reader.read_exact(&mut body).unwrap();
+                let row = serde_json::from_slice::<JsonValue>(&body).unwrap();
+                let inserted = usize::from(keys.insert(row["idempotency_key"].to_string()));
+                tx.send((path, prefer, inserted)).unwrap();
+                let resp = format!(
+                    "HTTP/1.1 201 Created\r\ncontent-range: */{}\r\n\
+                     content-length: 0\r\nconnection: close\r\n\r\n",
+                    inserted
+                );
+                reader.get_mut().write_all(resp.as_bytes()).unwrap();
+            }
+        });
+        (url, rx)
+    }
+
+    #[tokio::test]
+    async fn test_idempotency_key_execute() {
+        let (url, rx) = mock_unique_table();
+        let execute = crate::postgrest::execute_query::build().unwrap();
+        let mut affected_rows = Vec::new();
+        for _ in 0..2 {
+            let query = run(
+                Context::default(),
+                Input {
+                    query: postgrest::Builder::new(
+                        &url,
+                        None,
+                        <_>::default(),
+                        reqwest::Client::new(),
+                    )
+                    .into(),
+                    body: serde_json::json!({ "name": "a" }),
+                    returning: ReturnMode::Minimal,
+                    select: None,
+                    rollback: false,
+                    idempotency_key: Some("run-1".to_owned()),
+                    idempotency_column: None,
+                },
+            )
+            .await
+            .unwrap()
+            .query;
+            let inputs = value::to_map(&serde_json::json!({ "query": query })).unwrap();
+            let output = execute.run(Context::default(), inputs).await.unwrap();
+            affected_rows.push(output["affected_rows"].clone());
+        }
+
+        let requests = rx.try_iter().collect::<Vec<_>>();
+        assert_eq!(requests.len(), 2);
+        for (path, prefer, _) in &requests {
+            assert_eq!(path, "/rest/v1/table?on_conflict=idempotency_key");
+            assert!(
+                prefer.contains("resolution=ignore-duplicates"),
+                "{}",
+                prefer
+            );
+        }
+        // the second run sends the same row, which the unique constraint ignores
+        assert_eq!(
+            requests.iter().map(|(_, _, n)| *n).collect::<Vec<_>>(),
+            [1, 0]
+        );
+        assert_eq!(affected_rows, [Value::U64(1), Value::U64(0)]);
+    }
+
+    #[tokio::test]
+    async fn test_select() {
+        let input = |returning| Input {
+            query: new_query(),
+            body: serde_json::json!({ "name": "a" }),
+            returning,
+            select: Some(Columns::Raw("id, created_at".to_owned())),
+            rollback: false,
+            idempotency_key: None,
+            idempotency_column: None,
+        };
+        let query = run(Context::default(), input(ReturnMode::Representation))
+            .await
+            .unwrap()
+            .query;
+        let req = postgrest::Builder::from_query(query.clone(), reqwest::Client::new())
+            .build()
+            .build()
+            .unwrap();
+        assert_eq!(req.method(), reqwest::Method::POST);
+        assert_eq!(
+            query_pairs(query),
+            [("select".to_owned(), "id,created_at".to_owned())]
+        );
+
+        assert!(run(Context::default(), input(ReturnMode::Minimal))
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn test_check_rows() {
+        let error = check_rows(&[
+            serde_json::json!({ "id": 1, "name": "a" }),
+            serde_json::json!({ "id": 2, "title": "b" }),
+        ])
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            r#"row 1 has different keys than row 0, missing: ["name"], unexpected: ["title"]"#
+        );
+    }
 }
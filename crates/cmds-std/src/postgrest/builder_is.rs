@@ -1,25 +1,72 @@
+use super::{filter_value, FilterFragment, FilterOutput};
+use anyhow::bail;
 use flow_lib::command::prelude::*;
 
 const NAME: &str = "postgrest_builder_is";
 
+/// Operands of `is`, `unknown` matches null in boolean columns.
+///
+/// Case-insensitive, flows made when `filter` was a string may use `NULL` or `TRUE`.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String")]
+enum IsValue {
+    Null,
+    True,
+    False,
+    Unknown,
+}
+
+impl IsValue {
+    fn as_str(&self) -> &'static str {
+        match self {
+            IsValue::Null => "null",
+            IsValue::True => "true",
+            IsValue::False => "false",
+            IsValue::Unknown => "unknown",
+        }
+    }
+}
+
+impl TryFrom<String> for IsValue {
+    type Error = anyhow::Error;
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Ok(match value.trim().to_ascii_lowercase().as_str() {
+            "null" => IsValue::Null,
+            "true" => IsValue::True,
+            "false" => IsValue::False,
+            "unknown" => IsValue::Unknown,
+            _ => bail!(
+                "invalid filter {:?}, expected null, true, false or unknown",
+                value
+            ),
+        })
+    }
+}
+
 #[derive(Deserialize, Debug)]
 struct Input {
-    query: postgrest::Query,
+    #[serde(default)]
+    query: Option<postgrest::Query>,
     column: String,
-    filter: String,
+    #[serde(default)]
+    filter: Option<IsValue>,
+    /// Use `IS DISTINCT FROM` instead, which treats null as a comparable value.
+    #[serde(default)]
+    distinct_from: Option<Value>,
 }
 
-#[derive(Serialize, Debug)]
-struct Output {
-    query: postgrest::Query,
-}
-
-async fn run(ctx: Context, input: Input) -> Result<Output, CommandError> {
-    Ok(Output {
-        query: postgrest::Builder::from_query(input.query, ctx.http)
-            .is(input.column, input.filter)
-            .into(),
-    })
+async fn run(_: Context, input: Input) -> Result<FilterOutput, CommandError> {
+    let fragment = match (input.filter, input.distinct_from) {
+        (Some(filter), None) => {
+            FilterFragment::literal(input.column, "is", filter.as_str().to_owned())
+        }
+        (None, Some(value)) => {
+            FilterFragment::new(input.column, "isdistinct", filter_value(&value)?)
+        }
+        (Some(_), Some(_)) => bail!("filter and distinct_from can't be used together"),
+        (None, None) => bail!("either filter or distinct_from is required"),
+    };
+    Ok(FilterOutput::new(input.query, fragment))
 }
 
 fn build() -> BuildResult {
@@ -35,9 +82,63 @@ flow_lib::submit!(CommandDescription::new(NAME, |_| build()));
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::postgrest::tests::{new_query, query_pairs};
 
     #[test]
     fn test_build() {
         build().unwrap();
     }
+
+    #[tokio::test]
+    async fn test_run() {
+        async fn t(filter: Option<IsValue>, distinct_from: Option<Value>) -> String {
+            let output = run(
+                Context::default(),
+                Input {
+                    query: Some(new_query()),
+                    column: "flag".to_owned(),
+                    filter,
+                    distinct_from,
+                },
+            )
+            .await
+            .unwrap();
+            query_pairs(output.query.unwrap()).pop().unwrap().1
+        }
+
+        assert_eq!(t(Some(IsValue::Null), None).await, "is.null");
+        assert_eq!(t(Some(IsValue::True), None).await, "is.true");
+        assert_eq!(t(Some(IsValue::False), None).await, "is.false");
+        assert_eq!(t(Some(IsValue::Unknown), None).await, "is.unknown");
+        assert_eq!(
+            t(None, Some(Value::String("a".to_owned()))).await,
+            "isdistinct.a"
+        );
+        assert_eq!(
+            value::from_value::<IsValue>(Value::String("unknown".to_owned())).unwrap(),
+            IsValue::Unknown
+        );
+    }
+
+    #[test]
+    fn test_old_filters() {
+        for (filter, expected) in [
+            ("NULL", IsValue::Null),
+            ("TRUE", IsValue::True),
+            ("False", IsValue::False),
+            (" unknown ", IsValue::Unknown),
+        ] {
+            let input = serde_json::from_value::<Input>(serde_json::json!({
+                "column": "flag",
+                "filter": filter,
+            }))
+            .unwrap();
+            assert_eq!(input.filter, Some(expected));
+        }
+        assert!(serde_json::from_value::<Input>(serde_json::json!({
+            "column": "flag",
+            "filter": "maybe",
+        }))
+        .is_err());
+    }
 }
@@ -1,3 +1,4 @@
+use super::push_filter;
 use flow_lib::command::prelude::*;
 
 const NAME: &str = "postgrest_builder_limit";
@@ -6,6 +7,9 @@ const NAME: &str = "postgrest_builder_limit";
 struct Input {
     query: postgrest::Query,
     count: u64,
+    /// Limit rows of an embedded resource instead, e.g. `authors.limit=2`.
+    #[serde(default)]
+    foreign_table: Option<String>,
 }
 
 #[derive(Serialize, Debug)]
@@ -14,11 +18,21 @@ struct Output {
 }
 
 async fn run(ctx: Context, input: Input) -> Result<Output, CommandError> {
-    Ok(Output {
-        query: postgrest::Builder::from_query(input.query, ctx.http)
+    let query = match input.foreign_table {
+        Some(table) => {
+            let mut query = input.query;
+            push_filter(
+                &mut query,
+                format!("{}.limit", table),
+                input.count.to_string(),
+            );
+            query
+        }
+        None => postgrest::Builder::from_query(input.query, ctx.http)
             .limit(input.count as usize)
             .into(),
-    })
+    };
+    Ok(Output { query })
 }
 
 fn build() -> BuildResult {
@@ -34,9 +48,34 @@ flow_lib::submit!(CommandDescription::new(NAME, |_| build()));
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::postgrest::tests::{new_query, query_pairs};
 
     #[test]
     fn test_build() {
         build().unwrap();
     }
+
+    #[tokio::test]
+    async fn test_foreign_table() {
+        let query = postgrest::Builder::from_query(new_query(), reqwest::Client::new())
+            .select("id,authors(name)")
+            .into();
+        let output = run(
+            Context::default(),
+            Input {
+                query,
+                count: 2,
+                foreign_table: Some("authors".to_owned()),
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            query_pairs(output.query),
+            [
+                ("select".to_owned(), "id,authors(name)".to_owned()),
+                ("authors.limit".to_owned(), "2".to_owned()),
+            ]
+        );
+    }
 }
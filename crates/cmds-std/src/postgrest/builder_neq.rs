@@ -1,25 +1,21 @@
+use super::{FilterFragment, FilterOutput};
 use flow_lib::command::prelude::*;
 
 const NAME: &str = "postgrest_builder_neq";
 
 #[derive(Deserialize, Debug)]
 struct Input {
-    query: postgrest::Query,
+    #[serde(default)]
+    query: Option<postgrest::Query>,
     column: String,
     filter: String,
 }
 
-#[derive(Serialize, Debug)]
-struct Output {
-    query: postgrest::Query,
-}
-
-async fn run(ctx: Context, input: Input) -> Result<Output, CommandError> {
-    Ok(Output {
-        query: postgrest::Builder::from_query(input.query, ctx.http)
-            .neq(input.column, input.filter)
-            .into(),
-    })
+async fn run(_: Context, input: Input) -> Result<FilterOutput, CommandError> {
+    Ok(FilterOutput::new(
+        input.query,
+        FilterFragment::new(input.column, "neq", input.filter),
+    ))
 }
 
 fn build() -> BuildResult {
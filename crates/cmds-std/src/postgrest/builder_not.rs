@@ -1,26 +1,90 @@
+use super::{collection_value, filter_value, list_element, FilterFragment, FilterOutput};
+use anyhow::anyhow;
 use flow_lib::command::prelude::*;
 
 const NAME: &str = "postgrest_builder_not";
 
 #[derive(Deserialize, Debug)]
 struct Input {
-    query: postgrest::Query,
-    operator: String,
-    column: String,
-    filter: String,
+    #[serde(default)]
+    query: Option<postgrest::Query>,
+    /// Output of a filter builder, `operator`, `column`, `filter` and `value` are
+    /// ignored if this is set.
+    #[serde(default)]
+    fragment: Option<FilterFragment>,
+    #[serde(default)]
+    operator: Option<String>,
+    #[serde(default)]
+    column: Option<String>,
+    /// Raw operand, used verbatim, e.g. `(1,2)` for `in`.
+    #[serde(default)]
+    filter: Option<String>,
+    /// Operand formatted according to `operator`, e.g. an array for `in`.
+    #[serde(default)]
+    value: Option<Value>,
 }
 
-#[derive(Serialize, Debug)]
-struct Output {
-    query: postgrest::Query,
+/// Build a fragment from an operator and a value, using the syntax of the operator.
+fn fragment(column: String, operator: &str, value: &Value) -> Result<FilterFragment, CommandError> {
+    Ok(match operator {
+        "in" => {
+            let Value::Array(values) = value else {
+                return Err(anyhow!("`in` expects an array"));
+            };
+            let values = values
+                .iter()
+                .map(list_element)
+                .collect::<Result<Vec<_>, _>>()?;
+            FilterFragment::literal(column, operator, format!("({})", values.join(",")))
+        }
+        "cs" | "cd" | "ov" => FilterFragment::literal(column, operator, collection_value(value)?),
+        "is" => {
+            let operand = match value {
+                Value::Null => "null".to_owned(),
+                Value::Bool(x) => x.to_string(),
+                Value::String(x)
+                    if ["null", "true", "false", "unknown"]
+                        .contains(&x.to_ascii_lowercase().as_str()) =>
+                {
+                    x.to_ascii_lowercase()
+                }
+                _ => return Err(anyhow!("`is` expects null, true, false or unknown")),
+            };
+            FilterFragment::literal(column, operator, operand)
+        }
+        _ => FilterFragment::new(column, operator, filter_value(value)?),
+    })
 }
 
-async fn run(ctx: Context, input: Input) -> Result<Output, CommandError> {
-    Ok(Output {
-        query: postgrest::Builder::from_query(input.query, ctx.http)
-            .not(input.operator, input.column, input.filter)
-            .into(),
-    })
+/// Negate a fragment, e.g. `gt.5` becomes `not.gt.5`. Negating twice cancels out.
+fn negate(fragment: FilterFragment) -> FilterFragment {
+    let operator = match fragment.operator.strip_prefix("not.") {
+        Some(operator) => operator.to_owned(),
+        None => format!("not.{}", fragment.operator),
+    };
+    FilterFragment {
+        operator,
+        ..fragment
+    }
+}
+
+async fn run(_: Context, input: Input) -> Result<FilterOutput, CommandError> {
+    let fragment = match input.fragment {
+        Some(fragment) => fragment,
+        None => {
+            let operator = input
+                .operator
+                .ok_or_else(|| anyhow!("either fragment or operator is required"))?;
+            let column = input
+                .column
+                .ok_or_else(|| anyhow!("either fragment or column is required"))?;
+            match input.filter {
+                Some(filter) => FilterFragment::literal(column, &operator, filter),
+                None => fragment(column, &operator, &input.value.unwrap_or(Value::Null))?,
+            }
+        }
+    };
+    Ok(FilterOutput::new(input.query, negate(fragment)))
 }
 
 fn build() -> BuildResult {
@@ -36,9 +100,73 @@ flow_lib::submit!(CommandDescription::new(NAME, |_| build()));
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::postgrest::tests::{new_query, query_pairs};
 
     #[test]
     fn test_build() {
         build().unwrap();
     }
+
+    #[tokio::test]
+    async fn test_run() {
+        async fn t(operator: &str, value: Value) -> (String, String) {
+            let output = run(
+                Context::default(),
+                Input {
+                    query: Some(new_query()),
+                    fragment: None,
+                    operator: Some(operator.to_owned()),
+                    column: Some("name".to_owned()),
+                    filter: None,
+                    value: Some(value),
+                },
+            )
+            .await
+            .unwrap();
+            let condition = output.fragment.condition();
+            (
+                query_pairs(output.query.unwrap()).pop().unwrap().1,
+                condition,
+            )
+        }
+
+        assert_eq!(
+            t("is", Value::Null).await,
+            ("not.is.null".to_owned(), "name.not.is.null".to_owned())
+        );
+        assert_eq!(
+            t("like", Value::String("*a b*".to_owned())).await,
+            (
+                "not.like.*a b*".to_owned(),
+                "name.not.like.\"*a b*\"".to_owned()
+            )
+        );
+        assert_eq!(
+            t(
+                "in",
+                Value::Array(vec![Value::U64(1), Value::String("b,c".to_owned())])
+            )
+            .await,
+            (
+                "not.in.(1,\"b,c\")".to_owned(),
+                "name.not.in.(1,\"b,c\")".to_owned()
+            )
+        );
+
+        let fragment = FilterFragment::new("age".to_owned(), "gt", "5".to_owned());
+        let output = run(
+            Context::default(),
+            Input {
+                query: None,
+                fragment: Some(negate(fragment.clone())),
+                operator: None,
+                column: None,
+                filter: None,
+                value: None,
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(output.fragment, fragment);
+    }
 }
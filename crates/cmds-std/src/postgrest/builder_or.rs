@@ -0,0 +1,103 @@
+use super::{push_filter, FilterFragment};
+use anyhow::ensure;
+use flow_lib::command::prelude::*;
+
+const NAME: &str = "postgrest_builder_or";
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum Condition {
+    /// Output of a filter builder.
+    Fragment(FilterFragment),
+    /// Raw condition, e.g. `age.gt.18`.
+    Raw(String),
+}
+
+impl Condition {
+    fn to_condition(&self) -> String {
+        match self {
+            Condition::Fragment(fragment) => fragment.condition(),
+            Condition::Raw(raw) => raw.clone(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct Input {
+    #[serde(default)]
+    query: Option<postgrest::Query>,
+    conditions: Vec<Condition>,
+}
+
+#[derive(Serialize, Debug)]
+struct Output {
+    query: Option<postgrest::Query>,
+}
+
+async fn run(_: Context, input: Input) -> Result<Output, CommandError> {
+    ensure!(
+        input.conditions.len() >= 2,
+        "`or` needs at least 2 conditions, got {}",
+        input.conditions.len()
+    );
+    let conditions = input
+        .conditions
+        .iter()
+        .map(Condition::to_condition)
+        .collect::<Vec<_>>();
+    let query = input.query.map(|mut query| {
+        push_filter(
+            &mut query,
+            "or".to_owned(),
+            format!("({})", conditions.join(",")),
+        );
+        query
+    });
+    Ok(Output { query })
+}
+
+fn build() -> BuildResult {
+    Ok(
+        CmdBuilder::new(flow_lib::node_definition!("postgrest/builder_or.json"))?
+            .check_name(NAME)?
+            .build(run),
+    )
+}
+
+flow_lib::submit!(CommandDescription::new(NAME, |_| build()));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::postgrest::tests::{new_query, query_pairs};
+
+    #[test]
+    fn test_build() {
+        build().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let eq = FilterFragment::new("name".to_owned(), "eq", "a,b".to_owned());
+        let input = value::from_map::<Input>(value::map! {
+            "query" => value::to_value(&new_query()).unwrap(),
+            "conditions" => Value::Array(vec![
+                value::to_value(&eq).unwrap(),
+                Value::from("age.gt.18"),
+            ]),
+        })
+        .unwrap();
+        let output = run(Context::default(), input).await.unwrap();
+        assert_eq!(
+            query_pairs(output.query.unwrap()),
+            [("or".to_owned(), r#"(name.eq."a,b",age.gt.18)"#.to_owned())]
+        );
+
+        let input = value::from_map::<Input>(value::map! {
+            "conditions" => Value::Array(vec![Value::from("age.gt.18")]),
+        })
+        .unwrap();
+        assert!(input.query.is_none());
+        assert!(run(Context::default(), input).await.is_err());
+    }
+}
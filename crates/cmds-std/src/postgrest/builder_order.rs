@@ -2,10 +2,59 @@ use flow_lib::command::prelude::*;
 
 const NAME: &str = "postgrest_builder_order";
 
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum Columns {
+    /// e.g. `id.desc,name`
+    Raw(String),
+    List(Vec<OrderColumn>),
+}
+
+/// e.g. `{ "column": "age", "ascending": false, "nulls_first": false }`
+/// will be `age.desc.nullslast`.
+#[derive(Deserialize, Debug)]
+struct OrderColumn {
+    column: String,
+    #[serde(default = "ascending")]
+    ascending: bool,
+    /// Use the database's default if not set.
+    #[serde(default)]
+    nulls_first: Option<bool>,
+}
+
+fn ascending() -> bool {
+    true
+}
+
+impl OrderColumn {
+    fn render(&self) -> String {
+        let direction = if self.ascending { "asc" } else { "desc" };
+        match self.nulls_first {
+            Some(true) => format!("{}.{}.nullsfirst", self.column, direction),
+            Some(false) => format!("{}.{}.nullslast", self.column, direction),
+            None => format!("{}.{}", self.column, direction),
+        }
+    }
+}
+
+impl Columns {
+    /// Value of the `order` parameter, columns are kept in the order they were given.
+    fn render(&self) -> String {
+        match self {
+            Columns::Raw(s) => s.clone(),
+            Columns::List(list) => list
+                .iter()
+                .map(OrderColumn::render)
+                .collect::<Vec<_>>()
+                .join(","),
+        }
+    }
+}
+
 #[derive(Deserialize, Debug)]
 struct Input {
     query: postgrest::Query,
-    columns: String,
+    columns: Columns,
 }
 
 #[derive(Serialize, Debug)]
@@ -16,7 +65,7 @@ struct Output {
 async fn run(ctx: Context, input: Input) -> Result<Output, CommandError> {
     Ok(Output {
         query: postgrest::Builder::from_query(input.query, ctx.http)
-            .order(input.columns)
+            .order(input.columns.render())
             .into(),
     })
 }
@@ -34,9 +83,36 @@ flow_lib::submit!(CommandDescription::new(NAME, |_| build()));
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::postgrest::tests::{new_query, query_pairs};
 
     #[test]
     fn test_build() {
         build().unwrap();
     }
+
+    #[tokio::test]
+    async fn test_run() {
+        let columns: Columns = serde_json::from_value(serde_json::json!([
+            { "column": "created_at", "ascending": false, "nulls_first": false },
+            { "column": "name" },
+            { "column": "score", "nulls_first": true },
+        ]))
+        .unwrap();
+        let output = run(
+            Context::default(),
+            Input {
+                query: new_query(),
+                columns,
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            query_pairs(output.query),
+            [(
+                "order".to_owned(),
+                "created_at.desc.nullslast,name.asc,score.asc.nullsfirst".to_owned()
+            )]
+        );
+    }
 }
@@ -0,0 +1,72 @@
+use super::{collection_value, FilterFragment, FilterOutput};
+use flow_lib::command::prelude::*;
+
+const NAME: &str = "postgrest_builder_overlaps";
+
+#[derive(Deserialize, Debug)]
+struct Input {
+    #[serde(default)]
+    query: Option<postgrest::Query>,
+    column: String,
+    values: Value,
+}
+
+async fn run(_: Context, input: Input) -> Result<FilterOutput, CommandError> {
+    if !matches!(input.values, Value::Array(_)) {
+        return Err(anyhow::anyhow!("expected an array"));
+    }
+    Ok(FilterOutput::new(
+        input.query,
+        FilterFragment::literal(input.column, "ov", collection_value(&input.values)?),
+    ))
+}
+
+fn build() -> BuildResult {
+    Ok(CmdBuilder::new(flow_lib::node_definition!(
+        "postgrest/builder_overlaps.json"
+    ))?
+    .check_name(NAME)?
+    .build(run))
+}
+
+flow_lib::submit!(CommandDescription::new(NAME, |_| build()));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::postgrest::tests::{new_query, query_pairs};
+
+    #[test]
+    fn test_build() {
+        build().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let output = run(
+            Context::default(),
+            Input {
+                query: Some(new_query()),
+                column: "ids".to_owned(),
+                values: Value::Array(vec![Value::from(1u64), Value::from(2u64)]),
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            query_pairs(output.query.unwrap()),
+            [("ids".to_owned(), "ov.{1,2}".to_owned())]
+        );
+
+        let error = run(
+            Context::default(),
+            Input {
+                query: Some(new_query()),
+                column: "ids".to_owned(),
+                values: Value::Map(value::map! { "k" => "v" }),
+            },
+        )
+        .await;
+        assert!(error.is_err());
+    }
+}
@@ -0,0 +1,161 @@
+use super::{FilterFragment, FilterOutput};
+use anyhow::bail;
+use flow_lib::command::prelude::*;
+
+const NAME: &str = "postgrest_builder_range";
+
+/// Operators of range columns, e.g. `tstzrange`.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum RangeOperator {
+    /// Strictly left of, `<<`.
+    Sl,
+    /// Strictly right of, `>>`.
+    Sr,
+    /// Does not extend to the right of, `&<`.
+    Nxr,
+    /// Does not extend to the left of, `&>`.
+    Nxl,
+    /// Is adjacent to, `-|-`.
+    Adj,
+}
+
+impl RangeOperator {
+    fn as_str(&self) -> &'static str {
+        match self {
+            RangeOperator::Sl => "sl",
+            RangeOperator::Sr => "sr",
+            RangeOperator::Nxr => "nxr",
+            RangeOperator::Nxl => "nxl",
+            RangeOperator::Adj => "adj",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct Input {
+    #[serde(default)]
+    query: Option<postgrest::Query>,
+    column: String,
+    operator: RangeOperator,
+    /// Range literal, e.g. `[2024-01-01,2024-02-01)`.
+    range: String,
+}
+
+/// Check the syntax of a range literal: `empty`, or bounds between `[`/`(` and `]`/`)`.
+/// A bound is empty (unbounded), double-quoted, or a value without special characters.
+fn check_range(range: &str) -> Result<(), CommandError> {
+    if range.eq_ignore_ascii_case("empty") {
+        return Ok(());
+    }
+    let inner = range
+        .strip_prefix(['[', '('])
+        .and_then(|s| s.strip_suffix([']', ')']))
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "invalid range {:?}: must start with `[` or `(` and end with `]` or `)`",
+                range
+            )
+        })?;
+    let mut bounds = 1;
+    let mut chars = inner.chars();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if quoted => {
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => bounds += 1,
+            '(' | ')' | '[' | ']' | '\\' if !quoted => {
+                bail!(
+                    "invalid range {:?}: unexpected {:?}, quote the bound",
+                    range,
+                    c
+                )
+            }
+            _ => {}
+        }
+    }
+    if quoted {
+        bail!("invalid range {:?}: unterminated quote", range);
+    }
+    if bounds != 2 {
+        bail!(
+            "invalid range {:?}: expected 2 bounds separated by `,`, found {}",
+            range,
+            bounds
+        );
+    }
+    Ok(())
+}
+
+async fn run(_: Context, input: Input) -> Result<FilterOutput, CommandError> {
+    let range = input.range.trim();
+    check_range(range)?;
+    Ok(FilterOutput::new(
+        input.query,
+        FilterFragment::new(input.column, input.operator.as_str(), range.to_owned()),
+    ))
+}
+
+fn build() -> BuildResult {
+    Ok(
+        CmdBuilder::new(flow_lib::node_definition!("postgrest/builder_range.json"))?
+            .check_name(NAME)?
+            .build(run),
+    )
+}
+
+flow_lib::submit!(CommandDescription::new(NAME, |_| build()));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::postgrest::tests::{new_query, query_pairs};
+
+    #[test]
+    fn test_build() {
+        build().unwrap();
+    }
+
+    #[test]
+    fn test_check_range() {
+        for range in [
+            "[2024-01-01,2024-02-01)",
+            "(1,5]",
+            "[,5)",
+            "(,)",
+            "empty",
+            r#"["2024-01-01 00:00:00+00","2024-01-02 00:00:00+00")"#,
+        ] {
+            check_range(range).unwrap();
+        }
+        for range in ["1,5", "[1,5", "[1,2,3)", "[1)", "[(1,5)", r#"["1,5)"#] {
+            assert!(check_range(range).is_err(), "{}", range);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let output = run(
+            Context::default(),
+            Input {
+                query: Some(new_query()),
+                column: "during".to_owned(),
+                operator: RangeOperator::Sl,
+                range: "[2024-01-01,2024-02-01)".to_owned(),
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            query_pairs(output.query.unwrap()),
+            [("during".to_owned(), "sl.[2024-01-01,2024-02-01)".to_owned())]
+        );
+        assert_eq!(
+            output.fragment.condition(),
+            r#"during.sl."[2024-01-01,2024-02-01)""#
+        );
+    }
+}
@@ -1,11 +1,187 @@
+use super::{
+    execute_query::fix_profile, introspect::fetch_schema, push_filter, FilterFragment, ReturnMode,
+};
+use anyhow::{anyhow, bail};
 use flow_lib::command::prelude::*;
+use reqwest::header::HeaderMap;
 
 const NAME: &str = "postgrest_builder_select";
 
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum Columns {
+    /// e.g. `*`, `id,name,authors(name)`
+    Raw(String),
+    List(Vec<Column>),
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum Column {
+    Name(String),
+    Embed(Embed),
+    Cast(Cast),
+}
+
+/// Column with its output casted, e.g. `{ "column": "created_at", "cast": "date" }`
+/// will be `created_at::date`.
+#[derive(Deserialize, Debug)]
+pub struct Cast {
+    pub column: String,
+    /// Postgres type, e.g. `text`, `date`.
+    pub cast: String,
+    #[serde(default)]
+    pub alias: Option<String>,
+}
+
+impl Cast {
+    fn render(&self) -> String {
+        match &self.alias {
+            Some(alias) => format!("{}:{}::{}", alias, self.column, self.cast),
+            None => format!("{}::{}", self.column, self.cast),
+        }
+    }
+}
+
+/// Embedded resource, e.g. `{ "table": "authors", "columns": ["name"] }`
+/// will be `authors(name)`.
+#[derive(Deserialize, Debug)]
+pub struct Embed {
+    pub table: String,
+    #[serde(default)]
+    pub alias: Option<String>,
+    #[serde(default = "all_columns")]
+    pub columns: Vec<Column>,
+    /// Filters applied to embedded rows, e.g. `authors.name=eq.John`.
+    #[serde(default)]
+    pub filters: Vec<FilterFragment>,
+}
+
+fn all_columns() -> Vec<Column> {
+    [Column::Name("*".to_owned())].into()
+}
+
+impl Default for Columns {
+    fn default() -> Self {
+        Columns::Raw("*".to_owned())
+    }
+}
+
+/// Split a `select` string at top-level commas, e.g. `id,authors(id,name)` becomes
+/// `id` and `authors(id,name)`.
+fn split_columns(select: &str) -> Vec<Column> {
+    let mut columns = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (index, c) in select.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                columns.push(Column::Name(select[start..index].trim().to_owned()));
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    columns.push(Column::Name(select[start..].trim().to_owned()));
+    columns
+}
+
+fn is_wildcard(column: &Column) -> bool {
+    matches!(column, Column::Name(name) if name == "*")
+}
+
+impl Columns {
+    fn into_list(self) -> Vec<Column> {
+        match self {
+            Columns::Raw(s) => split_columns(&s),
+            Columns::List(list) => list,
+        }
+    }
+
+    /// Whether `*` is selected at the top level, embedded resources are not checked.
+    fn has_wildcard(&self) -> bool {
+        match self {
+            Columns::Raw(s) => split_columns(s).iter().any(is_wildcard),
+            Columns::List(list) => list.iter().any(is_wildcard),
+        }
+    }
+
+    /// Replace `*` with `table_columns`, and remove `exclude` from top-level columns.
+    fn exclude(self, table_columns: &[String], exclude: &[String]) -> Self {
+        let list = self
+            .into_list()
+            .into_iter()
+            .flat_map(|column| {
+                if is_wildcard(&column) {
+                    table_columns.iter().cloned().map(Column::Name).collect()
+                } else {
+                    vec![column]
+                }
+            })
+            .filter(|column| !matches!(column, Column::Name(name) if exclude.contains(name)))
+            .collect();
+        Columns::List(list)
+    }
+
+    /// Returns the `select` parameter and filters of embedded resources.
+    fn render(&self) -> (String, Vec<(String, String)>) {
+        match self {
+            Columns::Raw(s) => (s.clone(), Vec::new()),
+            Columns::List(list) => {
+                let mut filters = Vec::new();
+                let select = render_list(list, "", &mut filters);
+                (select, filters)
+            }
+        }
+    }
+}
+
+fn render_list(list: &[Column], path: &str, filters: &mut Vec<(String, String)>) -> String {
+    list.iter()
+        .map(|column| match column {
+            Column::Name(name) => name.clone(),
+            Column::Embed(embed) => embed.render(path, filters),
+            Column::Cast(cast) => cast.render(),
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+impl Embed {
+    fn render(&self, parent: &str, filters: &mut Vec<(String, String)>) -> String {
+        let name = self.alias.as_ref().unwrap_or(&self.table);
+        let path = if parent.is_empty() {
+            name.clone()
+        } else {
+            format!("{}.{}", parent, name)
+        };
+        filters.extend(
+            self.filters
+                .iter()
+                .map(|f| (format!("{}.{}", path, f.column), f.filter())),
+        );
+        let columns = render_list(&self.columns, &path, filters);
+        match &self.alias {
+            Some(alias) => format!("{}:{}({})", alias, self.table, columns),
+            None => format!("{}({})", self.table, columns),
+        }
+    }
+}
+
 #[derive(Deserialize, Debug)]
 pub struct Input {
     pub query: postgrest::Query,
-    pub columns: String,
+    #[serde(default)]
+    pub columns: Columns,
+    /// Columns to remove from `*`.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// All columns of the table, fetched from PostgREST's OpenAPI description if not
+    /// set.
+    #[serde(default)]
+    pub table_columns: Option<Vec<String>>,
 }
 
 #[derive(Serialize, Debug)]
@@ -13,12 +189,109 @@ pub struct Output {
     pub query: postgrest::Query,
 }
 
-async fn run(ctx: Context, input: Input) -> Result<Output, CommandError> {
-    Ok(Output {
-        query: postgrest::Builder::from_query(input.query, ctx.http)
-            .select(input.columns)
-            .into(),
-    })
+/// Get the columns of the query's table with [`fetch_schema`].
+async fn table_columns(
+    ctx: &mut Context,
+    query: &postgrest::Query,
+) -> Result<Vec<String>, CommandError> {
+    let mut req = postgrest::Builder::from_query(query.clone(), ctx.http.clone())
+        .build()
+        .build()?;
+    fix_profile(&mut req);
+    let schema = req
+        .headers()
+        .get("accept-profile")
+        .or_else(|| req.headers().get("content-profile"))
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_owned);
+    let mut headers = HeaderMap::new();
+    for name in ["apikey", "authorization"] {
+        if let Some(value) = req.headers().get(name) {
+            headers.insert(name, value.clone());
+        }
+    }
+    let mut root = req.url().clone();
+    root.set_query(None);
+    let table = root
+        .path_segments()
+        .and_then(|mut s| s.next_back())
+        .filter(|t| !t.is_empty())
+        .ok_or_else(|| anyhow!("no table in URL {}", root))?
+        .to_owned();
+    root.path_segments_mut()
+        .map_err(|_| anyhow!("invalid URL {}", root))?
+        .pop()
+        .push("");
+    let schema = fetch_schema(ctx, root, schema, headers)
+        .await
+        .map_err(|error| error.context("can't get columns of the table, set `table_columns`"))?;
+    Ok(schema
+        .table(&table)
+        .ok_or_else(|| anyhow!("table {:?} not found, set `table_columns`", table))?
+        .columns
+        .iter()
+        .map(|c| c.name.clone())
+        .collect())
+}
+
+/// Remove whitespace outside of double quotes, like [`postgrest::Builder::select`].
+fn clean_select(select: &str) -> String {
+    let mut quoted = false;
+    select
+        .chars()
+        .filter(|&c| {
+            if c == '"' {
+                quoted = !quoted;
+            }
+            quoted || !c.is_whitespace()
+        })
+        .collect()
+}
+
+/// Set the `select` parameter and filters of embedded resources.
+///
+/// Unlike [`postgrest::Builder::select`], the method is kept, so this also chooses the
+/// returned columns of a write, e.g. `POST /table?select=id,created_at`.
+pub(crate) fn apply_select(query: &mut postgrest::Query, columns: &Columns) {
+    let (select, filters) = columns.render();
+    query.queries.retain(|(k, _)| k != "select");
+    query
+        .queries
+        .push(("select".to_owned(), clean_select(&select)));
+    for (column, filter) in filters {
+        push_filter(query, column, filter);
+    }
+}
+
+/// Choose the columns returned by a write, rows are only returned with
+/// `return=representation`.
+pub(crate) fn apply_returning_select(
+    query: &mut postgrest::Query,
+    returning: ReturnMode,
+    columns: Option<&Columns>,
+) -> Result<(), CommandError> {
+    if let Some(columns) = columns {
+        if returning != ReturnMode::Representation {
+            bail!("`select` requires `returning` to be `representation`");
+        }
+        apply_select(query, columns);
+    }
+    Ok(())
+}
+
+async fn run(mut ctx: Context, input: Input) -> Result<Output, CommandError> {
+    let mut columns = input.columns;
+    if !input.exclude.is_empty() {
+        let all = match input.table_columns {
+            Some(all) => all,
+            None if columns.has_wildcard() => table_columns(&mut ctx, &input.query).await?,
+            None => Vec::new(),
+        };
+        columns = columns.exclude(&all, &input.exclude);
+    }
+    let mut query = input.query;
+    apply_select(&mut query, &columns);
+    Ok(Output { query })
 }
 
 pub fn build() -> BuildResult {
@@ -34,9 +307,141 @@ flow_lib::submit!(CommandDescription::new(NAME, |_| build()));
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::postgrest::tests::{new_query, query_pairs};
 
     #[test]
     fn test_build() {
         build().unwrap();
     }
+
+    #[test]
+    fn test_render() {
+        let columns = value::from_value::<Columns>(Value::from("id,name")).unwrap();
+        assert_eq!(columns.render(), ("id,name".to_owned(), Vec::new()));
+
+        let json = serde_json::json!([
+            "*",
+            { "table": "authors", "columns": ["name"] },
+            {
+                "table": "users",
+                "alias": "editor",
+                "columns": ["id", { "table": "teams" }],
+                "filters": [{ "column": "active", "operator": "is", "operand": "true" }]
+            }
+        ]);
+        let columns = value::from_value::<Columns>(json.into()).unwrap();
+        assert_eq!(
+            columns.render(),
+            (
+                "*,authors(name),editor:users(id,teams(*))".to_owned(),
+                [("editor.active".to_owned(), "is.true".to_owned())].into()
+            )
+        );
+
+        let json = serde_json::json!([
+            { "column": "created_at", "cast": "date" },
+            { "column": "amount", "cast": "text", "alias": "total" },
+            { "table": "authors", "columns": [{ "column": "id", "cast": "text" }] }
+        ]);
+        let columns = value::from_value::<Columns>(json.into()).unwrap();
+        assert_eq!(
+            columns.render(),
+            (
+                "created_at::date,total:amount::text,authors(id::text)".to_owned(),
+                Vec::new()
+            )
+        );
+    }
+
+    #[test]
+    fn test_apply_select() {
+        let mut query: postgrest::Query =
+            postgrest::Builder::from_query(new_query(), reqwest::Client::new())
+                .update("{}")
+                .into();
+        apply_select(&mut query, &Columns::Raw("id".to_owned()));
+        apply_select(&mut query, &Columns::Raw("id, name".to_owned()));
+        let req = postgrest::Builder::from_query(query.clone(), reqwest::Client::new())
+            .build()
+            .build()
+            .unwrap();
+        assert_eq!(req.method(), reqwest::Method::PATCH);
+        assert_eq!(
+            query_pairs(query),
+            [("select".to_owned(), "id,name".to_owned())]
+        );
+    }
+
+    #[test]
+    fn test_exclude() {
+        let table_columns = ["id", "name", "password_hash", "created_at"].map(str::to_owned);
+        let exclude = ["password_hash".to_owned()];
+        let columns = Columns::Raw("*,authors(id,name)".to_owned());
+        assert!(columns.has_wildcard());
+        assert_eq!(
+            columns.exclude(&table_columns, &exclude).render().0,
+            "id,name,created_at,authors(id,name)"
+        );
+
+        let columns = Columns::Raw("id,password_hash".to_owned());
+        assert!(!columns.has_wildcard());
+        assert_eq!(columns.exclude(&[], &exclude).render().0, "id");
+    }
+
+    #[tokio::test]
+    async fn test_exclude_fetch_columns() {
+        use std::io::{BufRead, BufReader, Write};
+
+        // serve a description with one table, send a message for each request
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/rest/v1/users", listener.local_addr().unwrap());
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let body = serde_json::json!({
+                "definitions": {
+                    "users": { "properties": { "id": {}, "name": {}, "password_hash": {} } }
+                }
+            })
+            .to_string();
+            for stream in listener.incoming() {
+                let mut reader = BufReader::new(stream.unwrap());
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap_or(0) > 0 && line != "\r\n" {
+                    line.clear();
+                }
+                tx.send(()).unwrap();
+                let resp = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/openapi+json\r\n\
+                     content-length: {}\r\nconnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                reader.get_mut().write_all(resp.as_bytes()).unwrap();
+            }
+        });
+
+        // a new context for each run, like flows do
+        for _ in 0..2 {
+            let ctx = crate::postgrest::tests::production_context(&<_>::default());
+            let query =
+                postgrest::Builder::new(&url, None, <_>::default(), reqwest::Client::new()).into();
+            let output = run(
+                ctx,
+                Input {
+                    query,
+                    columns: Columns::Raw("*".to_owned()),
+                    exclude: vec!["password_hash".to_owned()],
+                    table_columns: None,
+                },
+            )
+            .await
+            .unwrap();
+            assert_eq!(
+                query_pairs(output.query),
+                [("select".to_owned(), "id,name".to_owned())]
+            );
+        }
+        // the columns are cached between runs
+        assert_eq!(rx.try_iter().count(), 1);
+    }
 }
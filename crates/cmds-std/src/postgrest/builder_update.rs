@@ -1,3 +1,7 @@
+use super::{
+    builder_select::{apply_returning_select, Columns},
+    set_rollback, ReturnMode,
+};
 use flow_lib::command::prelude::*;
 
 const NAME: &str = "postgrest_builder_update";
@@ -6,6 +10,14 @@ const NAME: &str = "postgrest_builder_update";
 struct Input {
     query: postgrest::Query,
     body: serde_json::Map<String, JsonValue>,
+    #[serde(default)]
+    returning: ReturnMode,
+    /// Columns of returned rows, see [`apply_returning_select`].
+    #[serde(default)]
+    select: Option<Columns>,
+    /// Roll back the write after running it, see [`set_rollback`].
+    #[serde(default)]
+    rollback: bool,
 }
 
 #[derive(Serialize, Debug)]
@@ -14,11 +26,15 @@ struct Output {
 }
 
 async fn run(ctx: Context, input: Input) -> Result<Output, CommandError> {
-    Ok(Output {
-        query: postgrest::Builder::from_query(input.query, ctx.http)
-            .update(serde_json::to_string(&input.body)?)
-            .into(),
-    })
+    let mut query: postgrest::Query = postgrest::Builder::from_query(input.query, ctx.http)
+        .update(serde_json::to_string(&input.body)?)
+        .into();
+    input.returning.apply(&mut query);
+    apply_returning_select(&mut query, input.returning, input.select.as_ref())?;
+    if input.rollback {
+        set_rollback(&mut query);
+    }
+    Ok(Output { query })
 }
 
 fn build() -> BuildResult {
@@ -34,9 +50,32 @@ flow_lib::submit!(CommandDescription::new(NAME, |_| build()));
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::postgrest::{get_prefer, tests::new_query};
 
     #[test]
     fn test_build() {
         build().unwrap();
     }
+
+    #[tokio::test]
+    async fn test_rollback() {
+        let output = run(
+            Context::default(),
+            Input {
+                query: new_query(),
+                body: serde_json::json!({ "name": "a" })
+                    .as_object()
+                    .unwrap()
+                    .clone(),
+                returning: ReturnMode::Representation,
+                select: None,
+                rollback: true,
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(get_prefer(&output.query, "tx"), Some("rollback"));
+        assert_eq!(get_prefer(&output.query, "handling"), Some("strict"));
+        assert_eq!(get_prefer(&output.query, "return"), Some("representation"));
+    }
 }
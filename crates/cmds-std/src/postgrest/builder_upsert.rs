@@ -1,11 +1,60 @@
+use super::{
+    builder_select::{apply_returning_select, Columns},
+    marker, set_header, set_idempotency_key, set_prefer, set_rollback, ReturnMode,
+    DEFAULT_IDEMPOTENCY_COLUMN,
+};
 use flow_lib::command::prelude::*;
+use std::num::NonZeroUsize;
 
 const NAME: &str = "postgrest_builder_upsert";
 
+/// `Prefer: resolution=`, what to do with rows that conflict with existing ones.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+enum Resolution {
+    #[default]
+    MergeDuplicates,
+    IgnoreDuplicates,
+}
+
+impl Resolution {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Resolution::MergeDuplicates => "merge-duplicates",
+            Resolution::IgnoreDuplicates => "ignore-duplicates",
+        }
+    }
+}
+
 #[derive(Deserialize, Debug)]
 struct Input {
     query: postgrest::Query,
     body: JsonValue,
+    #[serde(default)]
+    returning: ReturnMode,
+    /// Columns of returned rows, see [`apply_returning_select`].
+    #[serde(default)]
+    select: Option<Columns>,
+    /// Roll back the write after running it, see [`set_rollback`].
+    #[serde(default)]
+    rollback: bool,
+    /// Columns of the unique constraint to use, e.g. `["org_id", "name"]`.
+    #[serde(default)]
+    on_conflict: Option<Vec<String>>,
+    #[serde(default)]
+    resolution: Resolution,
+    /// Store this key in rows, see [`set_idempotency_key`]. `on_conflict` defaults to
+    /// `idempotency_column` if set.
+    #[serde(default)]
+    idempotency_key: Option<String>,
+    /// Default to `idempotency_key`.
+    #[serde(default)]
+    idempotency_column: Option<String>,
+    /// Send rows of a bulk upsert in requests of this many rows, to stay under body and
+    /// statement limits. `execute_query` sends them one after another and concatenates
+    /// the returned rows.
+    #[serde(default)]
+    chunk_size: Option<NonZeroUsize>,
 }
 
 #[derive(Serialize, Debug)]
@@ -13,12 +62,31 @@ struct Output {
     query: postgrest::Query,
 }
 
-async fn run(ctx: Context, input: Input) -> Result<Output, CommandError> {
-    Ok(Output {
-        query: postgrest::Builder::from_query(input.query, ctx.http)
-            .upsert(serde_json::to_string(&input.body)?)
-            .into(),
-    })
+async fn run(ctx: Context, mut input: Input) -> Result<Output, CommandError> {
+    let mut on_conflict = input.on_conflict.filter(|c| !c.is_empty());
+    if let Some(key) = input.idempotency_key.filter(|k| !k.is_empty()) {
+        let column = input
+            .idempotency_column
+            .unwrap_or_else(|| DEFAULT_IDEMPOTENCY_COLUMN.to_owned());
+        set_idempotency_key(&mut input.body, &column, &key)?;
+        on_conflict.get_or_insert_with(|| vec![column]);
+    }
+    let mut builder = postgrest::Builder::from_query(input.query, ctx.http)
+        .upsert(serde_json::to_string(&input.body)?);
+    if let Some(columns) = on_conflict {
+        builder = builder.on_conflict(columns.join(","));
+    }
+    let mut query: postgrest::Query = builder.into();
+    input.returning.apply(&mut query);
+    apply_returning_select(&mut query, input.returning, input.select.as_ref())?;
+    if input.rollback {
+        set_rollback(&mut query);
+    }
+    set_prefer(&mut query, "resolution", input.resolution.as_str());
+    if let Some(size) = input.chunk_size {
+        set_header(&mut query, marker::CHUNK_SIZE, size.to_string());
+    }
+    Ok(Output { query })
 }
 
 fn build() -> BuildResult {
@@ -34,9 +102,48 @@ flow_lib::submit!(CommandDescription::new(NAME, |_| build()));
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::postgrest::{
+        get_prefer,
+        tests::{new_query, query_pairs},
+    };
 
     #[test]
     fn test_build() {
         build().unwrap();
     }
+
+    #[tokio::test]
+    async fn test_run() {
+        let query = postgrest::Builder::from_query(new_query(), reqwest::Client::new())
+            .select("id,name")
+            .into();
+        let output = run(
+            Context::default(),
+            Input {
+                query,
+                body: serde_json::json!({ "org_id": 1, "name": "a" }),
+                returning: ReturnMode::Representation,
+                select: None,
+                rollback: false,
+                on_conflict: Some(vec!["org_id".to_owned(), "name".to_owned()]),
+                resolution: Resolution::IgnoreDuplicates,
+                idempotency_key: None,
+                idempotency_column: None,
+                chunk_size: None,
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            get_prefer(&output.query, "resolution"),
+            Some("ignore-duplicates")
+        );
+        assert_eq!(
+            query_pairs(output.query),
+            [
+                ("select".to_owned(), "id,name".to_owned()),
+                ("on_conflict".to_owned(), "org_id,name".to_owned()),
+            ]
+        );
+    }
 }
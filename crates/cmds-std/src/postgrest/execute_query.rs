@@ -1,39 +1,660 @@
-use crate::supabase_error;
-use flow_lib::command::prelude::*;
-use reqwest::header::{HeaderName, AUTHORIZATION};
-use std::{collections::HashMap, str::FromStr};
+use super::{
+    get_header, get_prefer, marker, parse_content_range, postgrest_error, set_header, set_prefer,
+    take_header, PostgrestError, ReturnMode, RpcReturns,
+};
+use anyhow::{anyhow, bail};
+use flow_lib::{
+    command::prelude::*,
+    context::get_jwt,
+    utils::{metrics, Backoff},
+};
+use reqwest::{
+    header::{HeaderName, HeaderValue, AUTHORIZATION, RETRY_AFTER},
+    Method, StatusCode, Url,
+};
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 const NAME: &str = "postgrest_execute_query";
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum ResponseFormat {
+    #[default]
+    Json,
+    /// Return the body as a CSV string.
+    Csv,
+}
+
+/// An object, or a list of `[name, value]` pairs.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+enum Headers {
+    Map(HashMap<String, String>),
+    List(Vec<(String, String)>),
+}
+
+impl Default for Headers {
+    fn default() -> Self {
+        Headers::List(Vec::new())
+    }
+}
+
+impl Headers {
+    fn into_pairs(self) -> Vec<(String, String)> {
+        match self {
+            Headers::Map(map) => map.into_iter().collect(),
+            Headers::List(list) => list,
+        }
+    }
+}
+
+/// Headers set by builders or by this command.
+const MANAGED_HEADERS: &[&str] = &[
+    "accept",
+    "accept-profile",
+    "content-profile",
+    "content-type",
+    "prefer",
+    "range",
+    "range-unit",
+];
+
+/// Check extra headers, they can't override [`MANAGED_HEADERS`] or headers of the query.
+fn check_headers(
+    query: &postgrest::Query,
+    headers: &[(String, String)],
+) -> Result<Vec<(HeaderName, HeaderValue)>, CommandError> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let name = HeaderName::from_str(name)
+                .map_err(|_| anyhow!("invalid header name {:?}", name))?;
+            let value = HeaderValue::from_str(value)
+                .map_err(|_| anyhow!("invalid value of header {:?}", name.as_str()))?;
+            if MANAGED_HEADERS.contains(&name.as_str())
+                || get_header(query, name.as_str()).is_some()
+            {
+                bail!(
+                    "header {:?} is set by the query and can't be overridden",
+                    name.as_str()
+                );
+            }
+            Ok((name, value))
+        })
+        .collect()
+}
+
+/// How `offset` and `limit` are sent.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum PaginationStyle {
+    /// `offset` and `limit` query parameters.
+    #[default]
+    QueryParam,
+    /// `Range` header, `offset` and `limit` parameters of the query are also moved to the
+    /// header. Some reverse proxies strip or change it.
+    RangeHeader,
+}
+
+#[derive(Deserialize, Debug, Clone)]
 struct Input {
     query: postgrest::Query,
+    /// Extra headers, e.g. for an API gateway, see [`check_headers`].
+    #[serde(default)]
+    pub headers: Headers,
+    /// Return a single object, error if the result doesn't have exactly 1 row.
+    #[serde(default)]
+    pub single: bool,
+    /// Return a single object or `null`, error if the result has more than 1 row.
+    #[serde(default)]
+    pub maybe_single: bool,
+    /// Paginate, total count will be returned in `total_count`.
+    #[serde(default)]
+    pub offset: Option<u64>,
+    #[serde(default)]
+    pub limit: Option<u64>,
+    #[serde(default)]
+    pub pagination_style: PaginationStyle,
+    /// Sort query string parameters so that equivalent queries have the same URL and hit
+    /// the same HTTP cache entries.
+    #[serde(default)]
+    pub sort_params: bool,
+    /// Send `Prefer: handling=strict`, PostgREST rejects the request if it doesn't
+    /// understand a preference. By default unknown preferences are silently ignored, which
+    /// can hide typos.
+    #[serde(default)]
+    pub strict: bool,
+    #[serde(default)]
+    pub accept: ResponseFormat,
+    /// Retry on connection errors, 5xx and 429 responses.
+    /// Default to 3, or 0 for non-idempotent requests (`POST` without upsert).
+    #[serde(default)]
+    pub max_retries: Option<u32>,
     #[serde(default)]
-    pub headers: Vec<(String, String)>,
+    pub base_delay_ms: Option<u64>,
+    /// Time limit of the whole query, including retries, instead of the timeout of the
+    /// HTTP client. Fails with [`Error::QueryTimeout`][crate::error::Error::QueryTimeout].
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    /// Authenticate as the flow owner, the JWT replaces any `Authorization` header. If not
+    /// set, only requests to our Supabase without an `Authorization` header are
+    /// authenticated.
+    #[serde(default)]
+    pub authenticated: Option<bool>,
+}
+
+type HookFn = dyn Fn(reqwest::RequestBuilder) -> reqwest::RequestBuilder + Send + Sync;
+
+/// Modify requests of `execute_query` before they are sent, e.g. to sign them or to set a
+/// timeout, for deployments that need more than `headers`.
+///
+/// Register it as an extension of the context with
+/// [`ContextBuilder::register`][flow_lib::context::ContextBuilder::register]. The builder
+/// has the method, URL, headers, body and authentication of the query; `Prefer` and
+/// schema headers are fixed and `require_filter` is checked after the hook runs.
+/// Anything else the hook changes is not checked, replacing the URL or the credentials
+/// is at your own risk.
+#[derive(Clone)]
+pub struct RequestHook(Arc<HookFn>);
+
+impl RequestHook {
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn(reqwest::RequestBuilder) -> reqwest::RequestBuilder + Send + Sync + 'static,
+    {
+        Self(Arc::new(f))
+    }
+
+    pub fn apply(&self, req: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        (self.0)(req)
+    }
+}
+
+impl std::fmt::Debug for RequestHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("RequestHook").finish_non_exhaustive()
+    }
+}
+
+/// Delay before the next attempt, `None` if the result should not be retried.
+fn retry_delay(
+    result: &Result<reqwest::Response, reqwest::Error>,
+    attempt: u32,
+    backoff: &Backoff,
+) -> Option<Duration> {
+    let retry_after = match result {
+        Ok(resp) if resp.status() == StatusCode::TOO_MANY_REQUESTS => resp
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse::<u64>().ok())
+            .map(|secs| Duration::from_secs(secs).min(backoff.max_delay)),
+        Ok(resp) if resp.status().is_server_error() => None,
+        Err(error) if error.is_connect() || error.is_timeout() || error.is_request() => None,
+        _ => return None,
+    };
+    let delay = backoff.delay(attempt)?;
+    Some(retry_after.unwrap_or(delay))
+}
+
+async fn send(
+    http: &reqwest::Client,
+    req: &reqwest::Request,
+    backoff: &Backoff,
+) -> Result<reqwest::Response, CommandError> {
+    let mut attempt = 0;
+    loop {
+        let next = req
+            .try_clone()
+            .ok_or_else(|| anyhow!("request body can't be cloned"))?;
+        let result = http.execute(next).await;
+        match retry_delay(&result, attempt, backoff) {
+            Some(delay) => {
+                attempt += 1;
+                tracing::warn!(
+                    "postgrest request failed, retrying in {:?} ({}/{})",
+                    delay,
+                    attempt,
+                    backoff.max_attempts
+                );
+                tokio::time::sleep(delay).await;
+            }
+            None => break Ok(result?),
+        }
+    }
+}
+
+async fn jwt_header(ctx: &mut Context) -> Result<HeaderValue, CommandError> {
+    let header = ctx.get_jwt_header().await.map_err(|error| match error {
+        get_jwt::Error::NotAllowed => anyhow!(
+            "not allowed to authenticate as the flow owner, \
+            `user_tokens` permission is required"
+        ),
+        error => error.into(),
+    })?;
+    Ok(HeaderValue::from_str(&header)?)
+}
+
+/// Select the schema with `Accept-Profile` for `GET` and `HEAD`, `Content-Profile` for
+/// other methods, the method might have changed after the query was built.
+pub(super) fn fix_profile(req: &mut reqwest::Request) {
+    let (from, to) = match *req.method() {
+        Method::GET | Method::HEAD => ("content-profile", "accept-profile"),
+        _ => ("accept-profile", "content-profile"),
+    };
+    if let Some(schema) = req.headers_mut().remove(from) {
+        req.headers_mut().entry(to).or_insert(schema);
+    }
+}
+
+fn is_write(method: &Method) -> bool {
+    !matches!(*method, Method::GET | Method::HEAD)
 }
 
-async fn run(mut ctx: Context, input: Input) -> Result<ValueSet, CommandError> {
-    let contain_auth_header = !input.headers.iter().any(|(k, _)| {
-        HeaderName::from_str(k)
-            .ok()
-            .map(|name| name == AUTHORIZATION)
-            .unwrap_or(false)
+/// Add `Prefer: count=exact` to writes so that `Content-Range` has the number of affected
+/// rows, counting only the written rows is cheap.
+fn prefer_count(req: &mut reqwest::Request) {
+    if !is_write(req.method()) {
+        return;
+    }
+    let prefer = req
+        .headers()
+        .get("prefer")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    if prefer.split(',').any(|p| p.trim().starts_with("count=")) {
+        return;
+    }
+    let value = if prefer.is_empty() {
+        "count=exact".to_owned()
+    } else {
+        format!("{},count=exact", prefer)
+    };
+    if let Ok(value) = HeaderValue::from_str(&value) {
+        req.headers_mut().insert("prefer", value);
+    }
+}
+
+/// Number of rows changed by a write, from `Content-Range`, e.g. `*/0` if nothing matched.
+fn affected_rows(method: &Method, content_range: Option<&str>) -> Option<u64> {
+    if !is_write(method) {
+        return None;
+    }
+    content_range.and_then(parse_content_range)
+}
+
+/// Value of `Range` header, `limit` must not be 0.
+fn range_header(offset: Option<u64>, limit: Option<u64>) -> String {
+    let offset = offset.unwrap_or(0);
+    match limit {
+        Some(limit) => format!("{}-{}", offset, offset + limit - 1),
+        None => format!("{}-", offset),
+    }
+}
+
+/// Remove top-level `name` parameters from the query string, returns the last value.
+fn take_param(query: &mut postgrest::Query, name: &str) -> Option<String> {
+    let mut value = None;
+    query.queries.retain(|(k, v)| {
+        if k == name {
+            value = Some(v.clone());
+            false
+        } else {
+            true
+        }
     });
+    value
+}
+
+fn parse_param(query: &mut postgrest::Query, name: &str) -> Result<Option<u64>, CommandError> {
+    take_param(query, name)
+        .map(|v| {
+            v.parse::<u64>()
+                .map_err(|_| anyhow!("invalid `{}` parameter: {:?}", name, v))
+        })
+        .transpose()
+}
+
+/// Sort parameters by name then value, only the order of parameters changes, values such
+/// as `or=(a.eq.1,b.eq.2)` are kept as-is. Parameters with the same name are combined with
+/// AND by PostgREST, so their order doesn't matter either.
+fn sort_params(query: &mut postgrest::Query) {
+    query.queries.sort();
+}
+
+/// Set `offset` and `limit` in the query string or in the `Range` header, returns whether
+/// the query is paginated.
+fn paginate(
+    query: &mut postgrest::Query,
+    style: PaginationStyle,
+    offset: Option<u64>,
+    limit: Option<u64>,
+) -> Result<bool, CommandError> {
+    if limit == Some(0) {
+        bail!("`limit` must be greater than 0");
+    }
+    match style {
+        PaginationStyle::QueryParam => {
+            for (name, value) in [("offset", offset), ("limit", limit)] {
+                if let Some(value) = value {
+                    take_param(query, name);
+                    query.queries.push((name.to_owned(), value.to_string()));
+                }
+            }
+            Ok(offset.is_some() || limit.is_some())
+        }
+        PaginationStyle::RangeHeader => {
+            let offset = match offset {
+                Some(offset) => Some(offset),
+                None => parse_param(query, "offset")?,
+            };
+            let limit = match limit {
+                Some(limit) => Some(limit),
+                None => parse_param(query, "limit")?.filter(|&l| l > 0),
+            };
+            if offset.is_none() && limit.is_none() {
+                return Ok(false);
+            }
+            take_param(query, "offset");
+            take_param(query, "limit");
+            set_header(query, "Range-Unit", "items".to_owned());
+            set_header(query, "Range", range_header(offset, limit));
+            Ok(true)
+        }
+    }
+}
+
+fn maybe_single(body: Value) -> Result<Value, CommandError> {
+    match body {
+        Value::Array(mut rows) => match rows.len() {
+            0 => Ok(Value::Null),
+            1 => Ok(rows.pop().unwrap()),
+            len => Err(anyhow!("expected at most one row, found {}", len)),
+        },
+        body => Ok(body),
+    }
+}
+
+/// Check the result of an RPC, returns the output name and the result: `rows` for an array,
+/// `row` for an object, `scalar` otherwise. Scalar arrays like `int[]` can't be told apart
+/// from rows without `returns`.
+fn rpc_result(
+    body: Value,
+    returns: Option<RpcReturns>,
+) -> Result<(&'static str, Value), CommandError> {
+    Ok(match returns {
+        None => match body {
+            body @ Value::Array(_) => ("rows", body),
+            body @ Value::Map(_) => ("row", body),
+            body => ("scalar", body),
+        },
+        Some(RpcReturns::Rows) => match body {
+            body @ Value::Array(_) => ("rows", body),
+            // `single` or `maybe_single` is set
+            Value::Null => ("rows", Value::Array(Vec::new())),
+            body @ Value::Map(_) => ("rows", Value::Array(vec![body])),
+            _ => bail!("expected rows, found a scalar"),
+        },
+        Some(RpcReturns::Row) => match maybe_single(body)? {
+            body @ (Value::Map(_) | Value::Null) => ("row", body),
+            _ => bail!("expected a row, found a scalar"),
+        },
+        Some(RpcReturns::Scalar) => match body {
+            Value::Map(_) => bail!("expected a scalar, found a row"),
+            body => ("scalar", body),
+        },
+    })
+}
+
+/// Query parameters that are not filters.
+const NON_FILTER_PARAMS: &[&str] = &[
+    "select",
+    "order",
+    "limit",
+    "offset",
+    "on_conflict",
+    "columns",
+];
+
+fn has_filter(url: &Url) -> bool {
+    url.query_pairs().any(|(k, _)| {
+        // embedded resources, e.g. `authors.order`
+        let param = k.rsplit('.').next().unwrap_or_default();
+        !NON_FILTER_PARAMS.contains(&param)
+    })
+}
+
+async fn run(ctx: Context, input: Input) -> Result<ValueSet, CommandError> {
+    let flow_run_id = ctx.command.as_ref().map(|c| c.flow_run_id);
+    let start = Instant::now();
+    let result = match input.timeout_ms.map(Duration::from_millis) {
+        // dropping the future cancels the request
+        Some(timeout) => tokio::time::timeout(timeout, execute_chunks(ctx, input))
+            .await
+            .unwrap_or_else(|_| Err(crate::error::Error::QueryTimeout(timeout).into())),
+        None => execute_chunks(ctx, input).await,
+    };
+    let labeled = result.as_ref().map_err(QueryError);
+    metrics::record_call(NAME, flow_run_id, start.elapsed(), &labeled);
+    result
+}
+
+/// Label failures of [`run`] by kind in [`metrics`].
+struct QueryError<'a>(&'a CommandError);
+
+impl metrics::ErrorLabel for QueryError<'_> {
+    fn label(&self) -> &'static str {
+        if matches!(
+            self.0.downcast_ref::<crate::error::Error>(),
+            Some(crate::error::Error::QueryTimeout(_))
+        ) {
+            return "timeout";
+        }
+        if let Some(error) = self.0.downcast_ref::<PostgrestError>() {
+            return match error.code.as_str() {
+                "57014" => "statement_timeout",
+                "42501" => "permission_denied",
+                code if code.starts_with("23") => "constraint_violation",
+                code if code.starts_with("PGRST") => "postgrest",
+                _ if error.status >= 500 => "server_error",
+                _ => "database",
+            };
+        }
+        match self.0.downcast_ref::<reqwest::Error>() {
+            Some(error) if error.is_timeout() => "timeout",
+            Some(_) => "http",
+            None => "error",
+        }
+    }
+}
+
+/// Send rows of a bulk write in chunks of [`marker::CHUNK_SIZE`] rows, one after another.
+///
+/// Returned rows are concatenated and counts are added up. If a chunk fails, the error has
+/// its range of rows, the previous chunks have already been written.
+async fn execute_chunks(ctx: Context, mut input: Input) -> Result<ValueSet, CommandError> {
+    let chunk_size = take_header(&mut input.query, marker::CHUNK_SIZE)
+        .map(|s| {
+            s.parse::<usize>()
+                .ok()
+                .filter(|size| *size > 0)
+                .ok_or_else(|| anyhow!("invalid chunk size {:?}", s))
+        })
+        .transpose()?;
+    let body = match chunk_size {
+        Some(_) => input
+            .query
+            .body
+            .as_deref()
+            .map(serde_json::from_str::<JsonValue>)
+            .transpose()?,
+        None => None,
+    };
+    let (Some(size), Some(JsonValue::Array(rows))) = (chunk_size, body) else {
+        return execute(ctx, input).await;
+    };
+    if rows.len() <= size {
+        return execute(ctx, input).await;
+    }
+    if input.single || input.maybe_single {
+        bail!("`single` and `maybe_single` can't be used with `chunk_size`");
+    }
+
+    let mut output: Option<ValueSet> = None;
+    for (index, chunk) in rows.chunks(size).enumerate() {
+        let start = index * size;
+        let end = start + chunk.len();
+        let mut input = input.clone();
+        input.query.body = Some(serde_json::to_string(chunk)?);
+        let next = execute(ctx.clone(), input).await.map_err(|error| {
+            error.context(format!(
+                "rows {}..{} of {} failed, rows 0..{} were written",
+                start,
+                end,
+                rows.len(),
+                start
+            ))
+        })?;
+        output = Some(match output {
+            Some(output) => merge_outputs(output, next),
+            None => next,
+        });
+    }
+    Ok(output.unwrap_or_default())
+}
+
+/// Merge the output of the next chunk into `output`.
+fn merge_outputs(mut output: ValueSet, next: ValueSet) -> ValueSet {
+    for (key, value) in next {
+        let value = match (key.as_str(), output.swap_remove(&key), value) {
+            ("result", Some(Value::Array(mut rows)), Value::Array(more)) => {
+                rows.extend(more);
+                Value::Array(rows)
+            }
+            ("total_count" | "affected_rows", Some(Value::U64(a)), Value::U64(b)) => {
+                Value::U64(a + b)
+            }
+            (_, _, value) => value,
+        };
+        output.insert(key, value);
+    }
+    output
+}
+
+async fn execute(mut ctx: Context, mut input: Input) -> Result<ValueSet, CommandError> {
+    let require_filter = take_header(&mut input.query, marker::REQUIRE_FILTER).is_some();
+    let head = take_header(&mut input.query, marker::HEAD).is_some();
+    let rpc_returns = take_header(&mut input.query, marker::RPC_RETURNS)
+        .map(|s| RpcReturns::parse(&s).ok_or_else(|| anyhow!("invalid RPC return type {:?}", s)))
+        .transpose()?;
+    let is_rpc = input.query.is_rpc;
+    let minimal = get_prefer(&input.query, "return") == Some(ReturnMode::Minimal.as_str());
+    let is_upsert = get_prefer(&input.query, "resolution").is_some();
+    if input.single && input.maybe_single {
+        bail!("`single` and `maybe_single` can't be used together");
+    }
+    let csv = input.accept == ResponseFormat::Csv;
+    if csv {
+        if input.single || input.maybe_single {
+            bail!("`single` and `maybe_single` are not supported with CSV format");
+        }
+        set_header(&mut input.query, "Accept", "text/csv".to_owned());
+    }
+    if paginate(
+        &mut input.query,
+        input.pagination_style,
+        input.offset,
+        input.limit,
+    )? {
+        set_prefer(&mut input.query, "count", "exact");
+    }
+    if input.sort_params {
+        sort_params(&mut input.query);
+    }
+    if input.strict {
+        set_prefer(&mut input.query, "handling", "strict");
+    }
+    if input.single {
+        set_header(
+            &mut input.query,
+            "Accept",
+            "application/vnd.pgrst.object+json".to_owned(),
+        );
+    }
+
+    let mut headers = check_headers(&input.query, &input.headers.into_pairs())?;
+    let has_auth_header = headers.iter().any(|(name, _)| *name == AUTHORIZATION)
+        || get_header(&input.query, AUTHORIZATION.as_str()).is_some();
     let is_supabase = input
         .query
         .url
         .starts_with(&format!("{}/rest/v1", ctx.endpoints.supabase));
+    let authenticated = input
+        .authenticated
+        .unwrap_or(!has_auth_header && is_supabase);
+    if authenticated {
+        // replaced by the JWT, sending both would let the server pick either
+        headers.retain(|(name, _)| *name != AUTHORIZATION);
+        take_header(&mut input.query, AUTHORIZATION.as_str());
+    }
 
+    // set by `endpoints_override` of `new_query` or `new_rpc`
+    let has_apikey = get_header(&input.query, "apikey").is_some()
+        || headers.iter().any(|(name, _)| *name == "apikey");
     let mut req = postgrest::Builder::from_query(input.query, ctx.http.clone()).build();
-    for (k, v) in input.headers {
-        req = req.header(k, v);
+    for (name, value) in headers {
+        req = req.header(name, value);
     }
-    if contain_auth_header && is_supabase {
+    if authenticated {
         tracing::info!("using JWT of user: {}", ctx.flow_owner.id);
-        req = req.header("apikey", &ctx.endpoints.supabase_anon_key);
-        req = req.header(AUTHORIZATION, ctx.get_jwt_header().await?);
+        if is_supabase && !has_apikey {
+            req = req.header("apikey", &ctx.endpoints.supabase_anon_key);
+        }
+        req = req.header(AUTHORIZATION, jwt_header(&mut ctx).await?);
+    }
+    if let Some(hook) = ctx.get::<RequestHook>() {
+        req = hook.apply(req);
+    }
+    let mut req = req.build()?;
+    if head {
+        *req.method_mut() = Method::HEAD;
+    }
+    fix_profile(&mut req);
+    if let Some(ms) = input.timeout_ms {
+        // `run` enforces it, don't let the client's timeout end the query sooner
+        *req.timeout_mut() = Some(Duration::from_millis(ms));
+    }
+    if !is_rpc {
+        prefer_count(&mut req);
+    }
+    if require_filter && req.method() == Method::DELETE && !has_filter(req.url()) {
+        bail!("refusing to DELETE without filters, set `require_filter` to false to allow");
+    }
+    let is_idempotent = req.method() != Method::POST || is_upsert;
+    let mut backoff = Backoff::default();
+    if !is_idempotent {
+        backoff.max_attempts = 0;
+    }
+    if let Some(max_retries) = input.max_retries {
+        backoff.max_attempts = max_retries;
+    }
+    if let Some(ms) = input.base_delay_ms {
+        backoff.base = Duration::from_millis(ms);
+    }
+    let mut resp = send(&ctx.http, &req, &backoff).await?;
+    if authenticated && resp.status() == StatusCode::UNAUTHORIZED {
+        // the token might have expired mid-flow, get a new one and try again
+        tracing::warn!("JWT rejected, retrying with a new token");
+        ctx.invalidate_jwt(ctx.flow_owner.id);
+        req.headers_mut()
+            .insert(AUTHORIZATION, jwt_header(&mut ctx).await?);
+        resp = send(&ctx.http, &req, &backoff).await?;
     }
-    let resp = ctx.http.execute(req.build()?).await?;
 
     if resp.status().is_success() {
         let headers = resp
@@ -47,33 +668,80 @@ async fn run(mut ctx: Context, input: Input) -> Result<ValueSet, CommandError> {
             })
             .collect::<HashMap<String, String>>();
 
+        if head {
+            // the response has no body, the count is all there is
+            let total_count = headers
+                .get("content-range")
+                .map(String::as_str)
+                .and_then(parse_content_range)
+                .ok_or_else(|| anyhow!("response of count query has no total count"))?;
+            return Ok(value::map! {
+                "total_count" => total_count,
+            });
+        }
+
         let content_type = headers
             .get("content-type")
             .map(String::as_str)
             .unwrap_or("text/plain");
-        let body: Value = if content_type.starts_with("text/") {
+        let body: Value = if minimal {
+            Value::Map(<_>::default())
+        } else if csv || content_type.starts_with("text/") {
             resp.text().await?.into()
         } else if content_type.contains("json") {
             resp.json::<serde_json::Value>().await?.into()
         } else {
             resp.bytes().await?.into()
         };
+        let body = if input.maybe_single {
+            maybe_single(body)?
+        } else {
+            body
+        };
+        let (body, rpc_output) = if is_rpc && !minimal && !csv {
+            let (name, body) = rpc_result(body, rpc_returns)?;
+            (body, Some(name))
+        } else {
+            (body, None)
+        };
+
+        let content_range = headers.get("content-range").map(String::as_str);
+        let total_count = content_range.and_then(parse_content_range);
+        let affected_rows = if is_rpc {
+            None
+        } else {
+            affected_rows(req.method(), content_range)
+        };
 
         let headers = headers
             .into_iter()
             .map(|(k, v)| (k, Value::String(v)))
             .collect::<value::Map>();
 
-        Ok(value::map! {
-            "result" => body,
+        let mut output = value::map! {
             "headers" => headers,
-        })
+        };
+        if let Some(name) = rpc_output {
+            output.insert(name.into(), body.clone());
+        }
+        output.insert("result".into(), body);
+        if let Some(total_count) = total_count {
+            output.insert("total_count".into(), total_count.into());
+        }
+        if let Some(affected_rows) = affected_rows {
+            output.insert("affected_rows".into(), affected_rows.into());
+        }
+        Ok(output)
+    } else if input.single && resp.status() == StatusCode::NOT_ACCEPTABLE {
+        Err(postgrest_error(resp)
+            .await
+            .context("expected exactly one row"))
     } else {
-        Err(supabase_error(resp.status(), resp).await)
+        Err(postgrest_error(resp).await)
     }
 }
 
-fn build() -> BuildResult {
+pub(super) fn build() -> BuildResult {
     Ok(
         CmdBuilder::new(flow_lib::node_definition!("postgrest/execute_query.json"))?
             .check_name(NAME)?
@@ -92,4 +760,518 @@ mod tests {
     fn test_build() {
         build().unwrap();
     }
+
+    #[test]
+    fn test_request_hook() {
+        let ctx = Context::builder(<_>::default())
+            .flow_owner(<_>::default())
+            .register(RequestHook::new(|req| {
+                req.timeout(Duration::from_secs(1))
+                    .header("x-signature", "signed")
+            }))
+            .build()
+            .unwrap();
+        let hook = ctx.get::<RequestHook>().unwrap();
+        let req = hook
+            .apply(ctx.http.get("https://example.com/rest/v1/users"))
+            .build()
+            .unwrap();
+        assert_eq!(req.headers()["x-signature"], "signed");
+        assert_eq!(req.timeout(), Some(&Duration::from_secs(1)));
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/rest/v1/table", listener.local_addr().unwrap());
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            use std::io::Read;
+            let (mut stream, _) = listener.accept().unwrap();
+            // never respond, read until the client closes the connection
+            let mut buf = [0; 1024];
+            while matches!(stream.read(&mut buf), Ok(n) if n > 0) {}
+            tx.send(()).unwrap();
+        });
+
+        let query: postgrest::Query =
+            postgrest::Builder::new(url, None, <_>::default(), reqwest::Client::new()).into();
+        let input = serde_json::from_value::<Input>(serde_json::json!({
+            "query": query,
+            "timeout_ms": 100,
+        }))
+        .unwrap();
+        let error = run(Context::default(), input).await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<crate::error::Error>(),
+            Some(crate::error::Error::QueryTimeout(timeout)) if *timeout == Duration::from_millis(100)
+        ));
+        rx.recv_timeout(Duration::from_secs(5))
+            .expect("request was not cancelled");
+    }
+
+    /// Respond to writes with their rows, or with an error if a row has `fail` set,
+    /// and to `HEAD` requests with a count of 3573.
+    /// Returns the URL of a table and the number of rows of each request.
+    fn mock_postgrest() -> (String, std::sync::mpsc::Receiver<usize>) {
+        use std::io::{BufRead, BufReader, Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/rest/v1/table", listener.local_addr().unwrap());
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let tx = tx.clone();
+                std::thread::spawn(move || {
+                    let mut reader = BufReader::new(stream.unwrap());
+                    loop {
+                        let mut len = 0;
+                        let mut line = String::new();
+                        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                            return;
+                        }
+                        let is_head = line.starts_with("HEAD ");
+                        line.clear();
+                        while reader.read_line(&mut line).unwrap_or(0) > 0 && line != "\r\n" {
+                            if let Some((name, value)) = line.split_once(':') {
+                                if name.eq_ignore_ascii_case("content-length") {
+                                    len = value.trim().parse().unwrap();
+                                }
+                            }
+                            line.clear();
+                        }
+                        if line.is_empty() {
+                            return;
+                        }
+                        if is_head {
+                            let resp = "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\n\
+                                        content-range: 0-24/3573\r\ncontent-length: 0\r\n\r\n";
+                            reader.get_mut().write_all(resp.as_bytes()).unwrap();
+                            continue;
+                        }
+                        let mut body = vec![0; len];
+                        reader.read_exact(&mut body).unwrap();
+                        let rows = serde_json::from_slice::<Vec<JsonValue>>(&body).unwrap();
+                        tx.send(rows.len()).unwrap();
+                        let (status, body) = if rows.iter().any(|row| row["fail"] == true) {
+                            let error = r#"{"code":"23514","message":"check failed"}"#;
+                            ("400 Bad Request", error.to_owned())
+                        } else {
+                            ("201 Created", String::from_utf8(body).unwrap())
+                        };
+                        let resp = format!(
+                            "HTTP/1.1 {}\r\ncontent-type: application/json\r\n\
+                             content-range: */{}\r\ncontent-length: {}\r\n\r\n{}",
+                            status,
+                            rows.len(),
+                            body.len(),
+                            body
+                        );
+                        reader.get_mut().write_all(resp.as_bytes()).unwrap();
+                    }
+                });
+            }
+        });
+        (url, rx)
+    }
+
+    /// Answer the first request with 401, returns the `Authorization` headers of each.
+    fn mock_unauthorized_once() -> (String, std::sync::mpsc::Receiver<Vec<String>>) {
+        use std::io::{BufRead, BufReader, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/rest/v1/table", listener.local_addr().unwrap());
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            // responses close the connection, the retry comes on a new one
+            for (i, stream) in listener.incoming().enumerate() {
+                let mut reader = BufReader::new(stream.unwrap());
+                let mut auth = Vec::new();
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap_or(0) > 0 && line != "\r\n" {
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("authorization") {
+                            auth.push(value.trim().to_owned());
+                        }
+                    }
+                    line.clear();
+                }
+                tx.send(auth).unwrap();
+                let status = if i == 0 { "401 Unauthorized" } else { "200 OK" };
+                let resp = format!(
+                    "HTTP/1.1 {}\r\ncontent-type: application/json\r\n\
+                     content-length: 2\r\nconnection: close\r\n\r\n[]",
+                    status
+                );
+                reader.get_mut().write_all(resp.as_bytes()).unwrap();
+            }
+        });
+        (url, rx)
+    }
+
+    /// Context with a `get_jwt` service returning a new token for each call, tokens don't
+    /// expire soon so only `invalidate_jwt` makes the cache call it again.
+    fn jwt_context() -> (Context, [String; 2]) {
+        let tokens = ["eyJleHAiOjQxMDI0NDQ4MDB9", "eyJleHAiOjQxMDI0NDQ4MDF9"]
+            .map(|payload| format!("eyJhbGciOiJIUzI1NiJ9.{}.signature", payload));
+        let calls = std::sync::atomic::AtomicUsize::new(0);
+        let get_jwt = get_jwt::Svc::from_service(
+            tower::service_fn({
+                let tokens = tokens.clone();
+                move |_: get_jwt::Request| {
+                    let n = calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    let access_token = tokens[n.min(1)].clone();
+                    async move { Ok(get_jwt::Response { access_token }) }
+                }
+            }),
+            get_jwt::Error::worker,
+            16,
+        );
+        let ctx = Context::builder(<_>::default())
+            .flow_owner(<_>::default())
+            .get_jwt(get_jwt)
+            .build()
+            .unwrap();
+        (ctx, tokens)
+    }
+
+    #[tokio::test]
+    async fn test_jwt_rejected() {
+        let (url, rx) = mock_unauthorized_once();
+        let (ctx, tokens) = jwt_context();
+        let query: postgrest::Query =
+            postgrest::Builder::new(&url, None, <_>::default(), reqwest::Client::new())
+                .select("*")
+                .into();
+        let input = serde_json::from_value::<Input>(serde_json::json!({
+            "query": query,
+            "authenticated": true,
+        }))
+        .unwrap();
+
+        let output = run(ctx, input).await.unwrap();
+        assert_eq!(output["result"], Value::Array(Vec::new()));
+        assert_eq!(
+            rx.try_iter().collect::<Vec<_>>(),
+            tokens.map(|token| vec![format!("Bearer {}", token)])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_jwt_replaces_auth_header() {
+        let (url, rx) = mock_unauthorized_once();
+        let (ctx, tokens) = jwt_context();
+        let query: postgrest::Query =
+            postgrest::Builder::new(&url, None, <_>::default(), reqwest::Client::new())
+                .select("*")
+                .into();
+        let input = serde_json::from_value::<Input>(serde_json::json!({
+            "query": query,
+            "headers": { "Authorization": "Bearer user" },
+            "authenticated": true,
+        }))
+        .unwrap();
+
+        run(ctx, input).await.unwrap();
+        assert_eq!(
+            rx.try_iter().collect::<Vec<_>>(),
+            tokens.map(|token| vec![format!("Bearer {}", token)])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_count() {
+        let (url, _rx) = mock_postgrest();
+        let mut query: postgrest::Query =
+            postgrest::Builder::new(&url, None, <_>::default(), reqwest::Client::new())
+                .select("*")
+                .into();
+        set_prefer(&mut query, "count", "exact");
+        set_header(&mut query, marker::HEAD, "true".to_owned());
+        let input = serde_json::from_value::<Input>(serde_json::json!({ "query": query })).unwrap();
+
+        let output = run(Context::default(), input).await.unwrap();
+        assert_eq!(output["total_count"], Value::U64(3573));
+        assert!(!output.contains_key("result"));
+    }
+
+    #[tokio::test]
+    async fn test_chunks() {
+        let (url, rx) = mock_postgrest();
+        let upsert = |rows: &[JsonValue]| {
+            let mut query: postgrest::Query =
+                postgrest::Builder::new(&url, None, <_>::default(), reqwest::Client::new())
+                    .upsert(serde_json::to_string(rows).unwrap())
+                    .into();
+            ReturnMode::Representation.apply(&mut query);
+            set_header(&mut query, marker::CHUNK_SIZE, "1000".to_owned());
+            serde_json::from_value::<Input>(serde_json::json!({ "query": query })).unwrap()
+        };
+        let mut rows = (0..10_000)
+            .map(|id| serde_json::json!({ "id": id, "fail": false }))
+            .collect::<Vec<_>>();
+
+        let output = run(Context::default(), upsert(&rows)).await.unwrap();
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), [1000; 10]);
+        let Value::Array(result) = &output["result"] else {
+            panic!("{:?}", output["result"]);
+        };
+        assert_eq!(result.len(), 10_000);
+        assert_eq!(result[9_999], Value::from(rows[9_999].clone()));
+        assert_eq!(output["affected_rows"], Value::U64(10_000));
+
+        rows[4321]["fail"] = true.into();
+        let error = run(Context::default(), upsert(&rows)).await.unwrap_err();
+        assert_eq!(rx.try_iter().count(), 5);
+        assert!(
+            error
+                .to_string()
+                .starts_with("rows 4000..5000 of 10000 failed"),
+            "{}",
+            error
+        );
+        assert!(error
+            .downcast_ref::<crate::postgrest::PostgrestError>()
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn test_rpc_in_gateway_path() {
+        let (url, rx) = mock_postgrest();
+        // a table behind a gateway mounted at `/rpc/` is not an RPC
+        let url = url.replace("/rest/v1/", "/rpc/rest/v1/");
+        let rows = [serde_json::json!({ "id": 1 })];
+        let mut query: postgrest::Query =
+            postgrest::Builder::new(&url, None, <_>::default(), reqwest::Client::new())
+                .insert(serde_json::to_string(&rows).unwrap())
+                .into();
+        ReturnMode::Representation.apply(&mut query);
+        assert!(!query.is_rpc);
+        let input = serde_json::from_value::<Input>(serde_json::json!({ "query": query })).unwrap();
+
+        let output = run(Context::default(), input).await.unwrap();
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), [1]);
+        assert_eq!(output["affected_rows"], Value::U64(1));
+        assert_eq!(output["result"], Value::from(serde_json::json!(rows)));
+    }
+
+    #[test]
+    fn test_affected_rows() {
+        let client = reqwest::Client::new();
+        let mut req = client
+            .patch("https://example.com/rest/v1/users?id=eq.1")
+            .header("Prefer", "return=minimal")
+            .build()
+            .unwrap();
+        prefer_count(&mut req);
+        assert_eq!(req.headers()["prefer"], "return=minimal,count=exact");
+        prefer_count(&mut req);
+        assert_eq!(req.headers()["prefer"], "return=minimal,count=exact");
+
+        let mut req = client
+            .get("https://example.com/rest/v1/users")
+            .build()
+            .unwrap();
+        prefer_count(&mut req);
+        assert!(req.headers().get("prefer").is_none());
+
+        // an update matching nothing
+        assert_eq!(affected_rows(&Method::PATCH, Some("*/0")), Some(0));
+        assert_eq!(affected_rows(&Method::DELETE, Some("0-2/3")), Some(3));
+        assert_eq!(affected_rows(&Method::PATCH, Some("0-2/*")), None);
+        assert_eq!(affected_rows(&Method::GET, Some("0-2/3")), None);
+    }
+
+    #[test]
+    fn test_fix_profile() {
+        let client = reqwest::Client::new();
+        let mut req = client
+            .post("https://example.com/rest/v1/rpc/f")
+            .header("Accept-Profile", "api")
+            .build()
+            .unwrap();
+        fix_profile(&mut req);
+        assert_eq!(req.headers()["Content-Profile"], "api");
+        assert!(!req.headers().contains_key("Accept-Profile"));
+
+        let mut req = client
+            .get("https://example.com/rest/v1/table")
+            .header("Accept-Profile", "api")
+            .build()
+            .unwrap();
+        fix_profile(&mut req);
+        assert_eq!(req.headers()["Accept-Profile"], "api");
+    }
+
+    #[test]
+    fn test_range_header() {
+        assert_eq!(range_header(None, Some(10)), "0-9");
+        assert_eq!(range_header(Some(20), Some(10)), "20-29");
+        assert_eq!(range_header(Some(20), None), "20-");
+    }
+
+    #[test]
+    fn test_paginate() {
+        use crate::postgrest::tests::{new_query, query_pairs};
+
+        let mut query = new_query();
+        assert!(paginate(&mut query, PaginationStyle::QueryParam, Some(20), Some(10)).unwrap());
+        assert_eq!(
+            query_pairs(query),
+            [
+                ("offset".to_owned(), "20".to_owned()),
+                ("limit".to_owned(), "10".to_owned())
+            ]
+        );
+
+        // e.g. set by `builder_limit`
+        let mut query: postgrest::Query =
+            postgrest::Builder::from_query(new_query(), reqwest::Client::new())
+                .limit(10)
+                .into();
+        assert!(paginate(&mut query, PaginationStyle::RangeHeader, Some(20), None).unwrap());
+        assert_eq!(get_header(&query, "Range"), Some("20-29"));
+        assert!(query_pairs(query).is_empty());
+
+        let mut query = new_query();
+        assert!(!paginate(&mut query, PaginationStyle::RangeHeader, None, None).unwrap());
+        assert!(paginate(&mut query, PaginationStyle::QueryParam, None, Some(0)).is_err());
+    }
+
+    #[test]
+    fn test_sort_params() {
+        use crate::postgrest::tests::{new_query, query_pairs};
+
+        let query = |pairs: &[(&str, &str)]| {
+            let mut query = new_query();
+            query
+                .queries
+                .extend(pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())));
+            sort_params(&mut query);
+            query_pairs(query)
+        };
+        let a = query(&[
+            ("select", "id"),
+            ("or", "(b.eq.2,a.eq.1)"),
+            ("id", "lt.5"),
+            ("id", "gt.1"),
+        ]);
+        let b = query(&[
+            ("id", "gt.1"),
+            ("or", "(b.eq.2,a.eq.1)"),
+            ("id", "lt.5"),
+            ("select", "id"),
+        ]);
+        assert_eq!(a, b);
+        assert_eq!(
+            a,
+            [
+                ("id".to_owned(), "gt.1".to_owned()),
+                ("id".to_owned(), "lt.5".to_owned()),
+                ("or".to_owned(), "(b.eq.2,a.eq.1)".to_owned()),
+                ("select".to_owned(), "id".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_error_label() {
+        use flow_lib::utils::metrics::ErrorLabel;
+
+        let label = |error: CommandError| QueryError(&error).label();
+        let postgrest = |code: &str, status| PostgrestError {
+            status,
+            code: code.to_owned(),
+            message: "failed".to_owned(),
+            details: None,
+            hint: None,
+        };
+        assert_eq!(
+            label(crate::error::Error::QueryTimeout(Duration::from_secs(1)).into()),
+            "timeout"
+        );
+        assert_eq!(
+            label(CommandError::from(postgrest("23505", 409)).context("rows 0..10 failed")),
+            "constraint_violation"
+        );
+        assert_eq!(label(postgrest("PGRST116", 406).into()), "postgrest");
+        assert_eq!(label(postgrest("XX000", 500).into()), "server_error");
+        assert_eq!(label(anyhow!("other")), "error");
+    }
+
+    #[test]
+    fn test_check_headers() {
+        let query = crate::postgrest::tests::new_query();
+        let headers = serde_json::from_value::<Headers>(serde_json::json!({
+            "CF-Access-Client-Id": "id",
+        }))
+        .unwrap()
+        .into_pairs();
+        let headers = check_headers(&query, &headers).unwrap();
+        assert_eq!(headers[0].0, "cf-access-client-id");
+
+        for name in ["Content-Type", "prefer"] {
+            let headers = [(name.to_owned(), "x".to_owned())];
+            assert!(check_headers(&query, &headers).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_rpc_result() {
+        let row = || Value::Map(value::map! { "id" => 1 });
+
+        // RETURNS int
+        assert_eq!(
+            rpc_result(Value::from(1), None).unwrap(),
+            ("scalar", Value::from(1))
+        );
+        assert_eq!(
+            rpc_result(Value::from(1), Some(RpcReturns::Scalar)).unwrap(),
+            ("scalar", Value::from(1))
+        );
+        assert!(rpc_result(row(), Some(RpcReturns::Scalar)).is_err());
+
+        // RETURNS users
+        assert_eq!(rpc_result(row(), None).unwrap(), ("row", row()));
+        assert_eq!(
+            rpc_result(Value::Array(vec![row()]), Some(RpcReturns::Row)).unwrap(),
+            ("row", row())
+        );
+        assert!(rpc_result(Value::from(1), Some(RpcReturns::Row)).is_err());
+
+        // RETURNS SETOF users
+        let rows = Value::Array(vec![row(), row()]);
+        assert_eq!(
+            rpc_result(rows.clone(), None).unwrap(),
+            ("rows", rows.clone())
+        );
+        assert_eq!(
+            rpc_result(rows.clone(), Some(RpcReturns::Rows)).unwrap(),
+            ("rows", rows)
+        );
+        assert_eq!(
+            rpc_result(row(), Some(RpcReturns::Rows)).unwrap(),
+            ("rows", Value::Array(vec![row()]))
+        );
+    }
+
+    #[test]
+    fn test_maybe_single() {
+        assert_eq!(maybe_single(Value::Array(Vec::new())).unwrap(), Value::Null);
+        assert_eq!(
+            maybe_single(Value::Array(vec![Value::from("a")])).unwrap(),
+            Value::from("a")
+        );
+        assert!(maybe_single(Value::Array(vec![Value::Null, Value::Null])).is_err());
+    }
+
+    #[test]
+    fn test_has_filter() {
+        let t = |s: &str| has_filter(&Url::parse(s).unwrap());
+        assert!(!t("https://example.com/rest/v1/table"));
+        assert!(!t(
+            "https://example.com/rest/v1/table?select=*&authors.order=name&limit=1"
+        ));
+        assert!(t("https://example.com/rest/v1/table?select=*&id=eq.1"));
+    }
 }
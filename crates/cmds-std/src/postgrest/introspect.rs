@@ -0,0 +1,498 @@
+use super::{postgrest_error, query_endpoints};
+use anyhow::anyhow;
+use flow_lib::{command::prelude::*, config::EndpointsOverride, UserId};
+use once_cell::sync::Lazy;
+use reqwest::{
+    header::{HeaderMap, HeaderValue, ACCEPT, AUTHORIZATION},
+    Url,
+};
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+const NAME: &str = "postgrest_introspect";
+
+#[derive(Deserialize, Debug)]
+struct Input {
+    /// Root of the API, defaults to `{supabase}/rest/v1/`.
+    #[serde(default)]
+    url: Option<String>,
+    #[serde(default)]
+    schema: Option<String>,
+    /// Use other endpoints, unset fields default to the context's endpoints.
+    #[serde(default)]
+    endpoints_override: Option<EndpointsOverride>,
+}
+
+#[derive(Serialize, Debug)]
+struct Output {
+    #[serde(flatten)]
+    schema: Schema,
+}
+
+/// Tables, views and functions exposed by PostgREST, only the ones visible to the user are
+/// listed.
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub(crate) struct Schema {
+    pub tables: Vec<Table>,
+    pub functions: Vec<Function>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub(crate) struct Table {
+    pub name: String,
+    pub columns: Vec<Column>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub(crate) struct Column {
+    pub name: String,
+    /// Postgres type, e.g. `bigint`, `timestamp with time zone`.
+    #[serde(rename = "type")]
+    pub ty: Option<String>,
+    /// Whether inserts must set the column, it is not nullable and has no default.
+    pub required: bool,
+    pub description: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub(crate) struct Function {
+    pub name: String,
+    pub parameters: Vec<Column>,
+}
+
+/// Parts of the OpenAPI (Swagger 2.0) description served at the root of PostgREST.
+mod openapi {
+    use serde::Deserialize;
+    use std::collections::BTreeMap;
+
+    #[derive(Deserialize, Debug)]
+    pub struct Document {
+        #[serde(default)]
+        pub definitions: serde_json::Map<String, serde_json::Value>,
+        #[serde(default)]
+        pub paths: BTreeMap<String, serde_json::Value>,
+    }
+
+    #[derive(Deserialize, Debug, Default)]
+    pub struct Definition {
+        /// Keeps the order of columns, serde_json has `preserve_order` enabled.
+        #[serde(default)]
+        pub properties: serde_json::Map<String, serde_json::Value>,
+        #[serde(default)]
+        pub required: Vec<String>,
+    }
+
+    #[derive(Deserialize, Debug, Default)]
+    pub struct Property {
+        #[serde(default)]
+        pub format: Option<String>,
+        #[serde(default)]
+        pub description: Option<String>,
+    }
+
+    #[derive(Deserialize, Debug, Default)]
+    pub struct Operation {
+        #[serde(default)]
+        pub parameters: Vec<Parameter>,
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct Parameter {
+        #[serde(rename = "in")]
+        pub location: String,
+        #[serde(default)]
+        pub schema: Option<Definition>,
+    }
+}
+
+fn columns(definition: openapi::Definition) -> Vec<Column> {
+    definition
+        .properties
+        .into_iter()
+        .map(|(name, property)| {
+            let property =
+                serde_json::from_value::<openapi::Property>(property).unwrap_or_default();
+            Column {
+                required: definition.required.contains(&name),
+                name,
+                ty: property.format,
+                description: property.description,
+            }
+        })
+        .collect()
+}
+
+impl Schema {
+    pub fn parse(body: &[u8]) -> Result<Self, serde_json::Error> {
+        let doc = serde_json::from_slice::<openapi::Document>(body)?;
+        let tables = doc
+            .definitions
+            .into_iter()
+            .map(|(name, definition)| Table {
+                name,
+                columns: columns(serde_json::from_value(definition).unwrap_or_default()),
+            })
+            .collect();
+        let functions = doc
+            .paths
+            .into_iter()
+            .filter_map(|(path, item)| {
+                let name = path.strip_prefix("/rpc/")?.to_owned();
+                // the body of POST lists all arguments, GET only has functions without
+                // side effects
+                let operation = item
+                    .get("post")
+                    .cloned()
+                    .and_then(|op| serde_json::from_value::<openapi::Operation>(op).ok())
+                    .unwrap_or_default();
+                let parameters = operation
+                    .parameters
+                    .into_iter()
+                    .find(|p| p.location == "body")
+                    .and_then(|p| p.schema)
+                    .map(columns)
+                    .unwrap_or_default();
+                Some(Function { name, parameters })
+            })
+            .collect();
+        Ok(Self { tables, functions })
+    }
+
+    pub fn table(&self, name: &str) -> Option<&Table> {
+        self.tables.iter().find(|t| t.name == name)
+    }
+}
+
+const CACHE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Credentials that schemas are fetched with, what is visible depends on the user's role.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Credentials {
+    /// JWT of the flow owner.
+    User(UserId),
+    /// Hash of an `Authorization` header set by the caller, tokens are not kept in memory.
+    Header(u64),
+    Anonymous,
+}
+
+impl Credentials {
+    fn header(value: &HeaderValue) -> Self {
+        let mut hasher = DefaultHasher::new();
+        value.as_bytes().hash(&mut hasher);
+        Self::Header(hasher.finish())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct CacheKey {
+    root: String,
+    schema: Option<String>,
+    credentials: Credentials,
+}
+
+/// Introspected schemas, keyed by API root, schema and credentials.
+///
+/// Contexts without a `SchemaCache` extension share one process-wide cache, register one
+/// to keep schemas separate, e.g. `Context::builder(cfg).register(SchemaCache::default())`.
+#[derive(Default)]
+pub struct SchemaCache {
+    entries: Mutex<HashMap<CacheKey, (Instant, Arc<Schema>)>>,
+}
+
+impl SchemaCache {
+    fn get(&self, key: &CacheKey) -> Option<Arc<Schema>> {
+        let entries = self.entries.lock().unwrap();
+        let (time, schema) = entries.get(key)?;
+        (time.elapsed() < CACHE_TTL).then(|| schema.clone())
+    }
+
+    /// Store a schema, and remove the ones that expired.
+    fn insert(&self, key: CacheKey, schema: Arc<Schema>) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, (time, _)| time.elapsed() < CACHE_TTL);
+        entries.insert(key, (Instant::now(), schema));
+    }
+}
+
+/// Cache of contexts that don't register their own.
+static SHARED_CACHE: Lazy<SchemaCache> = Lazy::new(<_>::default);
+
+fn schema_cache(ctx: &Context) -> &SchemaCache {
+    ctx.get::<SchemaCache>().unwrap_or(&SHARED_CACHE)
+}
+
+/// Get the OpenAPI description at `root`, e.g. `https://xyz.supabase.co/rest/v1/`.
+///
+/// The anon key and the user's JWT are sent if `root` is the context's Supabase project
+/// and `headers` doesn't have them. Results are cached for [`CACHE_TTL`], see
+/// [`SchemaCache`].
+pub(crate) async fn fetch_schema(
+    ctx: &mut Context,
+    root: Url,
+    schema: Option<String>,
+    mut headers: HeaderMap,
+) -> Result<Arc<Schema>, CommandError> {
+    let is_supabase = root
+        .as_str()
+        .starts_with(&format!("{}/rest/v1", ctx.endpoints.supabase));
+    let credentials = match headers.get(AUTHORIZATION) {
+        Some(value) => Credentials::header(value),
+        None if is_supabase => Credentials::User(ctx.flow_owner.id),
+        None => Credentials::Anonymous,
+    };
+    let mut key = CacheKey {
+        root: root.to_string(),
+        schema: schema.clone(),
+        credentials,
+    };
+    if let Some(schema) = schema_cache(ctx).get(&key) {
+        return Ok(schema);
+    }
+
+    if let Some(schema) = &schema {
+        headers.insert("accept-profile", schema.parse()?);
+    }
+    headers.insert(ACCEPT, HeaderValue::from_static("application/openapi+json"));
+    if is_supabase {
+        if !headers.contains_key("apikey") {
+            headers.insert("apikey", ctx.endpoints.supabase_anon_key.parse()?);
+        }
+        if !headers.contains_key(AUTHORIZATION) {
+            match ctx.get_jwt_header().await {
+                Ok(jwt) => {
+                    headers.insert(AUTHORIZATION, jwt.parse()?);
+                }
+                Err(error) => {
+                    tracing::warn!("introspecting {} anonymously: {}", root, error);
+                    // the anonymous role sees less, don't cache it for the user
+                    key.credentials = Credentials::Anonymous;
+                    if let Some(schema) = schema_cache(ctx).get(&key) {
+                        return Ok(schema);
+                    }
+                }
+            }
+        }
+    }
+
+    let resp = ctx.http.get(root).headers(headers).send().await?;
+    if !resp.status().is_success() {
+        return Err(postgrest_error(resp).await);
+    }
+    let result = Arc::new(Schema::parse(&resp.bytes().await?)?);
+    schema_cache(ctx).insert(key, result.clone());
+    Ok(result)
+}
+
+async fn run(mut ctx: Context, input: Input) -> Result<Output, CommandError> {
+    let endpoints = query_endpoints(&ctx.endpoints, input.endpoints_override.as_ref());
+    let url = input
+        .url
+        .filter(|u| !u.is_empty())
+        .unwrap_or_else(|| format!("{}/rest/v1/", endpoints.supabase));
+    let mut root = Url::parse(&url).map_err(|error| anyhow!("invalid URL {:?}: {}", url, error))?;
+    if !root.path().ends_with('/') {
+        root.set_path(&format!("{}/", root.path()));
+    }
+    let mut headers = HeaderMap::new();
+    if !endpoints.supabase_anon_key.is_empty()
+        && endpoints.supabase_anon_key != ctx.endpoints.supabase_anon_key
+    {
+        headers.insert("apikey", endpoints.supabase_anon_key.parse()?);
+    }
+    let schema = input.schema.filter(|s| !s.is_empty());
+    Ok(Output {
+        schema: Schema::clone(&*fetch_schema(&mut ctx, root, schema, headers).await?),
+    })
+}
+
+fn build() -> BuildResult {
+    Ok(
+        CmdBuilder::new(flow_lib::node_definition!("postgrest/introspect.json"))?
+            .check_name(NAME)?
+            .build(run),
+    )
+}
+
+flow_lib::submit!(CommandDescription::new(NAME, |_| build()));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build() {
+        build().unwrap();
+    }
+
+    #[test]
+    fn test_parse() {
+        let body = serde_json::json!({
+            "swagger": "2.0",
+            "definitions": {
+                "users": {
+                    "required": ["id"],
+                    "properties": {
+                        "id": { "format": "bigint", "type": "integer" },
+                        "name": { "format": "text", "type": "string", "description": "display name" }
+                    }
+                }
+            },
+            "paths": {
+                "/": {},
+                "/users": { "get": {} },
+                "/rpc/add": {
+                    "post": {
+                        "parameters": [
+                            { "in": "header", "name": "Prefer" },
+                            {
+                                "in": "body",
+                                "name": "args",
+                                "schema": {
+                                    "required": ["a"],
+                                    "properties": { "a": { "format": "integer" } }
+                                }
+                            }
+                        ]
+                    }
+                }
+            }
+        });
+        let schema = Schema::parse(body.to_string().as_bytes()).unwrap();
+        assert_eq!(
+            schema.table("users").unwrap().columns,
+            [
+                Column {
+                    name: "id".to_owned(),
+                    ty: Some("bigint".to_owned()),
+                    required: true,
+                    description: None,
+                },
+                Column {
+                    name: "name".to_owned(),
+                    ty: Some("text".to_owned()),
+                    required: false,
+                    description: Some("display name".to_owned()),
+                },
+            ]
+        );
+        assert_eq!(
+            schema.functions,
+            [Function {
+                name: "add".to_owned(),
+                parameters: vec![Column {
+                    name: "a".to_owned(),
+                    ty: Some("integer".to_owned()),
+                    required: true,
+                    description: None,
+                }],
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cache() {
+        use std::io::{BufRead, BufReader, Write};
+
+        // serve an empty description, send `Authorization` header of each request
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let supabase = format!("http://{}", listener.local_addr().unwrap());
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut reader = BufReader::new(stream.unwrap());
+                let mut auth = None;
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap_or(0) > 0 && line != "\r\n" {
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("authorization") {
+                            auth = Some(value.trim().to_owned());
+                        }
+                    }
+                    line.clear();
+                }
+                tx.send(auth).unwrap();
+                let resp = "HTTP/1.1 200 OK\r\ncontent-type: application/openapi+json\r\n\
+                            content-length: 2\r\nconnection: close\r\n\r\n{}";
+                reader.get_mut().write_all(resp.as_bytes()).unwrap();
+            }
+        });
+
+        let mut cfg = flow_lib::ContextConfig::default();
+        cfg.endpoints.supabase = supabase.clone();
+        cfg.endpoints.supabase_anon_key = "anon".to_owned();
+        let mut ctx = Context::builder(cfg.clone())
+            .flow_owner(<_>::default())
+            .register(SchemaCache::default())
+            .build()
+            .unwrap();
+        let root = Url::parse(&format!("{}/rest/v1/", supabase)).unwrap();
+
+        // `get_jwt` is not configured, the anonymous schema is not cached for the user
+        for _ in 0..2 {
+            fetch_schema(&mut ctx, root.clone(), None, HeaderMap::new())
+                .await
+                .unwrap();
+        }
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), [None]);
+        let mut auth = HeaderMap::new();
+        auth.insert(AUTHORIZATION, HeaderValue::from_static("Bearer token"));
+        for _ in 0..2 {
+            fetch_schema(&mut ctx, root.clone(), None, auth.clone())
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            rx.try_iter().collect::<Vec<_>>(),
+            [Some("Bearer token".to_owned())]
+        );
+        let cache = ctx.get::<SchemaCache>().unwrap();
+        let mut credentials = cache
+            .entries
+            .lock()
+            .unwrap()
+            .keys()
+            .map(|key| key.credentials.clone())
+            .collect::<Vec<_>>();
+        credentials.sort_by_key(|c| matches!(c, Credentials::Anonymous));
+        assert_eq!(
+            credentials,
+            [
+                Credentials::header(&HeaderValue::from_static("Bearer token")),
+                Credentials::Anonymous
+            ]
+        );
+
+        // contexts of flow runs, which don't register a cache, share one
+        for _ in 0..2 {
+            let mut ctx = crate::postgrest::tests::production_context(&cfg);
+            fetch_schema(&mut ctx, root.clone(), None, auth.clone())
+                .await
+                .unwrap();
+        }
+        assert_eq!(rx.try_iter().count(), 1);
+    }
+
+    #[test]
+    fn test_cache_eviction() {
+        let cache = SchemaCache::default();
+        let key = |root: &str| CacheKey {
+            root: root.to_owned(),
+            schema: None,
+            credentials: Credentials::Anonymous,
+        };
+        let expired = Instant::now().checked_sub(CACHE_TTL).unwrap();
+        cache
+            .entries
+            .lock()
+            .unwrap()
+            .insert(key("a"), (expired, <_>::default()));
+        assert_eq!(cache.get(&key("a")), None);
+        cache.insert(key("b"), <_>::default());
+        assert!(cache.get(&key("b")).is_some());
+        assert_eq!(cache.entries.lock().unwrap().len(), 1);
+    }
+}
@@ -1,14 +1,549 @@
+use anyhow::anyhow;
+use flow_lib::{
+    command::CommandError,
+    config::{Endpoints, EndpointsOverride},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::borrow::Cow;
+use value::Value;
+
+pub mod builder_contained_in;
+pub mod builder_contains;
+pub mod builder_count;
+pub mod builder_delete;
 pub mod builder_eq;
+pub mod builder_fts;
+pub mod builder_gt;
+pub mod builder_gte;
+pub mod builder_ilike;
+pub mod builder_in;
 pub mod builder_insert;
 pub mod builder_is;
+pub mod builder_like;
 pub mod builder_limit;
+pub mod builder_lt;
+pub mod builder_lte;
 pub mod builder_match;
 pub mod builder_neq;
 pub mod builder_not;
+pub mod builder_or;
 pub mod builder_order;
+pub mod builder_overlaps;
+pub mod builder_range;
 pub mod builder_select;
 pub mod builder_update;
 pub mod builder_upsert;
 pub mod execute_query;
+pub mod introspect;
 pub mod new_query;
 pub mod new_rpc;
+
+/// Format a [`Value`] as the operand of a PostgREST filter, e.g. the `5` in `gt.5`.
+///
+/// Numbers are written as-is, strings (including RFC3339 timestamps) are not quoted,
+/// URL-encoding is done by `reqwest` when the query string is built.
+pub(crate) fn filter_value(value: &Value) -> Result<String, CommandError> {
+    Ok(match value {
+        Value::Null => "null".to_owned(),
+        Value::String(x) => x.clone(),
+        Value::Bool(x) => x.to_string(),
+        Value::U64(x) => x.to_string(),
+        Value::I64(x) => x.to_string(),
+        Value::F64(x) if x.is_nan() => "NaN".to_owned(),
+        Value::F64(x) if x.is_infinite() => {
+            if x.is_sign_positive() {
+                "Infinity".to_owned()
+            } else {
+                "-Infinity".to_owned()
+            }
+        }
+        Value::F64(x) => x.to_string(),
+        Value::Decimal(x) => x.normalize().to_string(),
+        Value::U128(x) => x.to_string(),
+        Value::I128(x) => x.to_string(),
+        Value::B32(x) => bs58::encode(x).into_string(),
+        Value::B64(x) => bs58::encode(x).into_string(),
+        Value::Bytes(_) => return Err(anyhow!("bytes type is not supported")),
+        Value::Array(_) => return Err(anyhow!("array type is not supported")),
+        Value::Map(_) => return Err(anyhow!("object type is not supported")),
+    })
+}
+
+/// Quote an element of a PostgREST list, e.g. `in.(a,"b,c")`, if it contains reserved
+/// characters or whitespace. Double quotes and backslashes inside are escaped with `\`.
+pub(crate) fn quote_element(s: &str) -> Cow<'_, str> {
+    const RESERVED: &[char] = &[',', '.', ':', '(', ')', '{', '}', '"', '\\'];
+    let needs_quote = s.is_empty()
+        || s.eq_ignore_ascii_case("null")
+        || s.contains(RESERVED)
+        || s.contains(char::is_whitespace);
+    if needs_quote {
+        let escaped = s.replace('\\', "\\\\").replace('"', "\\\"");
+        Cow::Owned(format!("\"{}\"", escaped))
+    } else {
+        Cow::Borrowed(s)
+    }
+}
+
+/// Format a [`Value`] as an element of a PostgREST list, strings are quoted when needed.
+pub(crate) fn list_element(value: &Value) -> Result<String, CommandError> {
+    match value {
+        Value::String(x) => Ok(quote_element(x).into_owned()),
+        value => filter_value(value),
+    }
+}
+
+/// Format a [`Value`] as the operand of an array or JSONB operator (`cs`, `cd`, `ov`).
+///
+/// Arrays become Postgres array literals, e.g. `{a,"b c"}`, objects are serialized as JSON.
+pub(crate) fn collection_value(value: &Value) -> Result<String, CommandError> {
+    match value {
+        Value::Array(values) => {
+            let values = values
+                .iter()
+                .map(list_element)
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(format!("{{{}}}", values.join(",")))
+        }
+        Value::Map(_) => Ok(serde_json::Value::from(value.clone()).to_string()),
+        _ => Err(anyhow!("expected an array or an object")),
+    }
+}
+
+/// Append a raw `column=filter` pair to the query string.
+pub(crate) fn push_filter(query: &mut postgrest::Query, column: String, filter: String) {
+    query.queries.push((column, filter));
+}
+
+/// Parse the total count of a `Content-Range` header, e.g. `0-24/3573` or `*/0`.
+///
+/// Returns `None` if the header doesn't include a count, e.g. `0-24/*`.
+pub(crate) fn parse_content_range(header: &str) -> Option<u64> {
+    header.rsplit_once('/')?.1.trim().parse().ok()
+}
+
+/// Headers that are only read by [`execute_query`] and never sent to PostgREST.
+pub(crate) mod marker {
+    /// Set by [`builder_delete`][super::builder_delete], refuse to send a `DELETE` without
+    /// filters.
+    pub const REQUIRE_FILTER: &str = "x-space-operator-require-filter";
+    /// Set by [`builder_count`][super::builder_count], send a `HEAD` request instead.
+    pub const HEAD: &str = "x-space-operator-head";
+    /// Set by [`new_rpc`][super::new_rpc], the value is a [`RpcReturns`][super::RpcReturns].
+    pub const RPC_RETURNS: &str = "x-space-operator-rpc-returns";
+    /// Set by [`builder_upsert`][super::builder_upsert], number of rows sent per request.
+    pub const CHUNK_SIZE: &str = "x-space-operator-chunk-size";
+}
+
+/// Set a header, replacing any existing header with the same name.
+pub(crate) fn set_header(query: &mut postgrest::Query, name: &str, value: String) {
+    query.headers.retain(|(k, _)| !k.eq_ignore_ascii_case(name));
+    query.headers.push((name.to_owned(), value));
+}
+
+pub(crate) fn get_header<'a>(query: &'a postgrest::Query, name: &str) -> Option<&'a str> {
+    query
+        .headers
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_str())
+}
+
+/// Get a preference from the `Prefer` header, e.g. `minimal` of `return=minimal`.
+pub(crate) fn get_prefer<'a>(query: &'a postgrest::Query, key: &str) -> Option<&'a str> {
+    get_header(query, "Prefer")?
+        .split(',')
+        .filter_map(|p| p.trim().split_once('='))
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| v)
+}
+
+/// Set a preference in the `Prefer` header, keeping other preferences.
+pub(crate) fn set_prefer(query: &mut postgrest::Query, key: &str, value: &str) {
+    let mut prefs = get_header(query, "Prefer")
+        .map(|h| {
+            h.split(',')
+                .map(str::trim)
+                .filter(|p| !p.is_empty() && p.split('=').next() != Some(key))
+                .map(str::to_owned)
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    prefs.push(format!("{}={}", key, value));
+    set_header(query, "Prefer", prefs.join(","));
+}
+
+/// `Prefer: return=` of write requests.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReturnMode {
+    /// Return nothing.
+    Minimal,
+    /// Return only headers, e.g. `Location`.
+    HeadersOnly,
+    /// Return affected rows.
+    #[default]
+    Representation,
+}
+
+impl ReturnMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReturnMode::Minimal => "minimal",
+            ReturnMode::HeadersOnly => "headers-only",
+            ReturnMode::Representation => "representation",
+        }
+    }
+
+    pub(crate) fn apply(&self, query: &mut postgrest::Query) {
+        set_prefer(query, "return", self.as_str());
+    }
+}
+
+/// What an RPC function returns, [`execute_query`] checks the result against it.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RpcReturns {
+    /// A value that is not a row, e.g. `RETURNS int` or `RETURNS int[]`.
+    Scalar,
+    /// One row or `null`, e.g. `RETURNS users`.
+    Row,
+    /// An array of rows, `RETURNS SETOF` or `RETURNS TABLE`.
+    Rows,
+}
+
+impl RpcReturns {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RpcReturns::Scalar => "scalar",
+            RpcReturns::Row => "row",
+            RpcReturns::Rows => "rows",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        [RpcReturns::Scalar, RpcReturns::Row, RpcReturns::Rows]
+            .into_iter()
+            .find(|r| r.as_str() == s)
+    }
+}
+
+/// Endpoints of a new query, `endpoints_override` is merged with the context's endpoints.
+pub(crate) fn query_endpoints(
+    endpoints: &Endpoints,
+    endpoints_override: Option<&EndpointsOverride>,
+) -> Endpoints {
+    match endpoints_override {
+        Some(o) => endpoints.merge(o),
+        None => endpoints.clone(),
+    }
+}
+
+/// Send the anon key of an overridden Supabase project with the query, [`execute_query`]
+/// only knows the key of the context's project.
+pub(crate) fn set_apikey(
+    query: &mut postgrest::Query,
+    endpoints: &Endpoints,
+    endpoints_override: Option<&EndpointsOverride>,
+) {
+    let overridden =
+        endpoints_override.is_some_and(|o| o.supabase.is_some() || o.supabase_anon_key.is_some());
+    if overridden && !endpoints.supabase_anon_key.is_empty() {
+        set_header(query, "apikey", endpoints.supabase_anon_key.clone());
+    }
+}
+
+/// Column storing the idempotency key of inserted rows, it must have a unique constraint.
+pub(crate) const DEFAULT_IDEMPOTENCY_COLUMN: &str = "idempotency_key";
+
+/// Make a write safe to re-execute: `key` is stored in `column` of every row, rows of a
+/// bulk insert get `{key}:{index}`. The write is then sent as an upsert on `column`, so the
+/// unique constraint rejects rows that were already written.
+///
+/// PostgREST has no `Idempotency-Key` header, so deduplication is done by the database.
+pub(crate) fn set_idempotency_key(
+    body: &mut JsonValue,
+    column: &str,
+    key: &str,
+) -> Result<(), CommandError> {
+    let set = |row: &mut JsonValue, key: String| {
+        row.as_object_mut()
+            .map(|row| {
+                row.insert(column.to_owned(), JsonValue::String(key));
+            })
+            .ok_or_else(|| anyhow!("rows must be objects to use idempotency_key"))
+    };
+    match body {
+        JsonValue::Array(rows) => {
+            for (index, row) in rows.iter_mut().enumerate() {
+                set(row, format!("{}:{}", key, index))?;
+            }
+            Ok(())
+        }
+        row => set(row, key.to_owned()),
+    }
+}
+
+/// Run a write and return its result, including constraint errors, but roll back the
+/// transaction so nothing is persisted.
+///
+/// PostgREST only honors `tx=rollback` since v10, when `db-tx-end` is set to
+/// `commit-allow-override` or `rollback-allow-override`. Otherwise the preference is
+/// ignored and the write is committed, `handling=strict` (v11.1+) makes PostgREST reject
+/// the request instead.
+pub(crate) fn set_rollback(query: &mut postgrest::Query) {
+    set_prefer(query, "tx", "rollback");
+    set_prefer(query, "handling", "strict");
+}
+
+/// Error body of PostgREST, e.g. a constraint violation.
+///
+/// <https://postgrest.org/en/stable/references/errors.html>
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PostgrestError {
+    /// HTTP status of the response.
+    #[serde(skip)]
+    pub status: u16,
+    /// PostgreSQL error code, e.g. `23505`, or PostgREST's own code, e.g. `PGRST116`.
+    pub code: String,
+    pub message: String,
+    #[serde(default)]
+    pub details: Option<String>,
+    #[serde(default)]
+    pub hint: Option<String>,
+}
+
+impl PostgrestError {
+    pub fn parse(status: reqwest::StatusCode, body: &[u8]) -> Option<Self> {
+        let error = serde_json::from_slice::<Self>(body).ok()?;
+        Some(Self {
+            status: status.as_u16(),
+            ..error
+        })
+    }
+}
+
+impl std::fmt::Display for PostgrestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ({}, status {})",
+            self.message, self.code, self.status
+        )?;
+        if let Some(details) = self.details.as_deref().filter(|d| !d.is_empty()) {
+            write!(f, "; details: {}", details)?;
+        }
+        if let Some(hint) = self.hint.as_deref().filter(|h| !h.is_empty()) {
+            write!(f, "; hint: {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for PostgrestError {}
+
+/// Turn an error response into [`PostgrestError`], other bodies, e.g. errors of the
+/// API gateway, are returned as text.
+pub(crate) async fn postgrest_error(resp: reqwest::Response) -> CommandError {
+    let status = resp.status();
+    let bytes = resp.bytes().await.unwrap_or_default();
+    match PostgrestError::parse(status, &bytes) {
+        Some(error) => error.into(),
+        None => match serde_json::from_slice::<crate::ErrorBody>(&bytes) {
+            Ok(crate::ErrorBody { error }) => CommandError::msg(error),
+            Err(_) => anyhow!("{}: {}", status, String::from_utf8_lossy(&bytes)),
+        },
+    }
+}
+
+/// Remove a header and return its value.
+pub(crate) fn take_header(query: &mut postgrest::Query, name: &str) -> Option<String> {
+    let index = query
+        .headers
+        .iter()
+        .position(|(k, _)| k.eq_ignore_ascii_case(name))?;
+    Some(query.headers.remove(index).1)
+}
+
+/// A filter condition that can be applied to a query, or combined with others by
+/// [`builder_or`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FilterFragment {
+    pub column: String,
+    /// e.g. `gt`, `fts(english)`.
+    pub operator: String,
+    pub operand: String,
+    /// `operand` is used verbatim in logical operators, e.g. a list literal `(a,"b,c")`
+    /// or a keyword such as `null`.
+    #[serde(default)]
+    pub literal: bool,
+}
+
+impl FilterFragment {
+    pub fn new(column: String, operator: &str, operand: String) -> Self {
+        Self {
+            column,
+            operator: operator.to_owned(),
+            operand,
+            literal: false,
+        }
+    }
+
+    pub fn literal(column: String, operator: &str, operand: String) -> Self {
+        Self {
+            literal: true,
+            ..Self::new(column, operator, operand)
+        }
+    }
+
+    /// Value of the query string pair, e.g. `gt.5` in `age=gt.5`.
+    pub fn filter(&self) -> String {
+        format!("{}.{}", self.operator, self.operand)
+    }
+
+    /// Format as a condition of a logical operator, e.g. `age.gt.5` in `or=(age.gt.5,...)`.
+    /// Scalar operands are quoted if they contain reserved characters.
+    pub fn condition(&self) -> String {
+        let operand = if self.literal {
+            Cow::Borrowed(self.operand.as_str())
+        } else {
+            quote_element(&self.operand)
+        };
+        format!("{}.{}.{}", self.column, self.operator, operand)
+    }
+
+    pub fn apply(&self, query: &mut postgrest::Query) {
+        push_filter(query, self.column.clone(), self.filter());
+    }
+}
+
+/// Output of filter builders, `query` is only returned if the input has one.
+#[derive(Serialize, Debug)]
+pub(crate) struct FilterOutput {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query: Option<postgrest::Query>,
+    pub fragment: FilterFragment,
+}
+
+impl FilterOutput {
+    pub fn new(query: Option<postgrest::Query>, fragment: FilterFragment) -> Self {
+        let query = query.map(|mut query| {
+            fragment.apply(&mut query);
+            query
+        });
+        Self { query, fragment }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Context without extensions of this crate, like the one of `FlowGraph::from_cfg`.
+    pub fn production_context(cfg: &flow_lib::ContextConfig) -> flow_lib::Context {
+        use flow_lib::context::{get_jwt, signer};
+        let user = flow_lib::User::default();
+        flow_lib::Context::from_cfg(
+            cfg,
+            user,
+            user,
+            signer::unimplemented_svc(),
+            get_jwt::unimplemented_svc(),
+            flow_lib::utils::Extensions::new(),
+        )
+    }
+
+    pub fn new_query() -> postgrest::Query {
+        postgrest::Builder::new(
+            "https://base.spaceoperator.com/rest/v1/table",
+            None,
+            <_>::default(),
+            reqwest::Client::new(),
+        )
+        .into()
+    }
+
+    pub fn query_pairs(query: postgrest::Query) -> Vec<(String, String)> {
+        postgrest::Builder::from_query(query, reqwest::Client::new())
+            .build()
+            .build()
+            .unwrap()
+            .url()
+            .query_pairs()
+            .into_owned()
+            .collect()
+    }
+
+    #[test]
+    fn test_set_idempotency_key() {
+        let mut body = serde_json::json!([{ "name": "a" }, { "name": "b" }]);
+        set_idempotency_key(&mut body, "key", "run-1").unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!([
+                { "name": "a", "key": "run-1:0" },
+                { "name": "b", "key": "run-1:1" },
+            ])
+        );
+        assert!(set_idempotency_key(&mut serde_json::json!(1), "key", "run-1").is_err());
+    }
+
+    #[test]
+    fn test_postgrest_error() {
+        let body = br#"{
+            "code": "23505",
+            "details": "Key (name)=(a) already exists.",
+            "hint": null,
+            "message": "duplicate key value violates unique constraint \"users_name_key\""
+        }"#;
+        let error = PostgrestError::parse(reqwest::StatusCode::CONFLICT, body).unwrap();
+        assert_eq!(error.status, 409);
+        assert_eq!(error.code, "23505");
+        assert_eq!(error.hint, None);
+        assert_eq!(
+            error.to_string(),
+            "duplicate key value violates unique constraint \"users_name_key\" \
+            (23505, status 409); details: Key (name)=(a) already exists."
+        );
+        assert!(PostgrestError::parse(reqwest::StatusCode::UNAUTHORIZED, b"{}").is_none());
+    }
+
+    #[test]
+    fn test_parse_content_range() {
+        assert_eq!(parse_content_range("0-24/3573"), Some(3573));
+        assert_eq!(parse_content_range("*/0"), Some(0));
+        assert_eq!(parse_content_range("0-24/*"), None);
+    }
+
+    #[test]
+    fn test_prefer() {
+        let mut query: postgrest::Query =
+            postgrest::Builder::from_query(new_query(), reqwest::Client::new())
+                .upsert("{}")
+                .into();
+        ReturnMode::Minimal.apply(&mut query);
+        assert_eq!(get_prefer(&query, "return"), Some("minimal"));
+        assert_eq!(get_prefer(&query, "resolution"), Some("merge-duplicates"));
+    }
+
+    #[test]
+    fn test_filter_value() {
+        assert_eq!(filter_value(&Value::U64(10)).unwrap(), "10");
+        assert_eq!(filter_value(&Value::I64(-10)).unwrap(), "-10");
+        assert_eq!(filter_value(&Value::F64(1.5)).unwrap(), "1.5");
+        assert_eq!(
+            filter_value(&Value::F64(f64::NEG_INFINITY)).unwrap(),
+            "-Infinity"
+        );
+        assert_eq!(
+            filter_value(&Value::Decimal("1.2500".parse().unwrap())).unwrap(),
+            "1.25"
+        );
+        assert_eq!(
+            filter_value(&Value::String("2024-01-01T00:00:00+07:00".to_owned())).unwrap(),
+            "2024-01-01T00:00:00+07:00"
+        );
+        assert!(filter_value(&Value::Array(Vec::new())).is_err());
+    }
+}
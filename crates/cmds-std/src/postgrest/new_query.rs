@@ -1,4 +1,6 @@
-use flow_lib::command::prelude::*;
+use super::{query_endpoints, set_apikey};
+use anyhow::bail;
+use flow_lib::{command::prelude::*, config::EndpointsOverride};
 
 const NAME: &str = "postgrest_new_query";
 
@@ -7,6 +9,12 @@ struct Input {
     url: Option<String>,
     schema: Option<String>,
     table: String,
+    /// Accept `schema.table`, the schema is sent in `Accept-Profile` or `Content-Profile`.
+    #[serde(default)]
+    allow_schema: bool,
+    /// Use other endpoints for this query, unset fields default to the context's endpoints.
+    #[serde(default)]
+    endpoints_override: Option<EndpointsOverride>,
 }
 
 #[derive(Serialize, Debug)]
@@ -14,11 +22,61 @@ struct Output {
     query: postgrest::Query,
 }
 
+/// Check that `name` can be used in the URL path, returns the schema and the table.
+fn parse_table(name: &str, allow_schema: bool) -> Result<(Option<&str>, &str), CommandError> {
+    let (schema, table) = match name.split_once('.') {
+        Some((schema, table)) if allow_schema => (Some(schema), table),
+        Some(_) => bail!(
+            "schema-qualified table name {:?} is not allowed, use `schema` or set `allow_schema`",
+            name
+        ),
+        None => (None, name),
+    };
+    let mut offset = 0;
+    for part in schema.into_iter().chain([table]) {
+        if part.is_empty() {
+            bail!("invalid table name {:?}: empty identifier", name);
+        }
+        if let Some((index, c)) = part
+            .char_indices()
+            .find(|(_, c)| !(c.is_alphanumeric() || matches!(c, '_' | '$' | '-')))
+        {
+            bail!(
+                "invalid character {:?} at position {} in table name {:?}",
+                c,
+                offset + index,
+                name
+            );
+        }
+        offset += part.len() + 1;
+    }
+    Ok((schema, table))
+}
+
 async fn run(ctx: Context, input: Input) -> Result<Output, CommandError> {
-    let url = input
-        .url
-        .unwrap_or_else(|| format!("{}/rest/v1/{}", ctx.endpoints.supabase, input.table));
-    let query = postgrest::Builder::new(url, input.schema, <_>::default(), ctx.http).into();
+    let endpoints_override = input.endpoints_override.as_ref();
+    let endpoints = query_endpoints(&ctx.endpoints, endpoints_override);
+    let mut schema = input.schema.filter(|s| !s.is_empty());
+    let url = match input.url {
+        Some(url) => url,
+        None => {
+            let (table_schema, table) = parse_table(&input.table, input.allow_schema)?;
+            if let Some(table_schema) = table_schema {
+                if schema.as_deref().is_some_and(|s| s != table_schema) {
+                    bail!(
+                        "schema {:?} doesn't match table name {:?}",
+                        schema.unwrap_or_default(),
+                        input.table
+                    );
+                }
+                schema = Some(table_schema.to_owned());
+            }
+            format!("{}/rest/v1/{}", endpoints.supabase, table)
+        }
+    };
+    // `Accept-Profile` or `Content-Profile` header is set when the request is built
+    let mut query = postgrest::Builder::new(url, schema, <_>::default(), ctx.http).into();
+    set_apikey(&mut query, &endpoints, endpoints_override);
     Ok(Output { query })
 }
 
@@ -40,4 +98,46 @@ mod tests {
     fn test_build() {
         build().unwrap();
     }
+
+    #[test]
+    fn test_parse_table() {
+        assert_eq!(parse_table("users", false).unwrap(), (None, "users"));
+        assert_eq!(
+            parse_table("auth.users", true).unwrap(),
+            (Some("auth"), "users")
+        );
+        assert!(parse_table("auth.users", false).is_err());
+        assert!(parse_table("", false).is_err());
+        assert_eq!(
+            parse_table("auth.us/ers", true).unwrap_err().to_string(),
+            r#"invalid character '/' at position 7 in table name "auth.us/ers""#
+        );
+        assert_eq!(
+            parse_table("users?select=*", false)
+                .unwrap_err()
+                .to_string(),
+            r#"invalid character '?' at position 5 in table name "users?select=*""#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_endpoints_override() {
+        let input: Input = serde_json::from_value(serde_json::json!({
+            "table": "users",
+            "endpoints_override": {
+                "supabase": "https://staging.spaceoperator.com",
+                "supabase_anon_key": "anon",
+            },
+        }))
+        .unwrap();
+        let output = run(Context::default(), input).await.unwrap();
+        assert_eq!(
+            output.query.url,
+            "https://staging.spaceoperator.com/rest/v1/users"
+        );
+        assert_eq!(
+            crate::postgrest::get_header(&output.query, "apikey"),
+            Some("anon")
+        );
+    }
 }
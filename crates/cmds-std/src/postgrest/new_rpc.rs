@@ -1,13 +1,68 @@
-use flow_lib::command::prelude::*;
+use super::{
+    collection_value, filter_value, marker, query_endpoints, set_apikey, set_header, RpcReturns,
+};
+use anyhow::{anyhow, bail};
+use flow_lib::{command::prelude::*, config::EndpointsOverride};
+use std::collections::HashMap;
 
 const NAME: &str = "postgrest_new_rpc";
 
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+enum RpcMethod {
+    /// Call read-only (`STABLE` or `IMMUTABLE`) functions, arguments are sent in the query
+    /// string so that responses can be cached.
+    Get,
+    #[default]
+    Post,
+}
+
+/// Postgres type of an argument, values are converted to it before sending.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum ParamType {
+    #[serde(alias = "integer", alias = "bigint", alias = "smallint")]
+    Int,
+    #[serde(alias = "decimal")]
+    Numeric,
+    #[serde(alias = "boolean")]
+    Bool,
+    #[serde(alias = "timestamp with time zone")]
+    Timestamptz,
+}
+
+impl ParamType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ParamType::Int => "int",
+            ParamType::Numeric => "numeric",
+            ParamType::Bool => "bool",
+            ParamType::Timestamptz => "timestamptz",
+        }
+    }
+}
+
 #[derive(Deserialize, Debug)]
 struct Input {
     url: Option<String>,
     schema: Option<String>,
     function: String,
     params: JsonValue,
+    #[serde(default)]
+    method: RpcMethod,
+    /// Names of the function's parameters, `params` is checked against it before sending.
+    #[serde(default)]
+    expected_params: Option<Vec<String>>,
+    /// Types of some parameters, e.g. numeric strings of other nodes are sent as numbers
+    /// to `int` parameters.
+    #[serde(default)]
+    param_types: HashMap<String, ParamType>,
+    /// Return type of the function, the result is auto-detected if not set.
+    #[serde(default)]
+    returns: Option<RpcReturns>,
+    /// Use other endpoints for this query, unset fields default to the context's endpoints.
+    #[serde(default)]
+    endpoints_override: Option<EndpointsOverride>,
 }
 
 #[derive(Serialize, Debug)]
@@ -15,15 +70,196 @@ struct Output {
     query: postgrest::Query,
 }
 
-async fn run(ctx: Context, input: Input) -> Result<Output, CommandError> {
-    let url = input
-        .url
-        .unwrap_or_else(|| format!("{}/rest/v1", ctx.endpoints.supabase));
+fn check_params(params: &JsonValue, expected: &[String]) -> Result<(), CommandError> {
+    let empty = serde_json::Map::new();
+    let params = match params {
+        JsonValue::Object(params) => params,
+        JsonValue::Null => &empty,
+        _ => bail!("`params` must be an object"),
+    };
+    let missing = expected
+        .iter()
+        .filter(|name| !params.contains_key(name.as_str()))
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+    let unexpected = params
+        .keys()
+        .filter(|name| !expected.contains(*name))
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+    match (missing.is_empty(), unexpected.is_empty()) {
+        (true, true) => Ok(()),
+        (false, true) => Err(anyhow!("missing parameters: {}", missing.join(", "))),
+        (true, false) => Err(anyhow!("unexpected parameters: {}", unexpected.join(", "))),
+        (false, false) => Err(anyhow!(
+            "missing parameters: {}; unexpected parameters: {}",
+            missing.join(", "),
+            unexpected.join(", ")
+        )),
+    }
+}
+
+/// Convert `value` to `ty`, `null` is kept as SQL `NULL`.
+fn coerce(value: &JsonValue, ty: ParamType) -> Option<JsonValue> {
+    let text = match value {
+        JsonValue::Null => return Some(JsonValue::Null),
+        JsonValue::String(s) => Some(s.trim()),
+        _ => None,
+    };
+    match ty {
+        ParamType::Int => match (value, text) {
+            (JsonValue::Number(n), _) if n.is_i64() || n.is_u64() => Some(value.clone()),
+            (JsonValue::Number(n), _) => {
+                let f = n.as_f64()?;
+                (f.fract() == 0.0 && f.abs() < i64::MAX as f64).then(|| (f as i64).into())
+            }
+            (_, Some(text)) => text.parse::<i64>().ok().map(Into::into),
+            _ => None,
+        },
+        // kept as text so that precision is not lost in a float
+        ParamType::Numeric => match (value, text) {
+            (JsonValue::Number(_), _) => Some(value.clone()),
+            (_, Some(text)) => {
+                let d = text
+                    .parse::<Decimal>()
+                    .or_else(|_| Decimal::from_scientific(text))
+                    .ok()?;
+                Some(d.to_string().into())
+            }
+            _ => None,
+        },
+        ParamType::Bool => match (value, text) {
+            (JsonValue::Bool(_), _) => Some(value.clone()),
+            (JsonValue::Number(n), _) => match n.as_u64()? {
+                0 => Some(false.into()),
+                1 => Some(true.into()),
+                _ => None,
+            },
+            (_, Some(text)) => match text.to_lowercase().as_str() {
+                "true" | "t" | "yes" | "y" | "on" | "1" => Some(true.into()),
+                "false" | "f" | "no" | "n" | "off" | "0" => Some(false.into()),
+                _ => None,
+            },
+            _ => None,
+        },
+        ParamType::Timestamptz => match (value, text) {
+            // Unix timestamp in seconds
+            (JsonValue::Number(n), _) => {
+                let time = chrono::DateTime::<chrono::Utc>::from_timestamp(n.as_i64()?, 0)?;
+                Some(time.to_rfc3339().into())
+            }
+            (_, Some(text)) => {
+                let time = chrono::DateTime::parse_from_rfc3339(text).ok()?;
+                Some(time.to_rfc3339().into())
+            }
+            _ => None,
+        },
+    }
+}
+
+/// Apply `types` to `params`, errors of all arguments are reported together.
+fn coerce_params(
+    params: &mut JsonValue,
+    types: &HashMap<String, ParamType>,
+) -> Result<(), CommandError> {
+    let params = match params {
+        JsonValue::Object(params) => params,
+        JsonValue::Null => return Ok(()),
+        _ => bail!("`params` must be an object"),
+    };
+    let mut errors = Vec::new();
+    for (name, value) in params.iter_mut() {
+        let Some(ty) = types.get(name) else {
+            continue;
+        };
+        match coerce(value, *ty) {
+            Some(coerced) => *value = coerced,
+            None => {
+                errors.push(format!(
+                    "argument `{}`: can't convert {} to {}",
+                    name,
+                    value,
+                    ty.as_str()
+                ));
+            }
+        }
+    }
+    if !errors.is_empty() {
+        bail!("{}", errors.join("; "));
+    }
+    Ok(())
+}
+
+/// Serialize arguments of a `GET` call into query string pairs.
+fn query_params(params: JsonValue) -> Result<Vec<(String, String)>, CommandError> {
+    let params = match params {
+        JsonValue::Object(params) => params,
+        JsonValue::Null => return Ok(Vec::new()),
+        _ => bail!("`params` must be an object"),
+    };
+    params
+        .into_iter()
+        .map(|(name, value)| {
+            let value = match Value::from(value) {
+                Value::Map(_) => Err(anyhow!(
+                    "argument `{}` is an object, it can't be sent with GET, use POST instead",
+                    name
+                )),
+                Value::Array(values)
+                    if values
+                        .iter()
+                        .any(|v| matches!(v, Value::Array(_) | Value::Map(_))) =>
+                {
+                    Err(anyhow!(
+                        "argument `{}` is a nested array, it can't be sent with GET, use POST instead",
+                        name
+                    ))
+                }
+                value @ Value::Array(_) => collection_value(&value),
+                value => filter_value(&value),
+            }?;
+            Ok((name, value))
+        })
+        .collect()
+}
+
+/// Build the query, schema is selected with `Content-Profile` for POST and
+/// `Accept-Profile` for GET.
+fn rpc_query(
+    url: String,
+    mut input: Input,
+    http: reqwest::Client,
+) -> Result<postgrest::Query, CommandError> {
+    if let Some(expected) = &input.expected_params {
+        check_params(&input.params, expected)?;
+    }
+    coerce_params(&mut input.params, &input.param_types)?;
     let url = format!("{}/rpc/{}", url, input.function);
-    let query = postgrest::Builder::new(url, input.schema, <_>::default(), ctx.http)
-        .rpc(serde_json::to_string(&input.params)?)
-        .into();
+    let schema = input.schema.filter(|s| !s.is_empty());
+    let builder = postgrest::Builder::new(url, schema, <_>::default(), http);
+    let mut query: postgrest::Query = match input.method {
+        RpcMethod::Post => builder.rpc(serde_json::to_string(&input.params)?).into(),
+        RpcMethod::Get => {
+            let mut query: postgrest::Query = builder.into();
+            query.queries.extend(query_params(input.params)?);
+            query
+        }
+    };
+    if let Some(returns) = input.returns {
+        set_header(&mut query, marker::RPC_RETURNS, returns.as_str().to_owned());
+    }
+    Ok(query)
+}
 
+async fn run(ctx: Context, mut input: Input) -> Result<Output, CommandError> {
+    let endpoints_override = input.endpoints_override.take();
+    let endpoints = query_endpoints(&ctx.endpoints, endpoints_override.as_ref());
+    let url = input
+        .url
+        .take()
+        .unwrap_or_else(|| format!("{}/rest/v1", endpoints.supabase));
+    let mut query = rpc_query(url, input, ctx.http)?;
+    set_apikey(&mut query, &endpoints, endpoints_override.as_ref());
     Ok(Output { query })
 }
 
@@ -45,4 +281,115 @@ mod tests {
     fn test_build() {
         build().unwrap();
     }
+
+    #[test]
+    fn test_schema() {
+        let request = |method: &str| {
+            let input: Input = serde_json::from_value(serde_json::json!({
+                "schema": "api",
+                "function": "get_user",
+                "params": { "id": 1 },
+                "method": method,
+            }))
+            .unwrap();
+            let query = rpc_query(
+                "https://base.spaceoperator.com/rest/v1".to_owned(),
+                input,
+                reqwest::Client::new(),
+            )
+            .unwrap();
+            postgrest::Builder::from_query(query, reqwest::Client::new())
+                .build()
+                .build()
+                .unwrap()
+        };
+
+        let post = request("POST");
+        assert_eq!(post.method(), reqwest::Method::POST);
+        assert_eq!(
+            post.url().as_str(),
+            "https://base.spaceoperator.com/rest/v1/rpc/get_user"
+        );
+        assert_eq!(post.headers()["Content-Profile"], "api");
+
+        let get = request("GET");
+        assert_eq!(get.method(), reqwest::Method::GET);
+        assert_eq!(
+            get.url().as_str(),
+            "https://base.spaceoperator.com/rest/v1/rpc/get_user?id=1"
+        );
+        assert_eq!(get.headers()["Accept-Profile"], "api");
+    }
+
+    #[test]
+    fn test_check_params() {
+        let expected = ["user_id".to_owned(), "limit".to_owned()];
+        check_params(&serde_json::json!({ "user_id": 1, "limit": 10 }), &expected).unwrap();
+        let error =
+            check_params(&serde_json::json!({ "userid": 1, "limit": 10 }), &expected).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "missing parameters: user_id; unexpected parameters: userid"
+        );
+    }
+
+    #[test]
+    fn test_coerce_params() {
+        let types = [
+            ("id", ParamType::Int),
+            ("amount", ParamType::Numeric),
+            ("active", ParamType::Bool),
+            ("since", ParamType::Timestamptz),
+        ]
+        .map(|(name, ty)| (name.to_owned(), ty))
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+        let mut params = serde_json::json!({
+            "id": " 42",
+            "amount": "1.50",
+            "active": "t",
+            "since": "2024-01-01T00:00:00Z",
+            "name": "10",
+        });
+        coerce_params(&mut params, &types).unwrap();
+        assert_eq!(
+            params,
+            serde_json::json!({
+                "id": 42,
+                "amount": "1.50",
+                "active": true,
+                "since": "2024-01-01T00:00:00+00:00",
+                "name": "10",
+            })
+        );
+
+        let mut params = serde_json::json!({ "id": "4.2", "active": "maybe", "amount": null });
+        let error = coerce_params(&mut params, &types).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "argument `id`: can't convert \"4.2\" to int; \
+            argument `active`: can't convert \"maybe\" to bool"
+        );
+    }
+
+    #[test]
+    fn test_query_params() {
+        let params = query_params(serde_json::json!({
+            "name": "hello world",
+            "limit": 10,
+            "tags": ["a", "b c"],
+        }))
+        .unwrap();
+        assert_eq!(
+            params,
+            [
+                ("name".to_owned(), "hello world".to_owned()),
+                ("limit".to_owned(), "10".to_owned()),
+                ("tags".to_owned(), "{a,\"b c\"}".to_owned()),
+            ]
+        );
+
+        let error = query_params(serde_json::json!({ "filter": { "a": 1 } })).unwrap_err();
+        assert!(error.to_string().contains("`filter` is an object"));
+    }
 }
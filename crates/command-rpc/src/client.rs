@@ -119,6 +119,10 @@ impl ContextProxy {
             command,
             signer,
             get_jwt: _,
+            jwt_cache: _,
+            mint_cache: _,
+            execute_limit: _,
+            priority_fee: _,
         }: Context,
     ) -> Result<Self, CommandError> {
         let server = extensions
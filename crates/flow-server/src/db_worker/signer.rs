@@ -48,11 +48,16 @@ impl actix::Handler<SignatureRequest> for SignerWorker {
     fn handle(&mut self, msg: SignatureRequest, _: &mut Self::Context) -> Self::Result {
         match self.signers.get(&msg.pubkey) {
             None => ready(Err(signer::Error::Pubkey(msg.pubkey.to_string()))).boxed(),
-            Some(SignerType::Keypair(keypair)) => ready(Ok(signer::SignatureResponse {
-                signature: keypair.sign_message(&msg.message),
-                new_message: None,
-            }))
-            .boxed(),
+            Some(SignerType::Keypair(keypair)) => {
+                let result = match msg.kind.signed_bytes(&msg.message) {
+                    Ok(bytes) => Ok(signer::SignatureResponse {
+                        signature: keypair.sign_message(&bytes),
+                        new_message: None,
+                    }),
+                    Err(error) => Err(signer::Error::Other(error.into())),
+                };
+                ready(result).boxed()
+            }
             Some(SignerType::UserWallet { sender, .. }) => {
                 let fut = sender.send(msg);
                 async move { fut.await? }.boxed()
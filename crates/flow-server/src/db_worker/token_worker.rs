@@ -10,9 +10,12 @@ use flow_lib::{config::Endpoints, context::get_jwt, UserId};
 use futures_channel::oneshot;
 use futures_util::{future::BoxFuture, FutureExt};
 use hashbrown::HashMap;
-use reqwest::{header::HeaderName, StatusCode};
+use reqwest::{
+    header::{HeaderName, RETRY_AFTER},
+    StatusCode,
+};
 use serde::{Deserialize, Serialize};
-use std::future::ready;
+use std::{future::ready, sync::Mutex, time::Instant};
 use utils::{actix_service::ActixService, address_book::ManagableActor};
 
 pub trait ClaimToken: Unpin + 'static {
@@ -38,6 +41,8 @@ impl LoginWithAdminCred {
             .await
             .map_err(get_jwt::Error::other)?;
 
+        check_rate_limit()?;
+
         let resp = self
             .client
             .post(format!(
@@ -184,9 +189,48 @@ impl Actor for TokenWorker {
 struct GoTrueError {
     error: String,
     error_description: String,
+    #[serde(default)]
+    error_code: Option<String>,
+}
+
+/// Supabase rate limits the token endpoint of the whole project, workers don't send
+/// requests until this instant so that they don't extend the limit.
+static RATE_LIMITED_UNTIL: Mutex<Option<Instant>> = Mutex::new(None);
+
+fn check_rate_limit() -> Result<(), get_jwt::Error> {
+    let mut until = RATE_LIMITED_UNTIL.lock().unwrap();
+    match *until {
+        Some(time) if time > Instant::now() => Err(get_jwt::Error::RateLimited {
+            retry_after: Some(time - Instant::now()),
+        }),
+        Some(_) => {
+            *until = None;
+            Ok(())
+        }
+        None => Ok(()),
+    }
+}
+
+fn rate_limited(resp: &reqwest::Response) -> get_jwt::Error {
+    let retry_after = resp
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .map(std::time::Duration::from_secs);
+    let delay = retry_after.unwrap_or(get_jwt::DEFAULT_RETRY_AFTER);
+    let mut until = RATE_LIMITED_UNTIL.lock().unwrap();
+    let time = Instant::now() + delay;
+    if until.map_or(true, |until| until < time) {
+        *until = Some(time);
+    }
+    get_jwt::Error::RateLimited { retry_after }
 }
 
 async fn supabase_error(resp: reqwest::Response) -> get_jwt::Error {
+    if resp.status() == StatusCode::TOO_MANY_REQUESTS {
+        return rate_limited(&resp);
+    }
     let bytes = match resp.bytes().await {
         Ok(bytes) => bytes,
         Err(error) => return get_jwt::Error::other(error),
@@ -195,10 +239,8 @@ async fn supabase_error(resp: reqwest::Response) -> get_jwt::Error {
         Ok(GoTrueError {
             error,
             error_description,
-        }) => get_jwt::Error::Supabase {
-            error,
-            error_description,
-        },
+            error_code,
+        }) => get_jwt::Error::supabase(error, error_description, error_code.as_deref()),
         Err(_) => get_jwt::Error::other(String::from_utf8_lossy(&bytes)),
     }
 }
@@ -227,6 +269,7 @@ async fn refresh(refresh_token: String, endpoints: Endpoints) -> Result<Jwt, get
         refresh_token: String,
     }
 
+    check_rate_limit()?;
     let resp = reqwest::Client::new()
         .post(format!(
             "{}/auth/v1/token?grant_type=refresh_token",
@@ -251,18 +294,20 @@ async fn refresh(refresh_token: String, endpoints: Endpoints) -> Result<Jwt, get
 }
 
 impl TokenState {
+    /// `previous` is the token being refreshed, it is kept if the refresh was rate limited.
     fn process_result(
         &mut self,
         res: Result<Jwt, get_jwt::Error>,
         local: &LocalStorage,
         user_id: &UserId,
+        previous: Option<Jwt>,
     ) {
         *self = match std::mem::replace(self, TokenState::None) {
             TokenState::None => unreachable!(),
             TokenState::Available(_) => unreachable!(),
             TokenState::Fetching(vec) => {
-                let (result, state) = match res {
-                    Ok(jwt) => {
+                let (result, state) = match (res, previous) {
+                    (Ok(jwt), _) => {
                         local
                             .set_jwt(user_id, &jwt)
                             .map_err(|e| tracing::error!("{}", e))
@@ -274,7 +319,10 @@ impl TokenState {
                             TokenState::Available(jwt),
                         )
                     }
-                    Err(error) => {
+                    (Err(error @ get_jwt::Error::RateLimited { .. }), Some(jwt)) => {
+                        (Err(error), TokenState::Available(jwt))
+                    }
+                    (Err(error), _) => {
                         local
                             .remove_jwt(user_id)
                             .map_err(|e| tracing::error!("{}", e))
@@ -295,55 +343,58 @@ impl actix::Handler<get_jwt::Request> for TokenWorker {
     type Result = ResponseFuture<<get_jwt::Request as actix::Message>::Result>;
     fn handle(&mut self, msg: get_jwt::Request, ctx: &mut Self::Context) -> Self::Result {
         if self.user_id != msg.user_id {
-            return Box::pin(ready(Err(get_jwt::Error::WrongRecipient)));
+            return Box::pin(ready(Err(get_jwt::Error::WrongRecipient {
+                user_id: msg.user_id,
+            })));
         }
 
         let result: Self::Result;
         let from_rx = |rx: oneshot::Receiver<_>| {
             Box::pin(async move { rx.await.map_err(|_| get_jwt::Error::other("canceled"))? })
         };
-        self.state =
-            match std::mem::replace(&mut self.state, TokenState::None) {
-                TokenState::None => {
-                    tracing::info!("claim new JWT token, user_id={}", self.user_id);
-                    let task = self.claim.claim().into_actor(&*self).map(|res, act, _| {
-                        act.state.process_result(res, &act.local_db, &act.user_id)
-                    });
+        self.state = match std::mem::replace(&mut self.state, TokenState::None) {
+            TokenState::None => {
+                tracing::info!("claim new JWT token, user_id={}", self.user_id);
+                let task = self.claim.claim().into_actor(&*self).map(|res, act, _| {
+                    act.state
+                        .process_result(res, &act.local_db, &act.user_id, None)
+                });
+                ctx.spawn(task);
+
+                let (tx, rx) = oneshot::channel();
+                result = from_rx(rx);
+                TokenState::Fetching([tx].into())
+            }
+            TokenState::Available(jwt) => {
+                if jwt.expires_at - Utc::now() < Duration::try_minutes(5).unwrap() {
+                    let refresh_token = jwt.refresh_token.clone();
+                    let endpoints = self.endpoints.clone();
+                    tracing::info!("refresh JWT token, user_id={}", self.user_id);
+                    let task = refresh(refresh_token, endpoints).into_actor(&*self).map(
+                        move |res, act, _| {
+                            act.state
+                                .process_result(res, &act.local_db, &act.user_id, Some(jwt))
+                        },
+                    );
                     ctx.spawn(task);
 
                     let (tx, rx) = oneshot::channel();
                     result = from_rx(rx);
-                    TokenState::Fetching([tx].into())
+                    TokenState::Fetching(vec![tx])
+                } else {
+                    result = Box::pin(ready(Ok(get_jwt::Response {
+                        access_token: jwt.access_token.clone(),
+                    })));
+                    TokenState::Available(jwt)
                 }
-                TokenState::Available(jwt) => {
-                    if jwt.expires_at - Utc::now() < Duration::try_minutes(5).unwrap() {
-                        let refresh_token = jwt.refresh_token;
-                        let endpoints = self.endpoints.clone();
-                        tracing::info!("refresh JWT token, user_id={}", self.user_id);
-                        let task = refresh(refresh_token, endpoints).into_actor(&*self).map(
-                            |res, act, _| {
-                                act.state.process_result(res, &act.local_db, &act.user_id)
-                            },
-                        );
-                        ctx.spawn(task);
-
-                        let (tx, rx) = oneshot::channel();
-                        result = from_rx(rx);
-                        TokenState::Fetching(vec![tx])
-                    } else {
-                        result = Box::pin(ready(Ok(get_jwt::Response {
-                            access_token: jwt.access_token.clone(),
-                        })));
-                        TokenState::Available(jwt)
-                    }
-                }
-                TokenState::Fetching(mut vec) => {
-                    let (tx, rx) = oneshot::channel();
-                    vec.push(tx);
-                    result = from_rx(rx);
-                    TokenState::Fetching(vec)
-                }
-            };
+            }
+            TokenState::Fetching(mut vec) => {
+                let (tx, rx) = oneshot::channel();
+                vec.push(tx);
+                result = from_rx(rx);
+                TokenState::Fetching(vec)
+            }
+        };
 
         result
     }
@@ -432,6 +483,7 @@ mod tests {
                 flow_server: String::new(),
                 supabase: "https://base.spaceoperator.com".to_owned(),
                 supabase_anon_key: std::env::var("ANON_KEY").unwrap(),
+                supabase_regions: Vec::new(),
             },
         )
         .await
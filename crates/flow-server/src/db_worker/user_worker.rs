@@ -27,7 +27,10 @@ use flow_lib::{
     FlowId, FlowRunId, User, UserId,
 };
 use futures_channel::{mpsc, oneshot};
-use futures_util::{future::BoxFuture, TryFutureExt};
+use futures_util::{
+    future::{select, BoxFuture, Either},
+    TryFutureExt,
+};
 use hashbrown::HashMap;
 use solana_sdk::signature::Signature;
 use std::future::ready;
@@ -161,11 +164,31 @@ impl UserWorker {
                             }),
                     );
                 }
-                ctx.run_later(timeout, move |act, _| {
-                    if let Some(SigReq { resp, .. }) = act.sigreg.remove(&id) {
-                        resp.send(Err(signer::Error::Timeout)).ok();
+                let cancel = req.cancel.clone();
+                ctx.spawn(
+                    async move {
+                        let sleep = std::pin::pin!(tokio::time::sleep(timeout));
+                        match cancel {
+                            Some(token) => {
+                                match select(sleep, std::pin::pin!(token.cancelled())).await {
+                                    Either::Left(_) => signer::Error::Timeout,
+                                    Either::Right(_) => signer::Error::Canceled,
+                                }
+                            }
+                            None => {
+                                sleep.await;
+                                signer::Error::Timeout
+                            }
+                        }
                     }
-                });
+                    .into_actor(&*self)
+                    .map(move |error, act, _| {
+                        // already removed if the signature was submitted
+                        if let Some(SigReq { resp, .. }) = act.sigreg.remove(&id) {
+                            resp.send(Err(error)).ok();
+                        }
+                    }),
+                );
                 Box::pin(async move {
                     rx.await
                         .map_err(|_| signer::Error::Other("tx dropped".into()))?
@@ -413,12 +436,13 @@ impl actix::Handler<SubmitSignature> for UserWorker {
             if new == req.req.message {
                 msg.new_msg = None;
             } else {
-                if !req
-                    .req
-                    .signatures
-                    .as_ref()
-                    .map(|s| s.is_empty())
-                    .unwrap_or(true)
+                if req.req.kind != signer::MessageKind::Transaction
+                    || !req
+                        .req
+                        .signatures
+                        .as_ref()
+                        .map(|s| s.is_empty())
+                        .unwrap_or(true)
                 {
                     return Box::pin(ready(Err(SubmitError::NotAllowChangeTx)));
                 }
@@ -430,7 +454,11 @@ impl actix::Handler<SubmitSignature> for UserWorker {
                 message = new;
             }
         }
-        if !Signature::from(msg.signature).verify(&req.req.pubkey.to_bytes(), &message) {
+        if !req
+            .req
+            .kind
+            .verify(&req.req.pubkey, &message, &Signature::from(msg.signature))
+        {
             self.sigreg.insert(msg.id, req);
             return Box::pin(ready(Err(SubmitError::WrongSignature)));
         }
@@ -523,10 +551,15 @@ impl ResponseError for StartError {
                 | new_flow_run::Error::Other(_) => StatusCode::UNAUTHORIZED,
             },
             StartError::Jwt(e) => match e {
-                get_jwt::Error::NotAllowed | get_jwt::Error::UserNotFound => {
-                    StatusCode::UNAUTHORIZED
-                }
-                get_jwt::Error::WrongRecipient
+                get_jwt::Error::NotAllowed
+                | get_jwt::Error::UserNotFound
+                | get_jwt::Error::RefreshTokenNotFound
+                | get_jwt::Error::RefreshTokenAlreadyUsed
+                | get_jwt::Error::InvalidGrant { .. } => StatusCode::UNAUTHORIZED,
+                get_jwt::Error::UserBanned => StatusCode::FORBIDDEN,
+                get_jwt::Error::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+                get_jwt::Error::WrongRecipient { .. }
+                | get_jwt::Error::NotConfigured
                 | get_jwt::Error::Worker(_)
                 | get_jwt::Error::MailBox(_)
                 | get_jwt::Error::Supabase { .. }
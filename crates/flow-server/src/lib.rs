@@ -105,6 +105,12 @@ pub struct SupabaseConfig {
     pub wasm_bucket: String,
     #[serde(default = "SupabaseConfig::default_open_whitelists")]
     pub open_whitelists: bool,
+    /// Endpoints of the same project in other regions, see [`Endpoints::supabase_regions`].
+    #[serde(default)]
+    pub regions: Vec<Url>,
+    /// How often to measure the latency of `regions`, in seconds.
+    #[serde(default = "SupabaseConfig::default_regions_refresh_secs")]
+    pub regions_refresh_secs: u64,
 }
 
 impl SupabaseConfig {
@@ -116,6 +122,10 @@ impl SupabaseConfig {
         false
     }
 
+    pub fn default_regions_refresh_secs() -> u64 {
+        300
+    }
+
     pub fn get_endpoint(&self) -> Url {
         self.endpoint.url.clone()
     }
@@ -130,6 +140,8 @@ impl Default for SupabaseConfig {
             service_key: None,
             wasm_bucket: Self::default_bucket(),
             open_whitelists: Self::default_open_whitelists(),
+            regions: Vec::new(),
+            regions_refresh_secs: Self::default_regions_refresh_secs(),
         }
     }
 }
@@ -296,6 +308,12 @@ impl Config {
             },
             supabase: self.supabase_endpoint(),
             supabase_anon_key: self.supabase.anon_key.clone(),
+            supabase_regions: self
+                .supabase
+                .regions
+                .iter()
+                .map(|url| url.as_str().trim_end_matches('/').to_owned())
+                .collect(),
         }
     }
 
@@ -45,6 +45,13 @@ async fn main() {
         }
     }
 
+    // contexts of flow runs use the fastest Supabase region
+    actix::spawn(flow_lib::context::refresh_supabase(
+        reqwest::Client::new(),
+        config.endpoints(),
+        Duration::from_secs(config.supabase.regions_refresh_secs.max(1)),
+    ));
+
     let fac = flow::context::CommandFactory::new();
     let natives = fac.natives.keys().collect::<Vec<_>>();
     tracing::info!("native commands: {:?}", natives);
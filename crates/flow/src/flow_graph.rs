@@ -12,8 +12,11 @@ use chrono::{DateTime, Utc};
 use flow_lib::{
     command::{CommandError, CommandTrait, InstructionInfo},
     config::client::{self, PartialConfig},
-    context::{execute, get_jwt, CommandContext, Context},
-    solana::{find_failed_instruction, ExecutionConfig, Instructions, KeypairExt, Pubkey},
+    context::{execute, get_jwt, signer, CommandContext, Context},
+    solana::{
+        find_failed_instruction, landed_at, ExecuteOptions, ExecutionConfig, Instructions,
+        KeypairExt, Pubkey,
+    },
     utils::{Extensions, TowerClient},
     CommandType, FlowConfig, FlowId, FlowRunId, Name, NodeId, ValueSet,
 };
@@ -160,6 +163,7 @@ pub struct PartialOutput {
     pub node_id: NodeId,
     pub times: u32,
     pub output: Result<(Option<Instructions>, ValueSet), CommandError>,
+    pub options: ExecuteOptions,
     pub resp: oneshot::Sender<Result<execute::Response, execute::Error>>,
 }
 
@@ -268,6 +272,7 @@ struct RunningNodeInfo {
 #[derive(Debug)]
 struct Waiting {
     instructions: Instructions,
+    options: ExecuteOptions,
     resp: oneshot::Sender<Result<execute::Response, execute::Error>>,
 }
 
@@ -373,9 +378,12 @@ impl FlowGraph {
         let signer = registry.signer.clone();
         let token = registry.token.clone();
         let rhai_permit = registry.rhai_permit.clone();
-        let tx_exec_config = ExecutionConfig::from_env(&c.ctx.environment)
-            .inspect_err(|error| tracing::error!("error parsing ExecutionConfig: {}", error))
-            .unwrap_or_default();
+        let tx_exec_config = ExecutionConfig {
+            ws_url: c.ctx.solana_client.websocket_url(),
+            ..ExecutionConfig::from_env(&c.ctx.environment)
+                .inspect_err(|error| tracing::error!("error parsing ExecutionConfig: {}", error))
+                .unwrap_or_default()
+        };
         let parent_flow_execute = registry.parent_flow_execute.clone();
         tracing::debug!("execution config: {:?}", tx_exec_config);
 
@@ -906,10 +914,18 @@ impl FlowGraph {
                     .extend(values);
 
                 if ins.is_none() {
-                    o.resp.send(Ok(execute::Response { signature: None })).ok();
+                    o.resp
+                        .send(Ok(execute::Response {
+                            signature: None,
+                            serialized_tx: None,
+                            slot: None,
+                            block_time: None,
+                        }))
+                        .ok();
                 } else if info.instruction_info.is_some() {
                     info.waiting = Some(Waiting {
                         instructions: ins.expect("ins.is_none() == false"),
+                        options: o.options,
                         resp: o.resp,
                     });
                 } else {
@@ -950,6 +966,7 @@ impl FlowGraph {
                         node_id: node.id,
                         times,
                         output: result.map(|v| (None, v)),
+                        options: <_>::default(),
                         resp,
                     },
                     s,
@@ -1048,8 +1065,9 @@ impl FlowGraph {
                         range: Range<usize>,
                     }
 
-                    let (mut ins, resp) = {
+                    let (mut ins, options, resp) = {
                         let mut ins = w.instructions;
+                        let mut options = w.options;
                         if let Some(signer) = self
                             .tx_exec_config
                             .overwrite_feepayer
@@ -1066,6 +1084,7 @@ impl FlowGraph {
                             let old_len = ins.instructions.len();
                             match ins.combine(w.instructions) {
                                 Ok(_) => {
+                                    options.merge(&w.options);
                                     let new_len = ins.instructions.len();
                                     resp.push(Responder {
                                         sender: w.resp,
@@ -1075,13 +1094,14 @@ impl FlowGraph {
                                 Err(ins) => {
                                     tx.push(Waiting {
                                         instructions: ins,
+                                        options: w.options,
                                         resp: w.resp,
                                     });
                                     break;
                                 }
                             }
                         }
-                        (ins, resp)
+                        (ins, options, resp)
                     };
                     if !self.fees.is_empty() {
                         ins.combine(Instructions {
@@ -1104,7 +1124,12 @@ impl FlowGraph {
                     } else if s.stop_shared.token.is_cancelled() {
                         Err(execute::Error::Canceled(s.stop_shared.get_reason()))
                     } else if ins.instructions.is_empty() {
-                        Ok(execute::Response { signature: None })
+                        Ok(execute::Response {
+                            signature: None,
+                            serialized_tx: None,
+                            slot: None,
+                            block_time: None,
+                        })
                     } else if let Some(exec) = &self.parent_flow_execute {
                         self.collect_flow_output(s).await;
                         s.stop
@@ -1113,6 +1138,9 @@ impl FlowGraph {
                                     std::pin::pin!(exec.call_ref(execute::Request {
                                         instructions: ins,
                                         output: s.result.output.clone(),
+                                        options,
+                                        dry_run: false,
+                                        transaction: None,
                                     })),
                                     execute::Error::Canceled,
                                 )),
@@ -1121,8 +1149,9 @@ impl FlowGraph {
                             .await
                     } else {
                         tracing::info!("executing instructions");
-                        let config = self.tx_exec_config.clone();
-                        s.stop
+                        let config = self.tx_exec_config.clone().with_options(&options);
+                        let res = s
+                            .stop
                             .race(
                                 std::pin::pin!(s.stop_shared.race(
                                     std::pin::pin!(ins.execute(
@@ -1135,17 +1164,28 @@ impl FlowGraph {
                                 )),
                                 execute::Error::Canceled,
                             )
-                            .await
-                            .map(|signature| execute::Response {
-                                signature: Some(signature),
-                            })
+                            .await;
+                        match res {
+                            Ok(signature) => {
+                                let (slot, block_time) =
+                                    landed_at(&self.ctx.solana_client, &signature).await;
+                                Ok(execute::Response {
+                                    signature: Some(signature),
+                                    serialized_tx: None,
+                                    slot,
+                                    block_time,
+                                })
+                            }
+                            Err(error) => Err(error),
+                        }
                     };
 
                     let failed_instruction = res.as_ref().err().and_then(|e| match e {
-                        execute::Error::Solana { error, inserted } => {
-                            find_failed_instruction(error)
-                                .and_then(|pos| pos.checked_sub(*inserted))
-                        }
+                        execute::Error::Solana {
+                            error, inserted, ..
+                        } => find_failed_instruction(error)
+                            .and_then(|pos| pos.checked_sub(*inserted)),
+                        execute::Error::TxSimFailed(Some(failure)) => failure.failed_instruction(),
                         _ => None,
                     });
                     for resp in resp {
@@ -1154,7 +1194,9 @@ impl FlowGraph {
                                 resp.sender.send(res.clone()).ok();
                             } else {
                                 debug_assert!(res.is_err());
-                                resp.sender.send(Err(execute::Error::TxSimFailed)).ok();
+                                resp.sender
+                                    .send(Err(execute::Error::TxSimFailed(None)))
+                                    .ok();
                             }
                         } else {
                             resp.sender.send(res.clone()).ok();
@@ -1336,6 +1378,8 @@ impl FlowGraph {
                 tracing::error!("could not insert to extensions, this is a bug");
             }
         }
+        // release pending signature requests when the run is stopped
+        self.ctx.signer = signer::with_cancel(self.ctx.signer.clone(), s.stop.token.clone());
 
         'LOOP: loop {
             tracing::trace!("new round");
@@ -1551,6 +1595,8 @@ struct ExecuteWithBundling {
     node_id: NodeId,
     times: u32,
     tx: mpsc::UnboundedSender<PartialOutput>,
+    /// Dry runs and prebuilt transactions are not bundled.
+    simple_svc: execute::Svc,
 }
 
 impl tower::Service<execute::Request> for ExecuteWithBundling {
@@ -1564,12 +1610,36 @@ impl tower::Service<execute::Request> for ExecuteWithBundling {
         std::task::Poll::Ready(Ok(()))
     }
     fn call(&mut self, req: execute::Request) -> Self::Future {
+        if req.dry_run || req.transaction.is_some() {
+            let svc = self.simple_svc.clone();
+            let tx = self.tx.clone();
+            let node_id = self.node_id;
+            let times = self.times;
+            return async move {
+                let output = req.output.clone();
+                let res = svc.call_ref(req).await?;
+                // the node's instructions are not executed
+                let (resp, rx) = oneshot::channel();
+                tx.unbounded_send(PartialOutput {
+                    node_id,
+                    times,
+                    output: Ok((None, output)),
+                    options: <_>::default(),
+                    resp,
+                })
+                .ok();
+                rx.await??;
+                Ok(res)
+            }
+            .boxed();
+        }
         let (tx, rx) = oneshot::channel();
         self.tx
             .unbounded_send(PartialOutput {
                 node_id: self.node_id,
                 times: self.times,
                 output: Ok((Some(req.instructions), req.output)),
+                options: req.options,
                 resp: tx,
             })
             .ok();
@@ -1611,7 +1681,12 @@ impl tower::Service<execute::Request> for ExecuteNoBundling {
         let overwrite_feepayer = self.overwrite_feepayer.as_ref().map(|k| k.clone_keypair());
         let task = async move {
             if let Some(signer) = overwrite_feepayer {
-                req.instructions.set_feepayer(signer);
+                match &mut req.transaction {
+                    // the fee payer of a compiled message can't be changed, but it can sign
+                    // if the transaction was built for it
+                    Some(prebuilt) => prebuilt.keypairs.push(signer),
+                    None => req.instructions.set_feepayer(signer),
+                }
             }
             let res = svc.ready().await?.call(req).await;
             let output = match &res {
@@ -1626,6 +1701,7 @@ impl tower::Service<execute::Request> for ExecuteNoBundling {
                     node_id,
                     times,
                     output: output.map(|(ins, output)| (Some(ins), output)),
+                    options: <_>::default(),
                     resp,
                 })
                 .ok();
@@ -1681,6 +1757,7 @@ async fn run_command(
                 node_id: node.id,
                 times,
                 tx: tx.clone(),
+                simple_svc: execute::simple(&ctx, 32, Some(flow_run_id), tx_exec_config.clone()),
             },
             execute::Error::worker,
             32,
@@ -1694,6 +1771,8 @@ async fn run_command(
     });
     if !node.command.permissions().user_tokens {
         ctx.get_jwt = get_jwt::not_allowed();
+        // don't serve tokens cached by other nodes
+        ctx.jwt_cache = get_jwt::Cache::default();
     }
 
     event_tx
@@ -1,6 +1,13 @@
 use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
-use std::{collections::HashMap, num::NonZeroU64, str::FromStr, sync::LazyLock};
+use solana_sdk::commitment_config::CommitmentLevel;
+use std::{
+    collections::HashMap,
+    num::{NonZeroU32, NonZeroU64, NonZeroUsize},
+    str::FromStr,
+    sync::{LazyLock, Mutex},
+    time::Duration,
+};
 use thiserror::Error as ThisError;
 use uuid::Uuid;
 
@@ -130,6 +137,11 @@ pub struct Endpoints {
     pub flow_server: String,
     pub supabase: String,
     pub supabase_anon_key: String,
+    /// Endpoints of the same Supabase project in other regions. Contexts use the fastest
+    /// of them as `supabase` once [`select_supabase`][crate::context::select_supabase]
+    /// has measured them.
+    #[serde(default)]
+    pub supabase_regions: Vec<String>,
 }
 
 impl Default for Endpoints {
@@ -138,45 +150,223 @@ impl Default for Endpoints {
             flow_server: "http://localhost:8080".to_owned(),
             supabase: "http://localhost:8081".to_owned(),
             supabase_anon_key: String::new(),
+            supabase_regions: Vec::new(),
+        }
+    }
+}
+
+impl Endpoints {
+    /// Replace fields that are set in `other`.
+    pub fn merge(&self, other: &EndpointsOverride) -> Endpoints {
+        Endpoints {
+            flow_server: other
+                .flow_server
+                .clone()
+                .unwrap_or_else(|| self.flow_server.clone()),
+            supabase: other
+                .supabase
+                .clone()
+                .unwrap_or_else(|| self.supabase.clone()),
+            supabase_anon_key: other
+                .supabase_anon_key
+                .clone()
+                .unwrap_or_else(|| self.supabase_anon_key.clone()),
+            // regions of our project don't serve an overridden one
+            supabase_regions: if other.supabase.is_some() {
+                Vec::new()
+            } else {
+                self.supabase_regions.clone()
+            },
+        }
+    }
+
+    /// `supabase` followed by `supabase_regions`, without duplicates.
+    pub fn supabase_candidates(&self) -> Vec<&str> {
+        let mut candidates = vec![self.supabase.as_str()];
+        for url in &self.supabase_regions {
+            if !candidates.contains(&url.as_str()) {
+                candidates.push(url);
+            }
         }
+        candidates
     }
 }
 
+/// Partial [`Endpoints`], used to target other services in a node.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EndpointsOverride {
+    #[serde(default)]
+    pub flow_server: Option<String>,
+    #[serde(default)]
+    pub supabase: Option<String>,
+    #[serde(default)]
+    pub supabase_anon_key: Option<String>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ContextConfig {
+    #[serde(default)]
     pub http_client: HttpClientConfig,
     pub solana_client: SolanaClientConfig,
     pub environment: HashMap<String, String>,
     pub endpoints: Endpoints,
+    /// Maximum number of transactions a flow run can execute at the same time, the rest
+    /// are queued. Unlimited if not set.
+    #[serde(default)]
+    pub execute_concurrency: Option<NonZeroUsize>,
+    #[serde(default)]
+    pub solana: SolanaConfig,
 }
 
 impl Default for ContextConfig {
     fn default() -> Self {
         ContextConfig {
-            http_client: HttpClientConfig {
-                timeout_in_secs: NonZeroU64::new(100).unwrap(),
-                gzip: true,
-            },
-            solana_client: SolanaClientConfig {
-                url: SolanaNet::Devnet.url(),
-                cluster: SolanaNet::Devnet,
-            },
+            http_client: HttpClientConfig::default(),
+            solana_client: SolanaClientConfig::default(),
             environment: <_>::default(),
             endpoints: <_>::default(),
+            execute_concurrency: None,
+            solana: SolanaConfig::default(),
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct HttpClientConfig {
+    /// Timeout of a whole request, from connecting until the response body is read.
     pub timeout_in_secs: NonZeroU64,
     pub gzip: bool,
+    #[serde(default)]
+    pub connect_timeout_in_secs: Option<NonZeroU64>,
+    /// Send all requests through this proxy, e.g. `http://proxy.local:3128`.
+    #[serde(default)]
+    pub proxy: Option<String>,
+    #[serde(default)]
+    pub user_agent: Option<String>,
+}
+
+impl Default for HttpClientConfig {
+    fn default() -> Self {
+        Self {
+            timeout_in_secs: NonZeroU64::new(100).unwrap(),
+            gzip: true,
+            connect_timeout_in_secs: None,
+            proxy: None,
+            user_agent: None,
+        }
+    }
+}
+
+impl HttpClientConfig {
+    pub fn build(&self) -> Result<reqwest::Client, reqwest::Error> {
+        let mut builder =
+            reqwest::Client::builder().timeout(Duration::from_secs(self.timeout_in_secs.get()));
+        if let Some(secs) = self.connect_timeout_in_secs {
+            builder = builder.connect_timeout(Duration::from_secs(secs.get()));
+        }
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+        }
+        if let Some(user_agent) = &self.user_agent {
+            builder = builder.user_agent(user_agent);
+        }
+        builder.build()
+    }
+
+    /// Get a client built from this config, clients are created once per config and shared
+    /// by the whole process.
+    ///
+    /// [`reqwest::Client`] clones share the same connection pool, so contexts created for
+    /// each flow run reuse connections to Supabase and other services instead of doing a
+    /// new TCP and TLS handshake for every run.
+    pub fn shared(&self) -> Result<reqwest::Client, reqwest::Error> {
+        static CLIENTS: LazyLock<Mutex<HashMap<HttpClientConfig, reqwest::Client>>> =
+            LazyLock::new(<_>::default);
+        let mut clients = CLIENTS.lock().unwrap();
+        if let Some(client) = clients.get(self) {
+            return Ok(client.clone());
+        }
+        let client = self.build()?;
+        clients.insert(self.clone(), client.clone());
+        Ok(client)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SolanaClientConfig {
     pub url: String,
     pub cluster: SolanaNet,
+    /// Endpoints to use when `url` returns errors or times out.
+    #[serde(default)]
+    pub fallback_urls: Vec<String>,
+    /// Spread requests over `url` and `fallback_urls` instead of only using fallbacks
+    /// after a failure.
+    #[serde(default)]
+    pub round_robin: bool,
+    /// Limit the rate of RPC requests, requests over the limit wait instead of failing.
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitConfig>,
+    /// WebSocket endpoint used to confirm transactions, derived from `url` if not set.
+    #[serde(default)]
+    pub ws_url: Option<String>,
+}
+
+/// Defaults of [`ExecutionConfig`][crate::solana::ExecutionConfig] used by
+/// [`execute::simple`][crate::context::execute::simple], settings from the flow's
+/// environment and [`ExecuteOptions`][crate::solana::ExecuteOptions] take precedence.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SolanaConfig {
+    /// Commitment of sent and confirmed transactions, default to `confirmed`.
+    #[serde(default)]
+    pub commitment: Option<CommitmentLevel>,
+    /// Stop waiting for confirmation after this long, default to until the blockhash
+    /// expires.
+    #[serde(default)]
+    pub confirm_timeout_in_secs: Option<NonZeroU64>,
+    /// `maxRetries` of `sendTransaction`, default to the RPC node's retry policy.
+    #[serde(default)]
+    pub send_max_retries: Option<usize>,
+}
+
+impl SolanaConfig {
+    pub fn confirm_timeout(&self) -> Option<Duration> {
+        self.confirm_timeout_in_secs
+            .map(|secs| Duration::from_secs(secs.get()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    pub requests_per_second: NonZeroU32,
+    /// Number of requests that can be sent at once, default to `requests_per_second`.
+    #[serde(default)]
+    pub burst: Option<NonZeroU32>,
+}
+
+impl SolanaClientConfig {
+    /// `url` followed by `fallback_urls`.
+    pub fn urls(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.url.as_str()).chain(self.fallback_urls.iter().map(String::as_str))
+    }
+
+    /// `ws_url`, or `url` with `ws://` or `wss://` scheme. The port is incremented like
+    /// the default ports of `solana-test-validator`, e.g. `8899` becomes `8900`.
+    pub fn websocket_url(&self) -> Option<String> {
+        if let Some(url) = &self.ws_url {
+            return Some(url.clone());
+        }
+        let mut url = reqwest::Url::parse(&self.url).ok()?;
+        let scheme = match url.scheme() {
+            "http" => "ws",
+            "https" => "wss",
+            _ => return None,
+        };
+        url.set_scheme(scheme).ok()?;
+        if let Some(port) = url.port() {
+            url.set_port(Some(port.checked_add(1)?)).ok()?;
+        }
+        Some(url.to_string())
+    }
 }
 
 impl From<Network> for SolanaClientConfig {
@@ -184,6 +374,10 @@ impl From<Network> for SolanaClientConfig {
         Self {
             url: value.url,
             cluster: value.cluster,
+            fallback_urls: Vec::new(),
+            round_robin: false,
+            rate_limit: None,
+            ws_url: None,
         }
     }
 }
@@ -194,6 +388,10 @@ impl Default for SolanaClientConfig {
         Self {
             url: cluster.url().to_owned(),
             cluster,
+            fallback_urls: Vec::new(),
+            round_robin: false,
+            rate_limit: None,
+            ws_url: None,
         }
     }
 }
@@ -321,16 +519,12 @@ impl FlowConfig {
         Self {
             id: config.id,
             ctx: ContextConfig {
-                http_client: HttpClientConfig {
-                    timeout_in_secs: NonZeroU64::new(100).unwrap(),
-                    gzip: true,
-                },
-                solana_client: SolanaClientConfig {
-                    url: config.sol_network.url,
-                    cluster: config.sol_network.cluster,
-                },
+                http_client: HttpClientConfig::default(),
+                solana_client: config.sol_network.into(),
                 environment: config.environment,
                 endpoints: <_>::default(),
+                execute_concurrency: None,
+                solana: SolanaConfig::default(),
             },
             nodes,
             edges,
@@ -7,23 +7,43 @@
 //! - [`get_jwt`]
 //! - [`execute`]
 //! - [`signer`]
+//! - [`priority_fee`]
 
 use crate::{
     config::{client::FlowRunOrigin, Endpoints},
-    solana::Instructions,
-    utils::Extensions,
+    solana::{
+        anchor::AnchorIdls, token::MintCache, ExecuteOptions, Instructions, SignatureStatus,
+        TokenBalance,
+    },
+    utils::{
+        metrics::{self, ErrorLabel},
+        Extensions,
+    },
     ContextConfig, FlowRunId, NodeId, UserId,
 };
 use bytes::Bytes;
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
-use solana_client::nonblocking::rpc_client::RpcClient as SolanaClient;
+use solana_client::{
+    client_error::ClientError, nonblocking::rpc_client::RpcClient as SolanaClient,
+};
 use solana_sdk::{
     commitment_config::{CommitmentConfig, CommitmentLevel},
     pubkey::Pubkey,
+    signature::{Keypair, Signature},
+    transaction::VersionedTransaction,
+};
+use std::{
+    any::Any,
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, LazyLock, Mutex},
+    time::{Duration, Instant},
 };
-use std::{any::Any, collections::HashMap, sync::Arc, time::Duration};
+use thiserror::Error as ThisError;
+use tokio::sync::Semaphore;
 use tower::{Service, ServiceExt};
+use tracing::Instrument;
 
 pub mod env {
     pub const RUST_LOG: &str = "RUST_LOG";
@@ -35,13 +55,97 @@ pub mod env {
     pub const TX_COMMITMENT_LEVEL: &str = "TX_COMMITMENT_LEVEL";
     pub const WAIT_COMMITMENT_LEVEL: &str = "WAIT_COMMITMENT_LEVEL";
     pub const EXECUTE_ON: &str = "EXECUTE_ON";
+
+    use solana_sdk::{pubkey::Pubkey, signature::Signature};
+    use std::time::Duration;
+    use thiserror::Error as ThisError;
+
+    #[derive(ThisError, Debug, Clone, PartialEq, Eq)]
+    pub enum Error {
+        #[error("environment variable `{}` is not set", .0)]
+        Missing(String),
+        #[error("invalid environment variable `{key}`: {error}")]
+        Invalid { key: String, error: String },
+    }
+
+    /// Types that can be parsed from an environment variable.
+    pub trait FromEnv: Sized {
+        fn from_env(s: &str) -> Result<Self, String>;
+    }
+
+    macro_rules! impl_from_str {
+        ($($t:ty),*) => {
+            $(
+                impl FromEnv for $t {
+                    fn from_env(s: &str) -> Result<Self, String> {
+                        s.trim().parse().map_err(|e| format!("{}", e))
+                    }
+                }
+            )*
+        };
+    }
+
+    impl_from_str!(u8, u16, u32, u64, usize, i32, i64, f64, Pubkey, Signature);
+
+    impl FromEnv for String {
+        fn from_env(s: &str) -> Result<Self, String> {
+            Ok(s.to_owned())
+        }
+    }
+
+    impl FromEnv for bool {
+        /// `true`, `1`, `yes`, or `false`, `0`, `no`, case-insensitive.
+        fn from_env(s: &str) -> Result<Self, String> {
+            match s.trim().to_ascii_lowercase().as_str() {
+                "true" | "1" | "yes" => Ok(true),
+                "false" | "0" | "no" => Ok(false),
+                _ => Err(format!("expected a boolean, got `{}`", s)),
+            }
+        }
+    }
+
+    impl FromEnv for Duration {
+        /// Seconds, or a number with `ms`, `s`, `m` or `h` suffix, e.g. `1.5`, `500ms`.
+        fn from_env(s: &str) -> Result<Self, String> {
+            let s = s.trim();
+            let (number, unit) = match s.find(|c: char| c.is_ascii_alphabetic()) {
+                Some(index) => s.split_at(index),
+                None => (s, "s"),
+            };
+            let number = number
+                .trim()
+                .parse::<f64>()
+                .map_err(|_| format!("expected a duration, got `{}`", s))?;
+            let secs = match unit.trim() {
+                "ms" => number / 1000.0,
+                "s" => number,
+                "m" => number * 60.0,
+                "h" => number * 3600.0,
+                unit => return Err(format!("unknown duration unit `{}`", unit)),
+            };
+            Duration::try_from_secs_f64(secs).map_err(|e| e.to_string())
+        }
+    }
 }
 
 /// Get user's JWT, require
 /// [`user_token`][crate::config::node::Permissions::user_tokens] permission.
 pub mod get_jwt {
-    use crate::{utils::TowerClient, BoxError, UserId};
-    use std::{future::Ready, sync::Arc};
+    use crate::{
+        utils::{Backoff, TowerClient},
+        BoxError, UserId,
+    };
+    use base64::prelude::*;
+    use futures::{
+        future::{BoxFuture, Shared},
+        FutureExt,
+    };
+    use serde::Deserialize;
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
     use thiserror::Error as ThisError;
 
     #[derive(Clone, Copy)]
@@ -58,14 +162,32 @@ pub mod get_jwt {
     pub enum Error {
         #[error("not allowed")]
         NotAllowed,
+        #[error("service not configured in this context")]
+        NotConfigured,
         #[error("user not found")]
         UserNotFound,
-        #[error("wrong recipient")]
-        WrongRecipient,
+        /// The service can't issue tokens for this user, e.g. it is not the flow owner.
+        #[error("can't get token of user {}, wrong recipient", user_id)]
+        WrongRecipient { user_id: UserId },
         #[error(transparent)]
         Worker(Arc<BoxError>),
         #[error(transparent)]
         MailBox(#[from] Arc<actix::MailboxError>),
+        /// The refresh token was revoked or never existed.
+        #[error("refresh token not found")]
+        RefreshTokenNotFound,
+        /// The refresh token was already exchanged for a new one, e.g. by a concurrent
+        /// refresh.
+        #[error("refresh token already used")]
+        RefreshTokenAlreadyUsed,
+        #[error("user is banned")]
+        UserBanned,
+        /// Other `invalid_grant` errors, e.g. wrong credentials.
+        #[error("invalid grant: {}", error_description)]
+        InvalidGrant { error_description: String },
+        /// Supabase Auth responded with `429 Too Many Requests`.
+        #[error("rate limited, retry after {:?}", retry_after)]
+        RateLimited { retry_after: Option<Duration> },
         #[error("{}: {}", error, error_description)]
         Supabase {
             error: String,
@@ -81,6 +203,26 @@ pub mod get_jwt {
         }
     }
 
+    impl crate::utils::metrics::ErrorLabel for Error {
+        fn label(&self) -> &'static str {
+            match self {
+                Error::NotAllowed => "not_allowed",
+                Error::NotConfigured => "not_configured",
+                Error::UserNotFound => "user_not_found",
+                Error::WrongRecipient { .. } => "wrong_recipient",
+                Error::Worker(_) => "worker",
+                Error::MailBox(_) => "mailbox",
+                Error::RefreshTokenNotFound => "refresh_token_not_found",
+                Error::RefreshTokenAlreadyUsed => "refresh_token_already_used",
+                Error::UserBanned => "user_banned",
+                Error::InvalidGrant { .. } => "invalid_grant",
+                Error::RateLimited { .. } => "rate_limited",
+                Error::Supabase { .. } => "supabase",
+                Error::Other(_) => "other",
+            }
+        }
+    }
+
     impl Error {
         pub fn worker(e: BoxError) -> Self {
             Error::Other(Arc::new(e))
@@ -89,6 +231,38 @@ pub mod get_jwt {
         pub fn other<E: Into<BoxError>>(e: E) -> Self {
             Error::Other(Arc::new(e.into()))
         }
+
+        /// Parse an error response of Supabase Auth. Newer versions set `error_code`,
+        /// older versions only have a description such as
+        /// `Invalid Refresh Token: Refresh Token Not Found`.
+        pub fn supabase(
+            error: String,
+            error_description: String,
+            error_code: Option<&str>,
+        ) -> Self {
+            match error_code {
+                Some("refresh_token_not_found") => return Error::RefreshTokenNotFound,
+                Some("refresh_token_already_used") => return Error::RefreshTokenAlreadyUsed,
+                Some("user_banned") => return Error::UserBanned,
+                _ => {}
+            }
+            if error != "invalid_grant" {
+                return Error::Supabase {
+                    error,
+                    error_description,
+                };
+            }
+            let description = error_description.to_lowercase();
+            if description.contains("refresh token not found") {
+                Error::RefreshTokenNotFound
+            } else if description.contains("already used") {
+                Error::RefreshTokenAlreadyUsed
+            } else if description.contains("banned") {
+                Error::UserBanned
+            } else {
+                Error::InvalidGrant { error_description }
+            }
+        }
     }
 
     impl actix::Message for Request {
@@ -98,32 +272,72 @@ pub mod get_jwt {
     pub type Svc = TowerClient<Request, Response, Error>;
 
     pub fn unimplemented_svc() -> Svc {
-        Svc::unimplemented(|| Error::other("unimplemented"), Error::worker)
+        Svc::unimplemented(|| Error::NotConfigured, Error::worker)
     }
 
     pub fn not_allowed() -> Svc {
         Svc::unimplemented(|| Error::NotAllowed, Error::worker)
     }
 
+    /// Delay of rate limited requests without `Retry-After`.
+    pub const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(1);
+
     #[derive(Clone, Copy, Debug)]
-    pub struct RetryPolicy(pub usize);
+    pub struct RetryPolicy {
+        /// Remaining retries of refresh token errors.
+        pub attempts: usize,
+        /// Time left for waiting on rate limits, requests are not retried if `Retry-After`
+        /// is longer.
+        pub timeout: Duration,
+        /// Delays of rate limited requests without `Retry-After`.
+        pub backoff: Backoff,
+        /// Number of rate limited retries so far.
+        pub rate_limited: u32,
+    }
 
     impl Default for RetryPolicy {
         fn default() -> Self {
-            Self(1)
+            Self {
+                attempts: 1,
+                timeout: Duration::from_secs(30),
+                backoff: Backoff::new(DEFAULT_RETRY_AFTER, Duration::from_secs(10), 5),
+                rate_limited: 0,
+            }
         }
     }
 
     impl tower::retry::Policy<Request, Response, Error> for RetryPolicy {
-        type Future = Ready<Self>;
+        type Future = BoxFuture<'static, Self>;
 
         fn retry(&self, _: &Request, result: Result<&Response, &Error>) -> Option<Self::Future> {
             match result {
-                Err(Error::Supabase {
-                    error_description, ..
-                }) if error_description.contains("Refresh Token") && self.0 > 0 => {
-                    tracing::error!("get_jwt error: {}, retrying", error_description);
-                    Some(std::future::ready(Self(self.0 - 1)))
+                // the stored token is dropped on error, the retry claims a new one
+                Err(error @ (Error::RefreshTokenNotFound | Error::RefreshTokenAlreadyUsed))
+                    if self.attempts > 0 =>
+                {
+                    tracing::error!("get_jwt error: {}, retrying", error);
+                    let next = Self {
+                        attempts: self.attempts - 1,
+                        ..*self
+                    };
+                    Some(Box::pin(std::future::ready(next)))
+                }
+                Err(Error::RateLimited { retry_after }) => {
+                    let delay = match retry_after {
+                        Some(delay) => *delay,
+                        None => self.backoff.delay(self.rate_limited)?,
+                    };
+                    let timeout = self.timeout.checked_sub(delay)?;
+                    tracing::warn!("get_jwt rate limited, retrying in {:?}", delay);
+                    let next = Self {
+                        timeout,
+                        rate_limited: self.rate_limited + 1,
+                        ..*self
+                    };
+                    Some(Box::pin(async move {
+                        tokio::time::sleep(delay).await;
+                        next
+                    }))
                 }
                 _ => None,
             }
@@ -133,17 +347,257 @@ pub mod get_jwt {
             Some(*req)
         }
     }
+
+    pub const DEFAULT_EXPIRY_SKEW: Duration = Duration::from_secs(60);
+
+    #[derive(Clone)]
+    struct CachedToken {
+        access_token: String,
+        /// Unix timestamp from the `exp` claim.
+        exp: i64,
+    }
+
+    type Fetch = Shared<BoxFuture<'static, Result<String, Error>>>;
+
+    /// Cache access tokens until they are about to expire.
+    ///
+    /// Clones share the same cache.
+    #[derive(Clone)]
+    pub struct Cache {
+        tokens: Arc<Mutex<HashMap<UserId, CachedToken>>>,
+        /// Requests being sent, concurrent fetches of the same user wait for them.
+        in_flight: Arc<Mutex<HashMap<UserId, Fetch>>>,
+        /// Refresh tokens expiring within this duration.
+        skew: Duration,
+    }
+
+    impl Default for Cache {
+        fn default() -> Self {
+            Self::new(DEFAULT_EXPIRY_SKEW)
+        }
+    }
+
+    impl Cache {
+        pub fn new(skew: Duration) -> Self {
+            Self {
+                tokens: <_>::default(),
+                in_flight: <_>::default(),
+                skew,
+            }
+        }
+
+        fn get_at(&self, user_id: &UserId, now: i64) -> Option<String> {
+            let tokens = self.tokens.lock().unwrap();
+            let token = tokens.get(user_id)?;
+            (token.exp - self.skew.as_secs() as i64 > now).then(|| token.access_token.clone())
+        }
+
+        pub fn get(&self, user_id: &UserId) -> Option<String> {
+            self.get_at(user_id, chrono::Utc::now().timestamp())
+        }
+
+        /// Store a token, tokens without a valid `exp` claim are not cached.
+        pub fn insert(&self, user_id: UserId, access_token: String) {
+            if let Some(exp) = expiry(&access_token) {
+                self.tokens
+                    .lock()
+                    .unwrap()
+                    .insert(user_id, CachedToken { access_token, exp });
+            }
+        }
+
+        pub fn remove(&self, user_id: &UserId) {
+            self.tokens.lock().unwrap().remove(user_id);
+        }
+
+        /// Get a cached token, or call the service and cache the result.
+        ///
+        /// Concurrent calls for the same user share one request and its result.
+        pub async fn get_or_fetch(&self, svc: &Svc, user_id: UserId) -> Result<String, Error> {
+            if let Some(token) = self.get(&user_id) {
+                return Ok(token);
+            }
+            let fetch = self
+                .in_flight
+                .lock()
+                .unwrap()
+                .entry(user_id)
+                .or_insert_with(|| self.fetch(svc.clone(), user_id))
+                .clone();
+            fetch.await
+        }
+
+        fn fetch(&self, svc: Svc, user_id: UserId) -> Fetch {
+            let cache = self.clone();
+            async move {
+                let result = svc
+                    .call_ref(Request { user_id })
+                    .await
+                    .map(|resp| resp.access_token);
+                if let Ok(access_token) = &result {
+                    cache.insert(user_id, access_token.clone());
+                }
+                cache.in_flight.lock().unwrap().remove(&user_id);
+                result
+            }
+            .boxed()
+            .shared()
+        }
+    }
+
+    /// Decode the `exp` claim of a JWT, the signature is not verified.
+    pub fn expiry(token: &str) -> Option<i64> {
+        #[derive(Deserialize)]
+        struct Claims {
+            exp: i64,
+        }
+        let payload = token.split('.').nth(1)?;
+        let payload = BASE64_URL_SAFE_NO_PAD
+            .decode(payload.trim_end_matches('='))
+            .ok()?;
+        Some(serde_json::from_slice::<Claims>(&payload).ok()?.exp)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn token(exp: i64) -> String {
+            let payload =
+                BASE64_URL_SAFE_NO_PAD.encode(format!(r#"{{"sub":"user","exp":{}}}"#, exp));
+            format!("eyJhbGciOiJIUzI1NiJ9.{}.signature", payload)
+        }
+
+        #[test]
+        fn test_cache() {
+            assert_eq!(expiry(&token(1000)), Some(1000));
+            assert_eq!(expiry("not a jwt"), None);
+
+            let cache = Cache::new(Duration::from_secs(60));
+            let user_id = UserId::new_v4();
+            cache.insert(user_id, token(1000));
+            assert_eq!(cache.get_at(&user_id, 900), Some(token(1000)));
+            assert_eq!(cache.get_at(&user_id, 950), None);
+
+            cache.insert(user_id, "not a jwt".to_owned());
+            assert_eq!(cache.get_at(&user_id, 900), Some(token(1000)));
+        }
+
+        #[tokio::test]
+        async fn test_single_flight() {
+            let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+            let svc = Svc::from_service(
+                tower::service_fn({
+                    let calls = calls.clone();
+                    move |_: Request| {
+                        calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                        async move {
+                            tokio::time::sleep(Duration::from_millis(100)).await;
+                            Ok(Response {
+                                access_token: token(i64::MAX),
+                            })
+                        }
+                    }
+                }),
+                Error::worker,
+                64,
+            );
+            let cache = Cache::default();
+            let user_id = UserId::new_v4();
+            let tasks = (0..50)
+                .map(|_| {
+                    let cache = cache.clone();
+                    let svc = svc.clone();
+                    tokio::spawn(async move { cache.get_or_fetch(&svc, user_id).await })
+                })
+                .collect::<Vec<_>>();
+            for task in tasks {
+                assert_eq!(task.await.unwrap().unwrap(), token(i64::MAX));
+            }
+            assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+            assert!(cache.in_flight.lock().unwrap().is_empty());
+        }
+
+        #[tokio::test]
+        async fn test_retry_policy() {
+            use tower::retry::Policy;
+
+            let req = Request {
+                user_id: UserId::new_v4(),
+            };
+            let policy = RetryPolicy {
+                attempts: 1,
+                timeout: Duration::from_millis(30),
+                ..RetryPolicy::default()
+            };
+            let limited = |ms| Error::RateLimited {
+                retry_after: Some(Duration::from_millis(ms)),
+            };
+            let next = policy.retry(&req, Err(&limited(20))).unwrap().await;
+            assert_eq!(next.timeout, Duration::from_millis(10));
+            assert_eq!(next.attempts, 1);
+            assert!(next.retry(&req, Err(&limited(20))).is_none());
+
+            let next = next
+                .retry(&req, Err(&Error::RefreshTokenAlreadyUsed))
+                .unwrap()
+                .await;
+            assert_eq!(next.attempts, 0);
+            assert!(next
+                .retry(&req, Err(&Error::RefreshTokenNotFound))
+                .is_none());
+        }
+
+        #[test]
+        fn test_supabase_error() {
+            let e = |error: &str, description: &str, code| {
+                Error::supabase(error.to_owned(), description.to_owned(), code)
+            };
+            assert!(matches!(
+                e(
+                    "invalid_grant",
+                    "Invalid Refresh Token: Refresh Token Not Found",
+                    None
+                ),
+                Error::RefreshTokenNotFound
+            ));
+            assert!(matches!(
+                e("invalid_grant", "Invalid Refresh Token: Already Used", None),
+                Error::RefreshTokenAlreadyUsed
+            ));
+            assert!(matches!(
+                e("invalid_grant", "User is banned", Some("user_banned")),
+                Error::UserBanned
+            ));
+            assert!(matches!(
+                e("invalid_grant", "Invalid login credentials", None),
+                Error::InvalidGrant { .. }
+            ));
+            assert!(matches!(
+                e("server_error", "Database error", None),
+                Error::Supabase { .. }
+            ));
+        }
+    }
 }
 
 /// Request Solana signature from external wallets.
 pub mod signer {
-    use crate::{utils::TowerClient, BoxError, FlowRunId};
+    use crate::{
+        utils::{Backoff, TowerClient},
+        BoxError, FlowRunId,
+    };
     use chrono::{DateTime, Utc};
+    use futures::future::BoxFuture;
     use serde::{Deserialize, Serialize};
     use serde_with::{base64::Base64, serde_as, DisplayFromStr, DurationSecondsWithFrac};
-    use solana_sdk::{pubkey::Pubkey, signature::Signature};
-    use std::time::Duration;
+    use solana_sdk::{
+        offchain_message::OffchainMessage, pubkey::Pubkey, sanitize::SanitizeError,
+        signature::Signature,
+    };
+    use std::{borrow::Cow, collections::BTreeMap, time::Duration};
     use thiserror::Error as ThisError;
+    use tokio_util::sync::CancellationToken;
 
     #[derive(ThisError, Debug)]
     pub enum Error {
@@ -153,6 +607,12 @@ pub mod signer {
         User,
         #[error("timeout")]
         Timeout,
+        #[error("canceled")]
+        Canceled,
+        #[error("not allowed to change transaction")]
+        MessageChanged,
+        #[error("service not configured in this context")]
+        NotConfigured,
         #[error(transparent)]
         Worker(BoxError),
         #[error(transparent)]
@@ -161,6 +621,22 @@ pub mod signer {
         Other(#[from] BoxError),
     }
 
+    impl crate::utils::metrics::ErrorLabel for Error {
+        fn label(&self) -> &'static str {
+            match self {
+                Error::Pubkey(_) => "pubkey",
+                Error::User => "user",
+                Error::Timeout => "timeout",
+                Error::Canceled => "canceled",
+                Error::MessageChanged => "message_changed",
+                Error::NotConfigured => "not_configured",
+                Error::Worker(_) => "worker",
+                Error::MailBox(_) => "mailbox",
+                Error::Other(_) => "other",
+            }
+        }
+    }
+
     pub type Svc = TowerClient<SignatureRequest, SignatureResponse, Error>;
 
     #[serde_as]
@@ -172,6 +648,37 @@ pub mod signer {
         pub signature: Signature,
     }
 
+    /// What [`SignatureRequest::message`] is, wallets show a different prompt for each.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum MessageKind {
+        /// A serialized transaction message, signed as is.
+        #[default]
+        Transaction,
+        /// An arbitrary message, e.g. an auth challenge. It is signed in the off-chain
+        /// message format of `solana sign-offchain-message`, so that it can't be a valid
+        /// transaction.
+        OffchainMessage,
+    }
+
+    impl MessageKind {
+        /// Bytes that are signed for `message`.
+        pub fn signed_bytes(self, message: &[u8]) -> Result<Cow<'_, [u8]>, SanitizeError> {
+            match self {
+                MessageKind::Transaction => Ok(Cow::Borrowed(message)),
+                MessageKind::OffchainMessage => {
+                    Ok(Cow::Owned(OffchainMessage::new(0, message)?.serialize()?))
+                }
+            }
+        }
+
+        /// Check that `signature` is a signature of `message` by `pubkey`.
+        pub fn verify(self, pubkey: &Pubkey, message: &[u8], signature: &Signature) -> bool {
+            self.signed_bytes(message)
+                .is_ok_and(|bytes| signature.verify(pubkey.as_ref(), &bytes))
+        }
+    }
+
     #[serde_as]
     #[derive(Debug, Clone, Serialize, Deserialize)]
     pub struct SignatureRequest {
@@ -182,10 +689,17 @@ pub mod signer {
         pub pubkey: Pubkey,
         #[serde_as(as = "Base64")]
         pub message: bytes::Bytes,
+        /// Signers can't change the message of an off-chain message request.
+        #[serde(default)]
+        pub kind: MessageKind,
         #[serde_as(as = "DurationSecondsWithFrac<f64>")]
         pub timeout: Duration,
         pub flow_run_id: Option<FlowRunId>,
         pub signatures: Option<Vec<Presigner>>,
+        /// Signers should drop the request and return [`Error::Canceled`] once this is
+        /// cancelled, see [`with_cancel`].
+        #[serde(skip)]
+        pub cancel: Option<CancellationToken>,
     }
 
     impl actix::Message for SignatureRequest {
@@ -202,14 +716,409 @@ pub mod signer {
     }
 
     pub fn unimplemented_svc() -> Svc {
-        Svc::unimplemented(|| BoxError::from("unimplemented").into(), Error::Worker)
+        Svc::unimplemented(|| Error::NotConfigured, Error::Worker)
+    }
+
+    /// Retry [`Error::MailBox`] errors, which happen when the signer is overloaded, with
+    /// exponential backoff. Other errors are returned immediately.
+    #[derive(Clone, Copy, Debug)]
+    pub struct RetryPolicy {
+        pub backoff: Backoff,
+        /// Number of retries so far.
+        pub attempt: u32,
+    }
+
+    impl Default for RetryPolicy {
+        fn default() -> Self {
+            Self {
+                backoff: Backoff::new(Duration::from_millis(100), Duration::from_secs(5), 3),
+                attempt: 0,
+            }
+        }
+    }
+
+    impl tower::retry::Policy<SignatureRequest, SignatureResponse, Error> for RetryPolicy {
+        type Future = BoxFuture<'static, Self>;
+
+        fn retry(
+            &self,
+            _: &SignatureRequest,
+            result: Result<&SignatureResponse, &Error>,
+        ) -> Option<Self::Future> {
+            match result {
+                Err(error @ Error::MailBox(_)) => {
+                    let delay = self.backoff.delay(self.attempt)?;
+                    tracing::warn!("signer error: {}, retrying in {:?}", error, delay);
+                    let next = Self {
+                        attempt: self.attempt + 1,
+                        ..*self
+                    };
+                    Some(Box::pin(async move {
+                        tokio::time::sleep(delay).await;
+                        next
+                    }))
+                }
+                _ => None,
+            }
+        }
+
+        fn clone_request(&self, req: &SignatureRequest) -> Option<SignatureRequest> {
+            Some(req.clone())
+        }
+    }
+
+    /// Attach `token` to requests that don't have a [`SignatureRequest::cancel`] token.
+    ///
+    /// Requests fail with [`Error::Canceled`] as soon as their token is cancelled, e.g.
+    /// when the flow run is stopped, instead of waiting for the full timeout.
+    pub fn with_cancel(svc: Svc, token: CancellationToken) -> Svc {
+        let handle = move |mut req: SignatureRequest| {
+            let token = req.cancel.get_or_insert_with(|| token.clone()).clone();
+            let svc = svc.clone();
+            async move {
+                let call = std::pin::pin!(svc.call_ref(req));
+                let cancelled = std::pin::pin!(token.cancelled());
+                match futures::future::select(call, cancelled).await {
+                    futures::future::Either::Left((result, _)) => result,
+                    futures::future::Either::Right(_) => Err(Error::Canceled),
+                }
+            }
+        };
+        Svc::from_service(tower::service_fn(handle), Error::Worker, 32)
+    }
+
+    /// Request signatures of multiple pubkeys for the same message.
+    #[derive(Debug, Clone)]
+    pub struct SignManyRequest {
+        pub pubkeys: Vec<Pubkey>,
+        pub message: bytes::Bytes,
+        pub timeout: Duration,
+        pub flow_run_id: Option<FlowRunId>,
+        /// Signatures that are already available, sent along with every request.
+        pub signatures: Option<Vec<Presigner>>,
+    }
+
+    #[derive(Debug, Default)]
+    pub struct SignManyResponse {
+        pub signatures: BTreeMap<Pubkey, Signature>,
+        /// Pubkeys that we couldn't get a signature of.
+        pub failures: Vec<(Pubkey, Error)>,
+    }
+
+    impl SignManyResponse {
+        pub fn is_complete(&self) -> bool {
+            self.failures.is_empty()
+        }
+    }
+
+    /// Send a [`SignatureRequest`] for each pubkey concurrently.
+    ///
+    /// A failed pubkey doesn't abort the others, it is reported in
+    /// [`SignManyResponse::failures`]. Signers are not allowed to change the message.
+    pub async fn sign_many(svc: &Svc, req: SignManyRequest) -> SignManyResponse {
+        let SignManyRequest {
+            pubkeys,
+            message,
+            timeout,
+            flow_run_id,
+            signatures,
+        } = req;
+        let results = futures::future::join_all(pubkeys.into_iter().map(|pubkey| {
+            let fut = svc.call_ref(SignatureRequest {
+                id: None,
+                time: Utc::now(),
+                pubkey,
+                message: message.clone(),
+                kind: MessageKind::Transaction,
+                timeout,
+                flow_run_id,
+                signatures: signatures.clone(),
+                cancel: None,
+            });
+            let message = &message;
+            async move {
+                let result = match tokio::time::timeout(timeout, fut).await {
+                    Ok(Ok(resp)) => match resp.new_message {
+                        Some(new) if new != *message => Err(Error::MessageChanged),
+                        _ => Ok(resp.signature),
+                    },
+                    Ok(Err(error)) => Err(error),
+                    Err(_) => Err(Error::Timeout),
+                };
+                (pubkey, result)
+            }
+        }))
+        .await;
+
+        let mut resp = SignManyResponse::default();
+        for (pubkey, result) in results {
+            match result {
+                Ok(signature) => {
+                    resp.signatures.insert(pubkey, signature);
+                }
+                Err(error) => resp.failures.push((pubkey, error)),
+            }
+        }
+        resp
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use solana_sdk::signer::{keypair::Keypair, Signer};
+
+        #[test]
+        fn test_message_kind() {
+            let keypair = Keypair::new();
+            let pubkey = keypair.pubkey();
+            let message = b"sign in to space operator";
+            let kind = MessageKind::OffchainMessage;
+            let bytes = kind.signed_bytes(message).unwrap();
+            assert!(bytes.starts_with(b"\xffsolana offchain"));
+            let signature = keypair.sign_message(&bytes);
+            assert!(kind.verify(&pubkey, message, &signature));
+            assert!(!MessageKind::Transaction.verify(&pubkey, message, &signature));
+            assert!(!kind.verify(&pubkey, message, &keypair.sign_message(message)));
+        }
+
+        #[tokio::test]
+        async fn test_sign_many_partial() {
+            let keypair = Keypair::new();
+            let pubkey = keypair.pubkey();
+            let other = Pubkey::new_unique();
+            let svc = Svc::from_service(
+                tower::service_fn(move |req: SignatureRequest| {
+                    let result = if req.pubkey == pubkey {
+                        Ok(SignatureResponse {
+                            signature: keypair.sign_message(&req.message),
+                            new_message: None,
+                        })
+                    } else {
+                        Err(Error::Pubkey(req.pubkey.to_string()))
+                    };
+                    async move { result }
+                }),
+                Error::Worker,
+                32,
+            );
+            let resp = sign_many(
+                &svc,
+                SignManyRequest {
+                    pubkeys: vec![pubkey, other],
+                    message: bytes::Bytes::from_static(b"message"),
+                    timeout: Duration::from_secs(10),
+                    flow_run_id: None,
+                    signatures: None,
+                },
+            )
+            .await;
+            assert!(!resp.is_complete());
+            assert!(resp.signatures[&pubkey].verify(pubkey.as_ref(), b"message"));
+            assert_eq!(resp.failures.len(), 1);
+            assert_eq!(resp.failures[0].0, other);
+        }
+
+        #[tokio::test]
+        async fn test_retry_policy() {
+            use std::sync::{
+                atomic::{AtomicUsize, Ordering},
+                Arc,
+            };
+            use tower::ServiceExt;
+
+            let calls = Arc::new(AtomicUsize::new(0));
+            let svc = {
+                let calls = calls.clone();
+                Svc::from_service(
+                    tower::service_fn(move |req: SignatureRequest| {
+                        let result = match calls.fetch_add(1, Ordering::SeqCst) {
+                            0 | 1 => Err(Error::MailBox(actix::MailboxError::Closed)),
+                            2 => Ok(SignatureResponse {
+                                signature: Signature::default(),
+                                new_message: None,
+                            }),
+                            _ => Err(Error::Pubkey(req.pubkey.to_string())),
+                        };
+                        async move { result }
+                    }),
+                    Error::Worker,
+                    32,
+                )
+            };
+            let policy = RetryPolicy {
+                backoff: Backoff::new(Duration::from_millis(1), Duration::from_millis(1), 3),
+                attempt: 0,
+            };
+            let req = SignatureRequest {
+                id: None,
+                time: Utc::now(),
+                pubkey: Pubkey::new_unique(),
+                message: bytes::Bytes::from_static(b"message"),
+                kind: MessageKind::Transaction,
+                timeout: Duration::from_secs(10),
+                flow_run_id: None,
+                signatures: None,
+                cancel: None,
+            };
+            tower::retry::Retry::new(policy, svc.clone())
+                .oneshot(req.clone())
+                .await
+                .unwrap();
+            assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+            // not retried
+            let result = tower::retry::Retry::new(policy, svc).oneshot(req).await;
+            assert!(matches!(result, Err(Error::Pubkey(_))));
+            assert_eq!(calls.load(Ordering::SeqCst), 4);
+        }
+
+        #[tokio::test]
+        async fn test_with_cancel() {
+            // never responds
+            let svc = Svc::from_service(
+                tower::service_fn(|_: SignatureRequest| std::future::pending()),
+                Error::Worker,
+                32,
+            );
+            let token = CancellationToken::new();
+            let svc = with_cancel(svc, token.clone());
+            let fut = svc.call_ref(SignatureRequest {
+                id: None,
+                time: Utc::now(),
+                pubkey: Pubkey::new_unique(),
+                message: bytes::Bytes::from_static(b"message"),
+                kind: MessageKind::Transaction,
+                timeout: Duration::from_secs(60),
+                flow_run_id: None,
+                signatures: None,
+                cancel: None,
+            });
+            token.cancel();
+            let result = tokio::time::timeout(Duration::from_secs(1), fut)
+                .await
+                .unwrap();
+            assert!(matches!(result, Err(Error::Canceled)));
+        }
+    }
+}
+
+/// Estimate priority fee from recently landed transactions.
+pub mod priority_fee {
+    use crate::{utils::TowerClient, BoxError};
+    use solana_client::{client_error::ClientError, nonblocking::rpc_client::RpcClient};
+    use solana_sdk::pubkey::Pubkey;
+    use std::sync::Arc;
+    use thiserror::Error as ThisError;
+
+    #[derive(Clone, Debug)]
+    pub struct Request {
+        /// Writable accounts of the transaction.
+        pub accounts: Vec<Pubkey>,
+        /// e.g. `75` to pay more than 75% of recent transactions.
+        pub percentile: u8,
+    }
+
+    #[derive(Clone, Copy, Debug)]
+    pub struct Response {
+        /// Compute unit price in micro-lamports.
+        pub micro_lamports: u64,
+    }
+
+    #[derive(ThisError, Debug, Clone)]
+    pub enum Error {
+        #[error("percentile must be between 0 and 100, got {}", .0)]
+        InvalidPercentile(u8),
+        #[error("service not configured in this context")]
+        NotConfigured,
+        #[error(transparent)]
+        Solana(Arc<ClientError>),
+        #[error(transparent)]
+        Worker(Arc<BoxError>),
+        #[error(transparent)]
+        Other(#[from] Arc<BoxError>),
+    }
+
+    impl Error {
+        pub fn worker(e: BoxError) -> Self {
+            Error::Worker(Arc::new(e))
+        }
+
+        pub fn other<E: Into<BoxError>>(e: E) -> Self {
+            Error::Other(Arc::new(e.into()))
+        }
+    }
+
+    pub type Svc = TowerClient<Request, Response, Error>;
+
+    pub fn unimplemented_svc() -> Svc {
+        Svc::unimplemented(|| Error::NotConfigured, Error::worker)
+    }
+
+    /// Value at `percentile` of `fees` using nearest-rank method, `0` if `fees` is empty.
+    pub fn percentile_of(mut fees: Vec<u64>, percentile: u8) -> u64 {
+        if fees.is_empty() {
+            return 0;
+        }
+        fees.sort_unstable();
+        let rank = (percentile as usize * fees.len()).div_ceil(100);
+        fees[rank.saturating_sub(1)]
+    }
+
+    /// Call `getRecentPrioritizationFees` and return the fee at `percentile`.
+    pub async fn estimate(
+        rpc: &RpcClient,
+        accounts: &[Pubkey],
+        percentile: u8,
+    ) -> Result<u64, Error> {
+        if percentile > 100 {
+            return Err(Error::InvalidPercentile(percentile));
+        }
+        let fees = rpc
+            .get_recent_prioritization_fees(accounts)
+            .await
+            .map_err(|error| Error::Solana(Arc::new(error)))?
+            .into_iter()
+            .map(|fee| fee.prioritization_fee)
+            .collect();
+        Ok(percentile_of(fees, percentile))
+    }
+
+    /// Service backed by `getRecentPrioritizationFees` RPC method.
+    pub fn rpc(rpc: Arc<RpcClient>, size: usize) -> Svc {
+        let handle = move |req: Request| {
+            let rpc = rpc.clone();
+            async move {
+                Ok(Response {
+                    micro_lamports: estimate(&rpc, &req.accounts, req.percentile).await?,
+                })
+            }
+        };
+        Svc::from_service(tower::service_fn(handle), Error::worker, size)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_percentile_of() {
+            let fees = vec![0, 0, 100, 5000, 20, 1, 0, 300, 0, 10];
+            assert_eq!(percentile_of(fees.clone(), 50), 1);
+            assert_eq!(percentile_of(fees.clone(), 75), 100);
+            assert_eq!(percentile_of(fees.clone(), 100), 5000);
+            assert_eq!(percentile_of(fees, 0), 0);
+            assert_eq!(percentile_of(Vec::new(), 75), 0);
+        }
     }
 }
 
 /// Output values and Solana instructions to be executed.
 pub mod execute {
     use crate::{
-        solana::{ExecutionConfig, Instructions},
+        solana::{
+            anchor::{failed_program, AnchorIdls, IdlError},
+            ExecuteOptions, ExecutionConfig, Instructions, SimulationFailure,
+        },
         utils::TowerClient,
         BoxError, FlowRunId,
     };
@@ -217,9 +1126,15 @@ pub mod execute {
     use serde::{Deserialize, Serialize};
     use serde_with::{base64::Base64, serde_as, DisplayFromStr};
     use solana_client::client_error::ClientError;
-    use solana_sdk::{signature::Signature, signer::SignerError};
+    use solana_sdk::{
+        pubkey::Pubkey,
+        signature::{Keypair, Signature},
+        signer::SignerError,
+        transaction::VersionedTransaction,
+    };
     use std::sync::Arc;
     use thiserror::Error as ThisError;
+    use tower::limit::ConcurrencyLimit;
 
     pub type Svc = TowerClient<Request, Response, Error>;
 
@@ -228,6 +1143,23 @@ pub mod execute {
     pub struct Request {
         pub instructions: Instructions,
         pub output: value::Map,
+        pub options: ExecuteOptions,
+        /// Build the transaction and return it in [`Response::serialized_tx`] without
+        /// signing or sending it.
+        pub dry_run: bool,
+        /// Send this transaction instead of building one from `instructions`.
+        pub transaction: Option<PrebuiltTransaction>,
+    }
+
+    /// A transaction built outside of the flow, e.g. by a DEX aggregator.
+    ///
+    /// Its message is already compiled, so it can't be bundled with other instructions
+    /// and settings that would change the message, such as priority fees, are ignored.
+    pub struct PrebuiltTransaction {
+        pub tx: VersionedTransaction,
+        /// Keypairs to sign with, other missing signatures are requested with the
+        /// signer service.
+        pub keypairs: Vec<Keypair>,
     }
 
     #[serde_as]
@@ -236,6 +1168,10 @@ pub mod execute {
         #[serde_as(as = "Base64")]
         instructions: Vec<u8>,
         output: value::Map,
+        #[serde(default)]
+        options: ExecuteOptions,
+        #[serde(default)]
+        dry_run: bool,
     }
 
     impl TryFrom<RequestRepr> for Request {
@@ -244,40 +1180,105 @@ pub mod execute {
             Ok(Self {
                 instructions: rmp_serde::from_slice(&value.instructions)?,
                 output: value.output,
+                options: value.options,
+                dry_run: value.dry_run,
+                transaction: None,
             })
         }
     }
 
+    /// How [`Context::execute_many`][super::Context::execute_many] handles failures.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub enum BatchMode {
+        /// Stop at the first failure, later transactions are not executed.
+        #[default]
+        Sequential,
+        /// Execute every transaction and collect all results.
+        BestEffort,
+    }
+
     #[serde_as]
-    #[derive(Serialize, Clone, Copy)]
+    #[derive(Serialize, Clone)]
     pub struct Response {
+        /// `None` if there are no instructions to execute, or if this is a dry run.
         #[serde_as(as = "Option<DisplayFromStr>")]
         pub signature: Option<Signature>,
+        /// Base64-encoded unsigned transaction of a dry run.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub serialized_tx: Option<String>,
+        /// Slot the transaction landed in.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub slot: Option<u64>,
+        /// Unix timestamp of the block, `None` if the RPC node doesn't have it yet.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub block_time: Option<i64>,
     }
 
     fn unwrap(s: &Option<String>) -> &str {
         s.as_ref().map(|v| v.as_str()).unwrap_or_default()
     }
 
+    fn sim_failure(f: &Option<Arc<SimulationFailure>>) -> String {
+        f.as_ref().map(|f| format!(": {}", f)).unwrap_or_default()
+    }
+
+    fn idl_msg(e: &IdlError) -> String {
+        e.msg
+            .as_ref()
+            .map(|m| format!(": {}", m))
+            .unwrap_or_default()
+    }
+
     #[derive(ThisError, Debug, Clone)]
     pub enum Error {
         #[error("canceled {}", unwrap(.0))]
         Canceled(Option<String>),
-        #[error("not available on this Context")]
+        /// The context has no [`CommandContext`][crate::context::CommandContext], or the
+        /// service is not configured.
+        #[error("service not configured in this context")]
         NotAvailable,
         #[error("some node failed to provide instructions")]
         TxIncomplete,
         #[error("time out")]
         Timeout,
-        #[error("insufficient solana balance, needed={needed}; have={balance};")]
+        #[error(
+            "insufficient solana balance, needed {} SOL; have {} SOL",
+            crate::solana::lamports_to_sol(*.needed),
+            crate::solana::lamports_to_sol(*.balance)
+        )]
         InsufficientSolanaBalance { needed: u64, balance: u64 },
-        #[error("transaction simulation failed")]
-        TxSimFailed,
+        #[error(
+            "transaction too large: {size} bytes, {} bytes over the limit of {max} bytes; \
+            use address lookup tables or split the instructions into multiple transactions",
+            .size - .max
+        )]
+        TxTooLarge { size: usize, max: usize },
+        /// `None` if another instruction of the bundled transaction failed.
+        #[error("transaction simulation failed{}", sim_failure(.0))]
+        TxSimFailed(Option<Arc<SimulationFailure>>),
         #[error("{}", crate::solana::verbose_solana_error(.error))]
         Solana {
             #[source]
             error: Arc<ClientError>,
             inserted: usize,
+            /// Program logs, if the error is a failed preflight simulation.
+            logs: Vec<String>,
+            /// Code of `InstructionError::Custom`.
+            custom_error: Option<u32>,
+        },
+        /// Custom error of an Anchor program, decoded with its IDL, `source` is the original
+        /// error.
+        #[error(
+            "{}{} (error {} of program {})",
+            .error.name,
+            idl_msg(.error),
+            .error.code,
+            .program_id
+        )]
+        Anchor {
+            program_id: Pubkey,
+            error: IdlError,
+            source: Arc<Error>,
         },
         #[error(transparent)]
         Signer(#[from] Arc<SignerError>),
@@ -294,14 +1295,68 @@ pub mod execute {
     impl Error {
         pub fn solana(error: ClientError, inserted: usize) -> Self {
             Self::Solana {
+                logs: crate::solana::program_logs(&error),
+                custom_error: error
+                    .get_transaction_error()
+                    .as_ref()
+                    .and_then(crate::solana::custom_error_code),
                 error: Arc::new(error),
                 inserted,
             }
         }
-    }
 
-    impl From<anyhow::Error> for Error {
-        fn from(value: anyhow::Error) -> Self {
+        /// Program logs of the failed transaction, empty if not available.
+        pub fn logs(&self) -> &[String] {
+            match self {
+                Self::Solana { logs, .. } => logs,
+                Self::TxSimFailed(Some(failure)) => &failure.logs,
+                Self::Anchor { source, .. } => source.logs(),
+                _ => &[],
+            }
+        }
+
+        /// `Program log: ` messages of [`Error::logs`], without the prefix.
+        pub fn program_log_messages(&self) -> impl Iterator<Item = &str> {
+            self.logs()
+                .iter()
+                .filter_map(|log| log.strip_prefix("Program log: "))
+        }
+
+        /// Custom error code returned by the failed program.
+        pub fn custom_error(&self) -> Option<u32> {
+            match self {
+                Self::Solana { custom_error, .. } => *custom_error,
+                Self::TxSimFailed(Some(failure)) => {
+                    crate::solana::custom_error_code(&failure.error)
+                }
+                Self::Anchor { error, .. } => Some(error.code),
+                _ => None,
+            }
+        }
+
+        /// Replace the custom error code of the failed program with its Anchor error, if
+        /// `idls` has the program's IDL. Other errors are returned unchanged.
+        pub fn decode_anchor(self, idls: &AnchorIdls) -> Self {
+            if matches!(self, Self::Anchor { .. }) {
+                return self;
+            }
+            let decoded = self.custom_error().and_then(|code| {
+                let program_id = failed_program(self.logs())?;
+                Some((program_id, idls.get(&program_id, code)?.clone()))
+            });
+            match decoded {
+                Some((program_id, error)) => Self::Anchor {
+                    program_id,
+                    error,
+                    source: Arc::new(self),
+                },
+                None => self,
+            }
+        }
+    }
+
+    impl From<anyhow::Error> for Error {
+        fn from(value: anyhow::Error) -> Self {
             value.downcast::<Self>().unwrap_or_else(Self::other)
         }
     }
@@ -318,6 +1373,27 @@ pub mod execute {
         }
     }
 
+    impl crate::utils::metrics::ErrorLabel for Error {
+        fn label(&self) -> &'static str {
+            match self {
+                Error::Canceled(_) => "canceled",
+                Error::NotAvailable => "not_available",
+                Error::TxIncomplete => "tx_incomplete",
+                Error::Timeout => "timeout",
+                Error::InsufficientSolanaBalance { .. } => "insufficient_solana_balance",
+                Error::TxTooLarge { .. } => "tx_too_large",
+                Error::TxSimFailed(_) => "tx_sim_failed",
+                Error::Solana { .. } => "solana",
+                Error::Anchor { .. } => "anchor",
+                Error::Signer(_) => "signer",
+                Error::Worker(_) => "worker",
+                Error::MailBox(_) => "mailbox",
+                Error::ChannelClosed(_) => "channel_closed",
+                Error::Other(_) => "other",
+            }
+        }
+    }
+
     impl Error {
         pub fn worker(e: BoxError) -> Self {
             Error::Worker(Arc::new(e))
@@ -329,9 +1405,18 @@ pub mod execute {
     }
 
     pub fn unimplemented_svc() -> Svc {
-        Svc::unimplemented(|| Error::other("unimplemented"), Error::worker)
+        Svc::unimplemented(|| Error::NotAvailable, Error::worker)
     }
 
+    /// Execute instructions with [`Instructions::execute`].
+    ///
+    /// `size` is the capacity of the buffer in front of the service: it bounds how many
+    /// requests are queued, callers wait in [`Svc::call_mut`] when it is full. It doesn't
+    /// bound how many transactions are executed at the same time, that is limited by
+    /// [`crate::ContextConfig::execute_concurrency`], which is shared by all services
+    /// created from clones of `ctx`. Requests over the limit wait in the buffer.
+    ///
+    /// Settings that `config` doesn't set default to [`crate::ContextConfig::solana`].
     pub fn simple(
         ctx: &super::Context,
         size: usize,
@@ -340,24 +1425,90 @@ pub mod execute {
     ) -> Svc {
         let rpc = ctx.solana_client.clone();
         let signer = ctx.signer.clone();
+        let mut config = config.with_defaults(&ctx.cfg.solana, &ctx.cfg.environment);
+        if config.ws_url.is_none() {
+            config.ws_url = ctx.cfg.solana_client.websocket_url();
+        }
         let handle = move |req: Request| {
             let rpc = rpc.clone();
             let signer = signer.clone();
-            let config = config.clone();
+            let config = config.clone().with_options(&req.options);
             async move {
+                if let Some(prebuilt) = req.transaction {
+                    if req.dry_run {
+                        return Err(Error::other("can't dry run a prebuilt transaction"));
+                    }
+                    let signature = crate::solana::execute_transaction(
+                        prebuilt.tx,
+                        &prebuilt.keypairs,
+                        &rpc,
+                        signer,
+                        flow_run_id,
+                        &config,
+                    )
+                    .await?;
+                    let (slot, block_time) = crate::solana::landed_at(&rpc, &signature).await;
+                    return Ok(Response {
+                        signature: Some(signature),
+                        serialized_tx: None,
+                        slot,
+                        block_time,
+                    });
+                }
+                if req.dry_run {
+                    return Ok(Response {
+                        signature: None,
+                        serialized_tx: Some(req.instructions.dry_run(&rpc, config).await?),
+                        slot: None,
+                        block_time: None,
+                    });
+                }
+                let signature = req
+                    .instructions
+                    .execute(&rpc, signer, flow_run_id, config)
+                    .await?;
+                let (slot, block_time) = crate::solana::landed_at(&rpc, &signature).await;
                 Ok(Response {
-                    signature: Some(
-                        req.instructions
-                            .execute(&rpc, signer, flow_run_id, config)
-                            .await?,
-                    ),
+                    signature: Some(signature),
+                    serialized_tx: None,
+                    slot,
+                    block_time,
                 })
             }
         };
-        Svc::from_service(tower::service_fn(handle), Error::worker, size)
+        let svc = tower::service_fn(handle);
+        match ctx.execute_limit.clone() {
+            Some(semaphore) => Svc::from_service(
+                ConcurrencyLimit::with_semaphore(svc, semaphore),
+                Error::worker,
+                size,
+            ),
+            None => Svc::from_service(svc, Error::worker, size),
+        }
     }
 }
 
+/// Run `fut` in `span`, record how long it took in the `elapsed_ms` field and in
+/// [`metrics`][crate::utils::metrics].
+async fn traced<T, E: std::fmt::Display + ErrorLabel>(
+    span: tracing::Span,
+    service: &'static str,
+    flow_run_id: Option<FlowRunId>,
+    fut: impl Future<Output = Result<T, E>>,
+) -> Result<T, E> {
+    let start = Instant::now();
+    let result = fut.instrument(span.clone()).await;
+    let elapsed = start.elapsed();
+    metrics::record_call(service, flow_run_id, elapsed, &result);
+    let elapsed_ms = elapsed.as_millis() as u64;
+    span.record("elapsed_ms", elapsed_ms);
+    span.in_scope(|| match &result {
+        Ok(_) => tracing::debug!(elapsed_ms, "service call finished"),
+        Err(error) => tracing::warn!(elapsed_ms, "service call failed: {}", error),
+    });
+    result
+}
+
 #[derive(Clone)]
 pub struct CommandContext {
     pub svc: execute::Svc,
@@ -379,6 +1530,13 @@ pub struct Context {
     pub command: Option<CommandContext>,
     pub signer: signer::Svc,
     pub get_jwt: get_jwt::Svc,
+    /// Cache of [`get_jwt`] results, shared by clones of the context.
+    pub jwt_cache: get_jwt::Cache,
+    /// Decimals and token programs of mints, shared by clones of the context.
+    pub mint_cache: MintCache,
+    pub priority_fee: priority_fee::Svc,
+    /// Limit of [`ContextConfig::execute_concurrency`], shared by clones of the context.
+    pub execute_limit: Option<Arc<Semaphore>>,
 }
 
 impl Default for Context {
@@ -421,7 +1579,287 @@ impl Default for User {
     }
 }
 
+#[derive(ThisError, Debug, Clone, PartialEq, Eq)]
+pub enum BuildError {
+    #[error("missing field: {}", .0)]
+    Missing(&'static str),
+    #[error("invalid HTTP client config: {}", .0)]
+    HttpClient(String),
+}
+
+/// Result of checking a service in [`Context::health_check`].
+#[derive(Debug, Clone)]
+pub struct ServiceHealth<T = ()> {
+    pub result: Result<T, String>,
+    pub latency: Duration,
+}
+
+impl<T> ServiceHealth<T> {
+    async fn check<E: std::fmt::Display>(fut: impl Future<Output = Result<T, E>>) -> Self {
+        let start = Instant::now();
+        let result = match tokio::time::timeout(HEALTH_CHECK_TIMEOUT, fut).await {
+            Ok(result) => result.map_err(|error| error.to_string()),
+            Err(_) => Err("timeout".to_owned()),
+        };
+        Self {
+            result,
+            latency: start.elapsed(),
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.result.is_ok()
+    }
+}
+
+/// Timeout of each service in [`Context::health_check`].
+pub const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub struct HealthCheck {
+    /// Current slot of the Solana RPC.
+    pub rpc: ServiceHealth<u64>,
+    /// PostgREST of our Supabase project.
+    pub postgrest: ServiceHealth,
+    /// Supabase Auth, which issues tokens of [`get_jwt`].
+    pub supabase_auth: ServiceHealth,
+}
+
+impl HealthCheck {
+    pub fn is_healthy(&self) -> bool {
+        self.rpc.is_ok() && self.postgrest.is_ok() && self.supabase_auth.is_ok()
+    }
+}
+
+fn fastest_endpoint<'a>(pings: &[(&'a str, ServiceHealth)]) -> Option<&'a str> {
+    pings
+        .iter()
+        .filter(|(_, health)| health.is_ok())
+        .min_by_key(|(_, health)| health.latency)
+        .map(|(url, _)| *url)
+}
+
+/// Endpoints chosen by [`select_supabase`], keyed by the sorted candidates, so that
+/// contexts built later for the same project start with the chosen one.
+static SELECTED_SUPABASE: LazyLock<Mutex<HashMap<Vec<String>, String>>> =
+    LazyLock::new(<_>::default);
+
+fn candidates_key(endpoints: &Endpoints) -> Vec<String> {
+    let mut key = endpoints
+        .supabase_candidates()
+        .into_iter()
+        .map(str::to_owned)
+        .collect::<Vec<_>>();
+    key.sort();
+    key
+}
+
+/// `endpoints` with the endpoint last chosen by [`select_supabase`] as `supabase`.
+fn with_selected_supabase(endpoints: &Endpoints) -> Endpoints {
+    let mut endpoints = endpoints.clone();
+    if !endpoints.supabase_regions.is_empty() {
+        let selected = SELECTED_SUPABASE.lock().unwrap();
+        if let Some(url) = selected.get(&candidates_key(&endpoints)) {
+            endpoints.supabase = url.clone();
+        }
+    }
+    endpoints
+}
+
+/// Ping `endpoints.supabase` and `endpoints.supabase_regions` concurrently, each with
+/// [`HEALTH_CHECK_TIMEOUT`], and return the fastest one, `None` if none respond.
+///
+/// The result is shared by the process: contexts built afterwards with the same
+/// endpoints use it as `endpoints.supabase`.
+pub async fn select_supabase(http: &reqwest::Client, endpoints: &Endpoints) -> Option<String> {
+    let pings = endpoints
+        .supabase_candidates()
+        .into_iter()
+        .map(|url| async move {
+            let health = ServiceHealth::check(async {
+                http.get(format!("{}/auth/v1/health", url))
+                    .header("apikey", &endpoints.supabase_anon_key)
+                    .send()
+                    .await?
+                    .error_for_status()?;
+                Ok::<_, reqwest::Error>(())
+            })
+            .await;
+            (url, health)
+        });
+    let pings = futures::future::join_all(pings).await;
+    let url = fastest_endpoint(&pings)?.to_owned();
+    SELECTED_SUPABASE
+        .lock()
+        .unwrap()
+        .insert(candidates_key(endpoints), url.clone());
+    Some(url)
+}
+
+/// Call [`select_supabase`] every `period` to follow latency changes, starting now.
+/// It does nothing if `endpoints.supabase_regions` is empty.
+pub async fn refresh_supabase(http: reqwest::Client, endpoints: Endpoints, period: Duration) {
+    if endpoints.supabase_regions.is_empty() {
+        return;
+    }
+    let mut interval = tokio::time::interval(period);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        match select_supabase(&http, &endpoints).await {
+            Some(url) => tracing::debug!("selected Supabase endpoint {}", url),
+            None => tracing::warn!("no Supabase endpoint responded"),
+        }
+    }
+}
+
+/// Build a [`Context`].
+///
+/// `flow_owner` is required, `started_by` default to `flow_owner`. Services that are not
+/// set are unimplemented, except `priority_fee` which uses the Solana RPC.
+#[derive(Default)]
+pub struct ContextBuilder {
+    cfg: ContextConfig,
+    flow_owner: Option<User>,
+    started_by: Option<User>,
+    signer: Option<signer::Svc>,
+    get_jwt: Option<get_jwt::Svc>,
+    jwt_cache: Option<get_jwt::Cache>,
+    mint_cache: Option<MintCache>,
+    priority_fee: Option<priority_fee::Svc>,
+    extensions: Extensions,
+    http_client: Option<reqwest::Client>,
+    solana_client: Option<Arc<SolanaClient>>,
+}
+
+impl ContextBuilder {
+    pub fn new(cfg: ContextConfig) -> Self {
+        Self {
+            cfg,
+            ..<_>::default()
+        }
+    }
+
+    pub fn flow_owner(mut self, user: User) -> Self {
+        self.flow_owner = Some(user);
+        self
+    }
+
+    pub fn started_by(mut self, user: User) -> Self {
+        self.started_by = Some(user);
+        self
+    }
+
+    pub fn signer(mut self, svc: signer::Svc) -> Self {
+        self.signer = Some(svc);
+        self
+    }
+
+    pub fn get_jwt(mut self, svc: get_jwt::Svc) -> Self {
+        self.get_jwt = Some(svc);
+        self
+    }
+
+    /// Share a JWT cache between contexts, by default each context creates a new cache with
+    /// [`get_jwt::DEFAULT_EXPIRY_SKEW`].
+    pub fn jwt_cache(mut self, cache: get_jwt::Cache) -> Self {
+        self.jwt_cache = Some(cache);
+        self
+    }
+
+    /// Share a cache of mints between contexts.
+    pub fn mint_cache(mut self, cache: MintCache) -> Self {
+        self.mint_cache = Some(cache);
+        self
+    }
+
+    pub fn priority_fee(mut self, svc: priority_fee::Svc) -> Self {
+        self.priority_fee = Some(svc);
+        self
+    }
+
+    pub fn extensions(mut self, extensions: Extensions) -> Self {
+        self.extensions = extensions;
+        self
+    }
+
+    /// Add an extension, replacing any existing extension of the same type.
+    ///
+    /// Extensions are shared by all clones of the [`Context`], use interior mutability
+    /// (e.g. `Mutex<T>`, atomics) for state that commands need to modify.
+    pub fn register<T: Send + Sync + 'static>(mut self, val: T) -> Self {
+        self.extensions.insert(val);
+        self
+    }
+
+    /// Use this client instead of the shared client of [`ContextConfig::http_client`],
+    /// see [`HttpClientConfig::shared`][crate::config::HttpClientConfig::shared].
+    pub fn http_client(mut self, http: reqwest::Client) -> Self {
+        self.http_client = Some(http);
+        self
+    }
+
+    /// Use this client instead of creating one from [`ContextConfig::solana_client`].
+    pub fn solana_client(mut self, client: Arc<SolanaClient>) -> Self {
+        self.solana_client = Some(client);
+        self
+    }
+
+    pub fn build(self) -> Result<Context, BuildError> {
+        let flow_owner = self.flow_owner.ok_or(BuildError::Missing("flow_owner"))?;
+        let http = match self.http_client {
+            Some(http) => http,
+            None => self
+                .cfg
+                .http_client
+                .shared()
+                .map_err(|error| BuildError::HttpClient(error.to_string()))?,
+        };
+        let started_by = self.started_by.unwrap_or(flow_owner);
+        let solana_client = self.solana_client.unwrap_or_else(|| {
+            Arc::new(crate::solana::new_rpc_client(
+                &self.cfg.solana_client,
+                Duration::from_secs(30),
+                CommitmentConfig {
+                    commitment: CommitmentLevel::Finalized,
+                },
+                Duration::from_secs(180),
+            ))
+        });
+        let priority_fee = self
+            .priority_fee
+            .unwrap_or_else(|| priority_fee::rpc(solana_client.clone(), 32));
+
+        let execute_limit = self
+            .cfg
+            .execute_concurrency
+            .map(|n| Arc::new(Semaphore::new(n.get())));
+
+        Ok(Context {
+            flow_owner,
+            started_by,
+            http,
+            priority_fee,
+            solana_client,
+            environment: self.cfg.environment.clone(),
+            endpoints: with_selected_supabase(&self.cfg.endpoints),
+            cfg: self.cfg,
+            extensions: Arc::new(self.extensions),
+            command: None,
+            signer: self.signer.unwrap_or_else(signer::unimplemented_svc),
+            get_jwt: self.get_jwt.unwrap_or_else(get_jwt::unimplemented_svc),
+            jwt_cache: self.jwt_cache.unwrap_or_default(),
+            mint_cache: self.mint_cache.unwrap_or_default(),
+            execute_limit,
+        })
+    }
+}
+
 impl Context {
+    pub fn builder(cfg: ContextConfig) -> ContextBuilder {
+        ContextBuilder::new(cfg)
+    }
+
     pub fn from_cfg(
         cfg: &ContextConfig,
         flow_owner: User,
@@ -430,42 +1868,76 @@ impl Context {
         token_svc: get_jwt::Svc,
         extensions: Extensions,
     ) -> Self {
-        let solana_client = SolanaClient::new_with_timeouts_and_commitment(
-            cfg.solana_client.url.clone(),
-            Duration::from_secs(30),
-            CommitmentConfig {
-                commitment: CommitmentLevel::Finalized,
-            },
-            Duration::from_secs(180),
-        );
+        let http = cfg.http_client.shared().unwrap_or_else(|error| {
+            tracing::error!(
+                "invalid HTTP client config: {}, using default client",
+                error
+            );
+            reqwest::Client::new()
+        });
+        ContextBuilder::new(cfg.clone())
+            .http_client(http)
+            .flow_owner(flow_owner)
+            .started_by(started_by)
+            .signer(sig_svc)
+            .get_jwt(token_svc)
+            .extensions(extensions)
+            .build()
+            .expect("flow_owner is set")
+    }
 
-        Self {
-            flow_owner,
-            started_by,
-            cfg: cfg.clone(),
-            http: reqwest::Client::new(),
-            solana_client: Arc::new(solana_client),
-            environment: cfg.environment.clone(),
-            endpoints: cfg.endpoints.clone(),
-            extensions: Arc::new(extensions),
-            command: None,
-            signer: sig_svc,
-            get_jwt: token_svc,
-        }
+    /// Get JWT of the flow owner, see [`Context::get_jwt_header_for`].
+    pub async fn get_jwt_header(&mut self) -> Result<String, get_jwt::Error> {
+        self.get_jwt_header_for(self.flow_owner.id).await
     }
 
     /// Call [`get_jwt`] service, the result will have `Bearer ` prefix.
-    pub async fn get_jwt_header(&mut self) -> Result<String, get_jwt::Error> {
-        Ok("Bearer ".to_owned()
-            + &self
-                .get_jwt
-                .ready()
-                .await?
-                .call(get_jwt::Request {
-                    user_id: self.flow_owner.id,
-                })
-                .await?
-                .access_token)
+    ///
+    /// Tokens are cached in [`Context::jwt_cache`] until they are about to expire.
+    /// Returns [`get_jwt::Error::WrongRecipient`] if the service is not allowed to issue
+    /// tokens for `user_id`.
+    pub async fn get_jwt_header_for(&mut self, user_id: UserId) -> Result<String, get_jwt::Error> {
+        let span = self.service_span("get_jwt");
+        span.record("user_id", tracing::field::display(user_id));
+        let token = traced(
+            span,
+            "get_jwt",
+            self.flow_run_id(),
+            self.jwt_cache.get_or_fetch(&self.get_jwt, user_id),
+        )
+        .await?;
+        Ok("Bearer ".to_owned() + &token)
+    }
+
+    /// Remove the cached token of `user_id`, e.g. when it was rejected before its expiry,
+    /// the next [`Context::get_jwt_header_for`] will call [`get_jwt`] service again.
+    pub fn invalidate_jwt(&self, user_id: UserId) {
+        self.jwt_cache.remove(&user_id);
+    }
+
+    fn flow_run_id(&self) -> Option<FlowRunId> {
+        self.command.as_ref().map(|ctx| ctx.flow_run_id)
+    }
+
+    /// A span for calling `service`, logs inside the span can be filtered by
+    /// `flow_run_id` and `node_id`.
+    fn service_span(&self, service: &'static str) -> tracing::Span {
+        let span = tracing::info_span!(
+            "service_call",
+            service,
+            flow_run_id = tracing::field::Empty,
+            node_id = tracing::field::Empty,
+            times = tracing::field::Empty,
+            user_id = tracing::field::Empty,
+            pubkey = tracing::field::Empty,
+            elapsed_ms = tracing::field::Empty,
+        );
+        if let Some(c) = &self.command {
+            span.record("flow_run_id", tracing::field::display(c.flow_run_id));
+            span.record("node_id", tracing::field::display(c.node_id));
+            span.record("times", c.times);
+        }
+        span
     }
 
     pub fn new_interflow_origin(&self) -> Option<FlowRunOrigin> {
@@ -483,20 +1955,116 @@ impl Context {
         instructions: Instructions,
         output: value::Map,
     ) -> Result<execute::Response, execute::Error> {
-        if let Some(ctx) = &mut self.command {
-            ctx.svc
-                .ready()
-                .await?
-                .call(execute::Request {
-                    instructions,
-                    output,
-                })
-                .await
+        self.execute_with_options(instructions, output, <_>::default())
+            .await
+    }
+
+    /// Call [`execute`] service, overriding the flow's
+    /// [`ExecutionConfig`][crate::solana::ExecutionConfig] for this request.
+    pub async fn execute_with_options(
+        &mut self,
+        instructions: Instructions,
+        output: value::Map,
+        options: ExecuteOptions,
+    ) -> Result<execute::Response, execute::Error> {
+        self.call_execute(execute::Request {
+            instructions,
+            output,
+            options,
+            dry_run: false,
+            transaction: None,
+        })
+        .await
+    }
+
+    /// Execute multiple transactions one after another with the [`execute`] service.
+    ///
+    /// Returns a result for each transaction that was executed, in order. With
+    /// [`execute::BatchMode::Sequential`], the last result is the first failure and the
+    /// remaining transactions are not executed.
+    pub async fn execute_many(
+        &mut self,
+        instructions: Vec<Instructions>,
+        mode: execute::BatchMode,
+    ) -> Vec<Result<execute::Response, execute::Error>> {
+        let mut results = Vec::with_capacity(instructions.len());
+        for instructions in instructions {
+            let result = self.execute(instructions, <_>::default()).await;
+            let failed = result.is_err();
+            results.push(result);
+            if failed && mode == execute::BatchMode::Sequential {
+                break;
+            }
+        }
+        results
+    }
+
+    /// Call [`execute`] service with [`execute::Request::dry_run`], returns the unsigned
+    /// transaction encoded in base64.
+    pub async fn dry_run(
+        &mut self,
+        instructions: Instructions,
+        output: value::Map,
+        options: ExecuteOptions,
+    ) -> Result<String, execute::Error> {
+        let resp = self
+            .call_execute(execute::Request {
+                instructions,
+                output,
+                options,
+                dry_run: true,
+                transaction: None,
+            })
+            .await?;
+        resp.serialized_tx
+            .ok_or_else(|| execute::Error::other("dry run didn't return a transaction"))
+    }
+
+    async fn call_execute(
+        &mut self,
+        req: execute::Request,
+    ) -> Result<execute::Response, execute::Error> {
+        let span = self.service_span("execute");
+        let result = if let Some(ctx) = &mut self.command {
+            let flow_run_id = Some(ctx.flow_run_id);
+            traced(span, "execute", flow_run_id, async {
+                ctx.svc.ready().await?.call(req).await
+            })
+            .await
         } else {
             Err(execute::Error::NotAvailable)
+        };
+        match self.get::<AnchorIdls>() {
+            Some(idls) => result.map_err(|error| error.decode_anchor(idls)),
+            None => result,
         }
     }
 
+    /// Sign and send a transaction built outside of the flow, e.g. by a DEX aggregator,
+    /// with the [`execute`] service.
+    ///
+    /// The flow's [`ExecutionConfig`][crate::solana::ExecutionConfig] is used for sending
+    /// and confirming, settings that would change the message, such as priority fees, are
+    /// ignored. Not available outside of a flow run.
+    pub async fn execute_transaction(
+        &mut self,
+        tx: VersionedTransaction,
+        keypairs: Vec<Keypair>,
+        options: ExecuteOptions,
+    ) -> Result<Signature, execute::Error> {
+        let resp = self
+            .call_execute(execute::Request {
+                instructions: Instructions::default(),
+                output: <_>::default(),
+                options,
+                dry_run: false,
+                transaction: Some(execute::PrebuiltTransaction { tx, keypairs }),
+            })
+            .await?;
+        resp.signature
+            .ok_or_else(|| execute::Error::other("transaction was not sent"))
+    }
+
     /// Call [`signer`] service.
     pub async fn request_signature(
         &self,
@@ -504,20 +2072,205 @@ impl Context {
         message: Bytes,
         timeout: Duration,
     ) -> Result<signer::SignatureResponse, anyhow::Error> {
-        let mut s = self.signer.clone();
+        self.call_signer(pubkey, message, signer::MessageKind::Transaction, timeout)
+            .await
+    }
+
+    /// Ask `pubkey` to sign an off-chain message, e.g. an auth challenge. The signature is
+    /// checked against the off-chain message format, see
+    /// [`signer::MessageKind::OffchainMessage`].
+    pub async fn sign_offchain_message(
+        &self,
+        pubkey: Pubkey,
+        message: Bytes,
+        timeout: Duration,
+    ) -> Result<Signature, anyhow::Error> {
+        let kind = signer::MessageKind::OffchainMessage;
+        let resp = self
+            .call_signer(pubkey, message.clone(), kind, timeout)
+            .await?;
+        if !kind.verify(&pubkey, &message, &resp.signature) {
+            return Err(anyhow::anyhow!("signature verification failed"));
+        }
+        Ok(resp.signature)
+    }
+
+    async fn call_signer(
+        &self,
+        pubkey: Pubkey,
+        message: Bytes,
+        kind: signer::MessageKind,
+        timeout: Duration,
+    ) -> Result<signer::SignatureResponse, anyhow::Error> {
+        let s = tower::retry::Retry::new(signer::RetryPolicy::default(), self.signer.clone());
+        let span = self.service_span("signer");
+        span.record("pubkey", tracing::field::display(pubkey));
+        let req = signer::SignatureRequest {
+            id: None,
+            time: Utc::now(),
+            pubkey,
+            message,
+            kind,
+            timeout,
+            flow_run_id: self.flow_run_id(),
+            signatures: None,
+            cancel: None,
+        };
+
+        Ok(traced(span, "signer", self.flow_run_id(), s.oneshot(req)).await?)
+    }
 
+    /// Call [`priority_fee`] service, returns compute unit price in micro-lamports.
+    pub async fn estimate_priority_fee(
+        &self,
+        accounts: &[Pubkey],
+        percentile: u8,
+    ) -> Result<u64, priority_fee::Error> {
+        let mut s = self.priority_fee.clone();
         Ok(s.ready()
             .await?
-            .call(signer::SignatureRequest {
-                id: None,
-                time: Utc::now(),
-                pubkey,
-                message,
-                timeout,
-                flow_run_id: self.command.as_ref().map(|ctx| ctx.flow_run_id),
-                signatures: None,
+            .call(priority_fee::Request {
+                accounts: accounts.to_vec(),
+                percentile,
             })
-            .await?)
+            .await?
+            .micro_lamports)
+    }
+
+    /// Get balance of `pubkey` in lamports.
+    pub async fn get_sol_balance(&self, pubkey: &Pubkey) -> Result<u64, ClientError> {
+        self.solana_client.get_balance(pubkey).await
+    }
+
+    /// Get balance of `mint` owned by `owner`, summed over all of its token accounts.
+    pub async fn get_token_balance(
+        &self,
+        owner: &Pubkey,
+        mint: &Pubkey,
+    ) -> Result<TokenBalance, ClientError> {
+        crate::solana::get_token_balance(&self.solana_client, owner, mint).await
+    }
+
+    /// Get the latest blockhash with the commitment of the Solana client, with its
+    /// `last_valid_block_height`. Nodes that build a transaction to be signed elsewhere can
+    /// hand this over as the transaction's expiry.
+    pub async fn get_latest_blockhash(
+        &self,
+    ) -> Result<crate::solana::LatestBlockhash, ClientError> {
+        crate::solana::get_latest_blockhash(&self.solana_client, self.solana_client.commitment())
+            .await
+    }
+
+    /// Stream changes of `pubkey`'s account, on the WebSocket endpoint of the Solana
+    /// client's config. See [`crate::solana::subscribe_account`].
+    pub fn subscribe_account(
+        &self,
+        pubkey: &Pubkey,
+        commitment: CommitmentLevel,
+    ) -> Result<futures::stream::BoxStream<'static, crate::solana::AccountUpdate>, anyhow::Error>
+    {
+        let ws_url = self.cfg.solana_client.websocket_url().ok_or_else(|| {
+            anyhow::anyhow!(
+                "no WebSocket endpoint for Solana RPC {}",
+                self.cfg.solana_client.url
+            )
+        })?;
+        Ok(crate::solana::subscribe_account(
+            ws_url,
+            *pubkey,
+            CommitmentConfig { commitment },
+        ))
+    }
+
+    /// Get decimals of `mint`, cached in [`Context::mint_cache`].
+    pub async fn resolve_token_mint_decimals(&self, mint: &Pubkey) -> Result<u8, anyhow::Error> {
+        Ok(self
+            .mint_cache
+            .get_or_fetch(&self.solana_client, mint)
+            .await?
+            .decimals)
+    }
+
+    /// Get the associated token account of `owner` for `mint`, with the instruction creating
+    /// it if it doesn't exist, see [`crate::solana::token::get_or_create_ata`].
+    pub async fn get_or_create_ata(
+        &self,
+        payer: &Pubkey,
+        owner: &Pubkey,
+        mint: &Pubkey,
+    ) -> Result<crate::solana::token::AssociatedTokenAccount, anyhow::Error> {
+        crate::solana::token::get_or_create_ata(&self.solana_client, payer, owner, mint).await
+    }
+
+    /// Get statuses of `signatures`, in the same order.
+    pub async fn get_signature_statuses(
+        &self,
+        signatures: &[Signature],
+    ) -> Result<Vec<SignatureStatus>, ClientError> {
+        crate::solana::get_signature_statuses(&self.solana_client, signatures).await
+    }
+
+    /// Check that the Solana RPC, PostgREST and Supabase Auth are reachable, concurrently
+    /// and each with [`HEALTH_CHECK_TIMEOUT`].
+    ///
+    /// PostgREST is reachable if it responds without a server error, it may reject the
+    /// anon key depending on the project's settings.
+    pub async fn health_check(&self) -> HealthCheck {
+        let get = |path: &str| {
+            self.http
+                .get(format!("{}{}", self.endpoints.supabase, path))
+                .header("apikey", &self.endpoints.supabase_anon_key)
+                .send()
+        };
+        let rpc = ServiceHealth::check(self.solana_client.get_slot());
+        let postgrest = ServiceHealth::check(async {
+            let resp = get("/rest/v1/").await?;
+            if resp.status().is_server_error() {
+                return Err(anyhow::anyhow!("status {}", resp.status()));
+            }
+            Ok::<_, anyhow::Error>(())
+        });
+        let supabase_auth = ServiceHealth::check(async {
+            get("/auth/v1/health").await?.error_for_status()?;
+            Ok::<_, anyhow::Error>(())
+        });
+        let (rpc, postgrest, supabase_auth) = futures::join!(rpc, postgrest, supabase_auth);
+        HealthCheck {
+            rpc,
+            postgrest,
+            supabase_auth,
+        }
+    }
+
+    /// Use the fastest of `endpoints.supabase` and `endpoints.supabase_regions` as
+    /// `endpoints.supabase`, see [`select_supabase`].
+    ///
+    /// The endpoint is unchanged if none respond. Call it again to follow latency
+    /// changes, or set `endpoints.supabase` to choose an endpoint manually.
+    pub async fn select_supabase_endpoint(&mut self) -> &str {
+        if let Some(url) = select_supabase(&self.http, &self.endpoints).await {
+            self.endpoints.supabase = url;
+        }
+        &self.endpoints.supabase
+    }
+
+    /// Get and parse an environment variable, `None` if it is not set.
+    pub fn env_get<T: env::FromEnv>(&self, key: &str) -> Result<Option<T>, env::Error> {
+        self.environment
+            .get(key)
+            .map(|value| {
+                T::from_env(value).map_err(|error| env::Error::Invalid {
+                    key: key.to_owned(),
+                    error,
+                })
+            })
+            .transpose()
+    }
+
+    /// Same as [`Context::env_get`], but error if the variable is not set.
+    pub fn env_require<T: env::FromEnv>(&self, key: &str) -> Result<T, env::Error> {
+        self.env_get(key)?
+            .ok_or_else(|| env::Error::Missing(key.to_owned()))
     }
 
     /// Get an extension by type.
@@ -525,6 +2278,28 @@ impl Context {
         self.extensions.get::<T>()
     }
 
+    /// Get a mutable reference to an extension.
+    ///
+    /// `extensions` is shared between clones of the context, this returns `None` if the
+    /// extension doesn't exist or if other clones are still alive. To share mutable state
+    /// between commands of a run, register a `Mutex<T>` or an atomic type instead and use
+    /// [`Context::get`]; extensions must be `Send + Sync` because commands run on
+    /// multiple threads.
+    pub fn get_mut<T: Any + Send + Sync + 'static>(&mut self) -> Option<&mut T> {
+        Arc::get_mut(&mut self.extensions)?.get_mut::<T>()
+    }
+
+    /// Add an extension, returns the old value if one exists.
+    ///
+    /// Like [`Context::get_mut`], this only works if the context is not shared, otherwise
+    /// `Err(val)` is returned.
+    pub fn insert<T: Any + Send + Sync + 'static>(&mut self, val: T) -> Result<Option<T>, T> {
+        match Arc::get_mut(&mut self.extensions) {
+            Some(ext) => Ok(ext.insert(val)),
+            None => Err(val),
+        }
+    }
+
     // A function to make sure Context is Send + Sync,
     // because !Sync will make it really hard to write async code.
     #[allow(dead_code)]
@@ -542,4 +2317,439 @@ mod tests {
     fn test_no_tokio() {
         Context::default();
     }
+
+    #[test]
+    fn test_builder() {
+        let owner = User::new(uuid::Uuid::new_v4());
+        let ctx = Context::builder(ContextConfig::default())
+            .flow_owner(owner)
+            .build()
+            .unwrap();
+        assert_eq!(ctx.started_by.id, owner.id);
+        assert_eq!(
+            Context::builder(ContextConfig::default())
+                .build()
+                .err()
+                .unwrap(),
+            BuildError::Missing("flow_owner")
+        );
+
+        let mut cfg = ContextConfig::default();
+        cfg.http_client.proxy = Some("not a url".to_owned());
+        assert!(matches!(
+            Context::builder(cfg).flow_owner(owner).build(),
+            Err(BuildError::HttpClient(_))
+        ));
+    }
+
+    /// Solana RPC answering every request with an error after `delay`, returns its URL
+    /// and the highest number of requests it handled at the same time.
+    fn slow_rpc(delay: Duration) -> (String, Arc<std::sync::atomic::AtomicUsize>) {
+        use std::{
+            io::{BufRead, BufReader, Read, Write},
+            sync::atomic::{AtomicUsize, Ordering},
+        };
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = max.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let (in_flight, max) = (in_flight.clone(), max.clone());
+                std::thread::spawn(move || {
+                    let mut reader = BufReader::new(stream.unwrap());
+                    loop {
+                        let mut len = 0;
+                        let mut line = String::new();
+                        while reader.read_line(&mut line).unwrap_or(0) > 0 && line != "\r\n" {
+                            if let Some((name, value)) = line.split_once(':') {
+                                if name.eq_ignore_ascii_case("content-length") {
+                                    len = value.trim().parse().unwrap();
+                                }
+                            }
+                            line.clear();
+                        }
+                        if line.is_empty() {
+                            return;
+                        }
+                        let mut body = vec![0; len];
+                        reader.read_exact(&mut body).unwrap();
+                        let id = serde_json::from_slice::<serde_json::Value>(&body).unwrap()["id"]
+                            .clone();
+
+                        let n = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                        max.fetch_max(n, Ordering::SeqCst);
+                        std::thread::sleep(delay);
+                        in_flight.fetch_sub(1, Ordering::SeqCst);
+
+                        let body = serde_json::json!({
+                            "jsonrpc": "2.0",
+                            "error": { "code": -32000, "message": "busy" },
+                            "id": id,
+                        })
+                        .to_string();
+                        let resp = format!(
+                            "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\n\
+                             content-length: {}\r\n\r\n{}",
+                            body.len(),
+                            body
+                        );
+                        reader.get_mut().write_all(resp.as_bytes()).unwrap();
+                    }
+                });
+            }
+        });
+        (url, max_in_flight)
+    }
+
+    #[tokio::test]
+    async fn test_execute_concurrency() {
+        let (url, max_in_flight) = slow_rpc(Duration::from_millis(100));
+        let mut cfg = ContextConfig::default();
+        cfg.solana_client.url = url;
+        cfg.execute_concurrency = std::num::NonZeroUsize::new(2);
+        let ctx = Context::builder(cfg)
+            .flow_owner(User::new(uuid::Uuid::new_v4()))
+            .build()
+            .unwrap();
+        let svc = execute::simple(&ctx, 32, None, <_>::default());
+
+        // each dry run sends one `getMultipleAccounts` request for its lookup table, which
+        // fails once the RPC responds
+        let results = futures::future::join_all((0..6).map(|_| {
+            svc.call_ref(execute::Request {
+                instructions: Instructions::default(),
+                output: <_>::default(),
+                options: ExecuteOptions {
+                    lookup_tables: vec![Pubkey::new_unique()],
+                    ..<_>::default()
+                },
+                dry_run: true,
+                transaction: None,
+            })
+        }))
+        .await;
+        assert!(results.iter().all(Result::is_err));
+        assert_eq!(max_in_flight.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_health_check() {
+        let mut ctx = Context::default();
+        ctx.endpoints.supabase = "http://127.0.0.1:1".to_owned();
+        ctx.solana_client = Arc::new(SolanaClient::new("http://127.0.0.1:1".to_owned()));
+        let health = ctx.health_check().await;
+        assert!(health.rpc.result.is_err());
+        assert!(health.postgrest.result.is_err());
+        assert!(health.supabase_auth.result.is_err());
+        assert!(!health.is_healthy());
+    }
+
+    #[tokio::test]
+    async fn test_execute_many() {
+        let mut ctx = Context::default();
+        let results = ctx
+            .execute_many(
+                vec![Instructions::default(), Instructions::default()],
+                execute::BatchMode::Sequential,
+            )
+            .await;
+        assert_eq!(results.len(), 1);
+        assert!(matches!(results[0], Err(execute::Error::NotAvailable)));
+
+        let results = ctx
+            .execute_many(
+                vec![Instructions::default(), Instructions::default()],
+                execute::BatchMode::BestEffort,
+            )
+            .await;
+        assert_eq!(results.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_execute_transaction() {
+        let tx = VersionedTransaction {
+            signatures: vec![Signature::new_unique()],
+            message: solana_sdk::message::VersionedMessage::Legacy(<_>::default()),
+        };
+        let mut ctx = Context::default();
+        assert!(matches!(
+            ctx.execute_transaction(tx.clone(), vec![], <_>::default())
+                .await,
+            Err(execute::Error::NotAvailable)
+        ));
+
+        // the flow's execute service receives the transaction
+        let svc = tower::service_fn(|req: execute::Request| async move {
+            Ok::<_, execute::Error>(execute::Response {
+                signature: req.transaction.map(|prebuilt| prebuilt.tx.signatures[0]),
+                serialized_tx: None,
+                slot: None,
+                block_time: None,
+            })
+        });
+        ctx.command = Some(CommandContext {
+            svc: execute::Svc::from_service(svc, execute::Error::worker, 32),
+            flow_run_id: uuid::Uuid::new_v4(),
+            node_id: uuid::Uuid::new_v4(),
+            times: 0,
+        });
+        let signature = ctx
+            .execute_transaction(tx.clone(), vec![], <_>::default())
+            .await
+            .unwrap();
+        assert_eq!(signature, tx.signatures[0]);
+    }
+
+    #[tokio::test]
+    async fn test_select_supabase_endpoint() {
+        let ping = |ok: bool, ms| ServiceHealth {
+            result: if ok {
+                Ok(())
+            } else {
+                Err("timeout".to_owned())
+            },
+            latency: Duration::from_millis(ms),
+        };
+        let pings = [
+            ("a", ping(true, 30)),
+            ("b", ping(false, 5)),
+            ("c", ping(true, 10)),
+        ];
+        assert_eq!(fastest_endpoint(&pings), Some("c"));
+        assert_eq!(fastest_endpoint(&pings[1..2]), None);
+
+        let mut ctx = Context::default();
+        ctx.endpoints.supabase = "http://127.0.0.1:1".to_owned();
+        ctx.endpoints.supabase_regions = vec!["http://127.0.0.1:2".to_owned()];
+        assert_eq!(ctx.select_supabase_endpoint().await, "http://127.0.0.1:1");
+    }
+
+    #[tokio::test]
+    async fn test_selected_supabase_endpoint_is_used() {
+        use std::io::{BufRead, BufReader, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let region = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap_or(0) > 0 && line != "\r\n" {
+                    line.clear();
+                }
+                stream
+                    .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\nconnection: close\r\n\r\n")
+                    .ok();
+            }
+        });
+
+        let mut cfg = ContextConfig::default();
+        cfg.endpoints.supabase = "http://127.0.0.1:1".to_owned();
+        cfg.endpoints.supabase_regions = vec![region.clone()];
+        let build = || {
+            Context::builder(cfg.clone())
+                .flow_owner(User::new(uuid::Uuid::new_v4()))
+                .build()
+                .unwrap()
+        };
+        assert_eq!(build().endpoints.supabase, "http://127.0.0.1:1");
+
+        assert_eq!(
+            select_supabase(&reqwest::Client::new(), &cfg.endpoints).await,
+            Some(region.clone())
+        );
+        // contexts of later flow runs start with the selected endpoint
+        assert_eq!(build().endpoints.supabase, region);
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_account() {
+        use futures::StreamExt;
+
+        let mut ctx = Context::default();
+        ctx.cfg.solana_client.url = "unix:/tmp/solana.sock".to_owned();
+        assert!(ctx
+            .subscribe_account(&Pubkey::new_unique(), CommitmentLevel::Confirmed)
+            .is_err());
+
+        // nothing listens on this port, the stream waits while reconnecting
+        ctx.cfg.solana_client.ws_url = Some("ws://127.0.0.1:1".to_owned());
+        let mut updates = ctx
+            .subscribe_account(&Pubkey::new_unique(), CommitmentLevel::Confirmed)
+            .unwrap();
+        let next = tokio::time::timeout(Duration::from_millis(200), updates.next()).await;
+        assert!(next.is_err());
+    }
+
+    #[test]
+    fn test_decode_anchor() {
+        use crate::solana::{
+            anchor::{AnchorIdls, IdlError},
+            SimulationFailure,
+        };
+        use solana_sdk::{instruction::InstructionError, transaction::TransactionError};
+
+        let program_id = Pubkey::new_unique();
+        let failure = |code| {
+            execute::Error::TxSimFailed(Some(Arc::new(SimulationFailure {
+                error: TransactionError::InstructionError(1, InstructionError::Custom(code)),
+                logs: vec![format!(
+                    "Program {} failed: custom program error: {:#x}",
+                    program_id, code
+                )],
+                inserted: 1,
+            })))
+        };
+        let mut idls = AnchorIdls::default();
+        idls.insert(
+            program_id,
+            [IdlError {
+                code: 6000,
+                name: "Overflow".to_owned(),
+                msg: Some("counter overflowed".to_owned()),
+            }],
+        );
+
+        let error = failure(6000).decode_anchor(&idls);
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Overflow: counter overflowed (error 6000 of program {})",
+                program_id
+            )
+        );
+        assert_eq!(error.custom_error(), Some(6000));
+        assert_eq!(error.logs().len(), 1);
+        assert!(matches!(
+            failure(6001).decode_anchor(&idls),
+            execute::Error::TxSimFailed(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_not_configured() {
+        let mut ctx = Context::default();
+        assert!(matches!(
+            ctx.get_jwt_header().await,
+            Err(get_jwt::Error::NotConfigured)
+        ));
+        let error = ctx
+            .request_signature(
+                Pubkey::new_unique(),
+                Bytes::from_static(b"message"),
+                Duration::from_secs(1),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<signer::Error>(),
+            Some(signer::Error::NotConfigured)
+        ));
+        ctx.command = None;
+        let result = ctx.execute(Instructions::default(), <_>::default()).await;
+        assert!(matches!(
+            result,
+            Err(error) if error.to_string() == "service not configured in this context"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_get_jwt_header_for() {
+        let owner = User::new(uuid::Uuid::new_v4());
+        let other = uuid::Uuid::new_v4();
+        let svc = get_jwt::Svc::from_service(
+            tower::service_fn(move |req: get_jwt::Request| async move {
+                if req.user_id == owner.id {
+                    Ok(get_jwt::Response {
+                        access_token: "token".to_owned(),
+                    })
+                } else {
+                    Err(get_jwt::Error::WrongRecipient {
+                        user_id: req.user_id,
+                    })
+                }
+            }),
+            get_jwt::Error::worker,
+            32,
+        );
+        let mut ctx = Context::builder(ContextConfig::default())
+            .flow_owner(owner)
+            .get_jwt(svc)
+            .build()
+            .unwrap();
+        assert_eq!(ctx.get_jwt_header().await.unwrap(), "Bearer token");
+        assert!(matches!(
+            ctx.get_jwt_header_for(other).await,
+            Err(get_jwt::Error::WrongRecipient { user_id }) if user_id == other
+        ));
+    }
+
+    #[test]
+    fn test_extensions() {
+        use std::sync::{
+            atomic::{AtomicU64, Ordering},
+            Mutex,
+        };
+
+        let mut ctx = Context::builder(ContextConfig::default())
+            .flow_owner(User::default())
+            .register(AtomicU64::new(0))
+            .build()
+            .unwrap();
+        assert!(ctx.insert(Mutex::new(Vec::<u64>::new())).unwrap().is_none());
+        ctx.get_mut::<Mutex<Vec<u64>>>()
+            .unwrap()
+            .get_mut()
+            .unwrap()
+            .push(1);
+
+        let shared = ctx.clone();
+        assert!(ctx.get_mut::<AtomicU64>().is_none());
+        assert_eq!(ctx.insert(1u8), Err(1u8));
+        shared
+            .get::<AtomicU64>()
+            .unwrap()
+            .fetch_add(1, Ordering::Relaxed);
+        assert_eq!(ctx.get::<AtomicU64>().unwrap().load(Ordering::Relaxed), 1);
+
+        drop(shared);
+        assert!(ctx.get_mut::<AtomicU64>().is_some());
+    }
+
+    #[test]
+    fn test_env() {
+        let mut ctx = Context::default();
+        ctx.environment = [
+            ("AMOUNT", "100"),
+            ("ENABLED", "Yes"),
+            ("TIMEOUT", "500ms"),
+            ("PUBKEY", "11111111111111111111111111111111"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_owned(), v.to_owned()))
+        .collect();
+        assert_eq!(ctx.env_get::<u64>("AMOUNT").unwrap(), Some(100));
+        assert_eq!(ctx.env_get::<u64>("NOT_SET").unwrap(), None);
+        assert!(ctx.env_require::<bool>("ENABLED").unwrap());
+        assert_eq!(
+            ctx.env_require::<Duration>("TIMEOUT").unwrap(),
+            Duration::from_millis(500)
+        );
+        assert_eq!(
+            ctx.env_require::<Pubkey>("PUBKEY").unwrap(),
+            solana_sdk::system_program::ID
+        );
+        assert_eq!(
+            ctx.env_require::<bool>("NOT_SET").unwrap_err(),
+            env::Error::Missing("NOT_SET".to_owned())
+        );
+        assert!(matches!(
+            ctx.env_get::<u64>("ENABLED"),
+            Err(env::Error::Invalid { .. })
+        ));
+    }
 }
@@ -1,57 +1,351 @@
 use crate::{
-    context::{execute::Error, signer},
-    FlowRunId, SolanaNet,
+    config::SolanaConfig,
+    context::{env, execute::Error, priority_fee, signer},
+    FlowRunId, SolanaClientConfig, SolanaNet,
 };
 use anyhow::{anyhow, bail, ensure};
+use base64::prelude::*;
 use borsh::BorshDeserialize;
 use bytes::Bytes;
 use chrono::Utc;
 use futures::TryStreamExt;
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
-use serde_with::{serde_as, serde_conv, DisplayFromStr};
+use serde_with::{serde_as, serde_conv, DisplayFromStr, DurationSecondsWithFrac};
+use solana_address_lookup_table_program::state::AddressLookupTable;
 use solana_client::{
-    nonblocking::rpc_client::RpcClient, rpc_client::SerializableTransaction,
-    rpc_config::RpcSendTransactionConfig,
+    client_error::ClientError,
+    http_sender::HttpSender,
+    nonblocking::rpc_client::RpcClient,
+    rpc_client::{RpcClientConfig, SerializableTransaction},
+    rpc_config::{RpcSendTransactionConfig, RpcSimulateTransactionConfig},
 };
 use solana_sdk::{
+    address_lookup_table_account::AddressLookupTableAccount,
+    clock::MAX_HASH_AGE_IN_SECONDS,
     commitment_config::{CommitmentConfig, CommitmentLevel},
     compute_budget::{self, ComputeBudgetInstruction},
     feature_set::FeatureSet,
+    hash::Hash,
     instruction::{AccountMeta, CompiledInstruction, Instruction},
-    message::Message,
+    message::{v0, Message, VersionedMessage},
+    packet::PACKET_DATA_SIZE,
     sanitize::Sanitize,
     signature::Presigner,
     signer::Signer,
-    transaction::Transaction,
+    system_instruction::{self, SystemInstruction},
+    system_program,
+    transaction::{Transaction, TransactionError, VersionedTransaction},
 };
 use spo_helius::{
     GetPriorityFeeEstimateOptions, GetPriorityFeeEstimateRequest, Helius, PriorityLevel,
 };
 use std::{
     borrow::Cow,
-    collections::{BTreeSet, HashMap},
+    collections::{BTreeMap, BTreeSet, HashMap},
     convert::Infallible,
     fmt::Display,
+    future::Future,
     num::ParseIntError,
+    ops::Range,
     str::FromStr,
-    time::Duration,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 use tower::ServiceExt;
 use value::{ConstBytes, Value};
 
 pub const SIGNATURE_TIMEOUT: Duration = Duration::from_secs(3 * 60);
 
+/// Percentile of recent priority fees to pay when Helius is not available.
+pub const DEFAULT_FEE_PERCENTILE: u8 = 75;
+
 pub use solana_sdk::pubkey::Pubkey;
 pub use solana_sdk::signature::Signature;
 pub use solana_sdk::signer::keypair::Keypair;
 
+pub mod anchor;
+pub mod failover;
+pub mod jito;
+pub mod lookup_table;
+pub mod rate_limit;
+pub mod token;
+
 pub mod utils;
 pub use utils::*;
 
 pub mod watcher;
 pub use watcher::*;
 
+/// Create a client of the endpoints in `config`, failover and rate limit are only
+/// used if configured.
+pub fn new_rpc_client(
+    config: &SolanaClientConfig,
+    timeout: Duration,
+    commitment: CommitmentConfig,
+    confirm_transaction_initial_timeout: Duration,
+) -> RpcClient {
+    let rpc_config = RpcClientConfig {
+        commitment_config: commitment,
+        confirm_transaction_initial_timeout: Some(confirm_transaction_initial_timeout),
+    };
+    match (config.fallback_urls.is_empty(), &config.rate_limit) {
+        (true, None) => RpcClient::new_with_timeouts_and_commitment(
+            config.url.clone(),
+            timeout,
+            commitment,
+            confirm_transaction_initial_timeout,
+        ),
+        (false, None) => RpcClient::new_sender(
+            failover::FailoverSender::new(config.urls(), timeout, config.round_robin),
+            rpc_config,
+        ),
+        (true, Some(limit)) => RpcClient::new_sender(
+            rate_limit::RateLimitedSender::new(
+                HttpSender::new_with_timeout(config.url.clone(), timeout),
+                limit,
+            ),
+            rpc_config,
+        ),
+        (false, Some(limit)) => RpcClient::new_sender(
+            rate_limit::RateLimitedSender::new(
+                failover::FailoverSender::new(config.urls(), timeout, config.round_robin),
+                limit,
+            ),
+            rpc_config,
+        ),
+    }
+}
+
+/// Turn a [`signer::SignManyResponse`] into an error if some pubkeys failed to sign.
+///
+/// Returns [`Error::Canceled`] if a request was canceled, or [`Error::Timeout`] if all
+/// failures are timeouts.
+fn all_signatures(resp: signer::SignManyResponse) -> Result<BTreeMap<Pubkey, Signature>, Error> {
+    if resp.is_complete() {
+        return Ok(resp.signatures);
+    }
+    if resp
+        .failures
+        .iter()
+        .any(|(_, e)| matches!(e, signer::Error::Canceled))
+    {
+        return Err(Error::Canceled(None));
+    }
+    if resp
+        .failures
+        .iter()
+        .all(|(_, e)| matches!(e, signer::Error::Timeout))
+    {
+        return Err(Error::Timeout);
+    }
+    let failures = resp
+        .failures
+        .iter()
+        .map(|(pubkey, error)| format!("{}: {}", pubkey, error))
+        .collect::<Vec<_>>()
+        .join("; ");
+    Err(Error::other(format!("signature failed: {}", failures)))
+}
+
+/// Simulate if [`ExecutionConfig::simulate_first`] is set, send with
+/// [`ExecutionConfig::submission`] and wait for confirmation.
+async fn submit<T: SerializableTransaction + Serialize>(
+    rpc: &RpcClient,
+    tx: &T,
+    inserted: usize,
+    config: &ExecutionConfig,
+    deadline: Option<Instant>,
+) -> Result<(Signature, ConfirmStatus, usize), Error> {
+    static JITO_HTTP: Lazy<reqwest::Client> = Lazy::new(reqwest::Client::new);
+
+    if config.simulate_first {
+        let simulation = simulate(rpc, tx, config.tx_commitment_level, inserted).await?;
+        tracing::info!(
+            "simulation succeeded, units consumed: {:?}",
+            simulation.units_consumed
+        );
+        for log in &simulation.logs {
+            tracing::debug!("{}", log);
+        }
+    }
+
+    let signature = match &config.submission {
+        SubmissionMode::Rpc => {
+            let signature = rpc
+                .send_transaction_with_config(tx, config.send_config())
+                .await
+                .map_err(move |error| Error::solana(error, inserted))?;
+            tracing::info!("submitted {}", signature);
+            signature
+        }
+        SubmissionMode::JitoBundle {
+            block_engine_url, ..
+        } => {
+            let url = block_engine_url
+                .as_deref()
+                .unwrap_or(jito::DEFAULT_BLOCK_ENGINE_URL);
+            let data = bincode::serialize(tx).map_err(Error::other)?;
+            let signature = *tx.get_signature();
+            let bundle_id = jito::send_bundle(&JITO_HTTP, url, &[data]).await?;
+            tracing::info!("submitted bundle {}, transaction {}", bundle_id, signature);
+
+            // the bundle can't land after the blockhash expired
+            let expiry = Instant::now() + Duration::from_secs(MAX_HASH_AGE_IN_SECONDS as u64);
+            let bundle_deadline = deadline.map_or(expiry, |deadline| deadline.min(expiry));
+            if jito::wait_for_bundle(&JITO_HTTP, url, &bundle_id, bundle_deadline)
+                .await?
+                .is_none()
+            {
+                let status = if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    ConfirmStatus::TimedOut
+                } else {
+                    ConfirmStatus::Expired
+                };
+                return Ok((signature, status, inserted));
+            }
+            signature
+        }
+    };
+
+    let status = config
+        .confirm(rpc, &signature, *tx.get_recent_blockhash(), deadline)
+        .await
+        .map_err(move |error| Error::solana(error, inserted))?;
+
+    Ok((signature, status, inserted))
+}
+
+/// Slot and block time of a confirmed transaction, `None` if the RPC node can't
+/// return them.
+pub async fn landed_at(rpc: &RpcClient, signature: &Signature) -> (Option<u64>, Option<i64>) {
+    let slot = match rpc.get_signature_statuses(&[*signature]).await {
+        Ok(resp) => resp
+            .value
+            .into_iter()
+            .next()
+            .flatten()
+            .map(|status| status.slot),
+        Err(error) => {
+            tracing::warn!("can't get slot of {}: {}", signature, error);
+            None
+        }
+    };
+    let block_time = match slot {
+        Some(slot) => rpc
+            .get_block_time(slot)
+            .await
+            .map_err(|error| tracing::warn!("can't get block time of slot {}: {}", slot, error))
+            .ok(),
+        None => None,
+    };
+    (slot, block_time)
+}
+
+/// Add the missing signatures of a transaction built outside of the flow, e.g. a swap
+/// transaction from an aggregator API. Existing signatures are kept, so the message can't
+/// be changed: no priority fee is inserted and the blockhash is not replaced.
+///
+/// Missing signatures are made with `keypairs`, adapter wallets and pubkeys that are not
+/// in `keypairs` are requested from `signer`.
+pub async fn sign_transaction(
+    mut tx: VersionedTransaction,
+    keypairs: &[Keypair],
+    signer: &signer::Svc,
+    flow_run_id: Option<FlowRunId>,
+) -> Result<VersionedTransaction, Error> {
+    let required = tx.message.header().num_required_signatures as usize;
+    let keys = tx.message.static_account_keys();
+    if required == 0 || keys.len() < required {
+        return Err(Error::other("invalid transaction: missing signer accounts"));
+    }
+    tx.signatures.resize(required, Signature::default());
+    let data: Bytes = tx.message.serialize().into();
+    check_tx_size(signed_tx_size(required as u8, &data))?;
+
+    let mut presigners = Vec::new();
+    let mut wallets = Vec::new();
+    for (index, pubkey) in keys[..required].iter().enumerate() {
+        if tx.signatures[index] != Signature::default() {
+            presigners.push(signer::Presigner {
+                pubkey: *pubkey,
+                signature: tx.signatures[index],
+            });
+            continue;
+        }
+        match keypairs
+            .iter()
+            .find(|k| k.pubkey() == *pubkey && !k.is_adapter_wallet())
+        {
+            Some(keypair) => tx.signatures[index] = keypair.sign_message(&data),
+            None => wallets.push(*pubkey),
+        }
+    }
+    if wallets.is_empty() {
+        return Ok(tx);
+    }
+
+    let fee_payer = keys[0];
+    let mut resp = signer::sign_many(
+        signer,
+        signer::SignManyRequest {
+            pubkeys: wallets,
+            message: data,
+            timeout: SIGNATURE_TIMEOUT,
+            flow_run_id,
+            signatures: (!presigners.is_empty()).then_some(presigners),
+        },
+    )
+    .await;
+    if let Some(index) = resp
+        .failures
+        .iter()
+        .position(|(pubkey, _)| *pubkey == fee_payer)
+    {
+        let (pubkey, error) = resp.failures.swap_remove(index);
+        return Err(fee_payer_error(&pubkey, error));
+    }
+    let signatures = all_signatures(resp)?;
+    for (index, pubkey) in keys[..required].iter().enumerate() {
+        if let Some(signature) = signatures.get(pubkey) {
+            tx.signatures[index] = *signature;
+        }
+    }
+    Ok(tx)
+}
+
+/// Sign a transaction with [`sign_transaction`], send it and wait for confirmation.
+///
+/// The transaction can't be re-signed with a new blockhash, so it is not sent again
+/// when it expires.
+pub async fn execute_transaction(
+    tx: VersionedTransaction,
+    keypairs: &[Keypair],
+    rpc: &RpcClient,
+    signer: signer::Svc,
+    flow_run_id: Option<FlowRunId>,
+    config: &ExecutionConfig,
+) -> Result<Signature, Error> {
+    let tx = sign_transaction(tx, keypairs, &signer, flow_run_id).await?;
+    let deadline = config
+        .confirm_timeout
+        .map(|timeout| Instant::now() + timeout);
+    let (signature, status, _) = submit(rpc, &tx, 0, config, deadline).await?;
+    match status {
+        ConfirmStatus::Confirmed => Ok(signature),
+        ConfirmStatus::TimedOut => Err(Error::Timeout),
+        ConfirmStatus::Expired => Err(Error::solana(unable_to_confirm(), 0)),
+    }
+}
+
+fn fee_payer_error(fee_payer: &Pubkey, error: signer::Error) -> Error {
+    match error {
+        signer::Error::Timeout => Error::Timeout,
+        signer::Error::Canceled => Error::Canceled(None),
+        error => Error::other(format!("can't sign for fee payer {}: {}", fee_payer, error)),
+    }
+}
+
 /// `l` is old, `r` is new
 pub fn is_same_message_logic(l: &[u8], r: &[u8]) -> Result<Message, anyhow::Error> {
     let l = bincode::deserialize::<Message>(l)?;
@@ -251,6 +545,86 @@ pub struct Instructions {
     pub instructions: Vec<Instruction>,
 }
 
+/// Longest memo that fits in a transaction with a single signer, in bytes.
+pub const MAX_MEMO_LEN: usize = 566;
+
+use token::{ASSOCIATED_TOKEN_PROGRAM_ID, TOKEN_2022_PROGRAM_ID};
+
+/// Size of a token account created by the associated token account program.
+const TOKEN_ACCOUNT_SIZE: usize = 165;
+/// Token-2022 accounts created by the associated token account program have the
+/// `ImmutableOwner` extension.
+const TOKEN_2022_ACCOUNT_SIZE: usize = 170;
+
+/// An account created and funded by the fee payer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct CreatedAccount {
+    address: Pubkey,
+    space: usize,
+    /// Lamports transferred to the account, `0` if it's decided by the program.
+    lamports: u64,
+    /// The account might already exist, e.g. `CreateIdempotent`.
+    idempotent: bool,
+}
+
+/// Find accounts that `fee_payer` pays for in `instructions`.
+fn created_accounts(fee_payer: &Pubkey, instructions: &[Instruction]) -> Vec<CreatedAccount> {
+    instructions
+        .iter()
+        .filter(|i| i.accounts.first().is_some_and(|a| a.pubkey == *fee_payer))
+        .filter_map(|i| {
+            let address = i.accounts.get(1)?.pubkey;
+            if i.program_id == system_program::ID {
+                match bincode::deserialize::<SystemInstruction>(&i.data).ok()? {
+                    SystemInstruction::CreateAccount {
+                        lamports, space, ..
+                    }
+                    | SystemInstruction::CreateAccountWithSeed {
+                        lamports, space, ..
+                    } => Some(CreatedAccount {
+                        address,
+                        space: space as usize,
+                        lamports,
+                        idempotent: false,
+                    }),
+                    _ => None,
+                }
+            } else if i.program_id == ASSOCIATED_TOKEN_PROGRAM_ID {
+                let token_program = i.accounts.get(5)?.pubkey;
+                let space = if token_program == TOKEN_2022_PROGRAM_ID {
+                    TOKEN_2022_ACCOUNT_SIZE
+                } else {
+                    TOKEN_ACCOUNT_SIZE
+                };
+                // 0 or empty: Create, 1: CreateIdempotent
+                let idempotent = match i.data.first() {
+                    None | Some(0) => false,
+                    Some(1) => true,
+                    Some(_) => return None,
+                };
+                Some(CreatedAccount {
+                    address,
+                    space,
+                    lamports: 0,
+                    idempotent,
+                })
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+impl Clone for Instructions {
+    fn clone(&self) -> Self {
+        Self {
+            fee_payer: self.fee_payer,
+            signers: self.signers.iter().map(|k| k.clone_keypair()).collect(),
+            instructions: self.instructions.clone(),
+        }
+    }
+}
+
 fn is_set_compute_unit_price(
     message: &Message,
     index: usize,
@@ -449,6 +823,59 @@ pub struct ExecutionConfig {
 
     #[serde(skip)]
     pub execute_on: ExecuteOn,
+
+    /// Compile a v0 transaction using these lookup tables, set with
+    /// [`ExecuteOptions::lookup_tables`].
+    #[serde(skip)]
+    pub lookup_tables: Vec<Pubkey>,
+    /// Use a durable nonce instead of a recent blockhash, set with
+    /// [`ExecuteOptions::nonce_account`].
+    #[serde(skip)]
+    pub durable_nonce: Option<DurableNonce>,
+    /// Simulate the signed transaction before sending, set with
+    /// [`ExecuteOptions::simulate_first`].
+    #[serde(skip)]
+    pub simulate_first: bool,
+    /// Options of `sendTransaction`, set with [`ExecuteOptions`].
+    #[serde(skip)]
+    pub skip_preflight: bool,
+    #[serde(skip)]
+    pub preflight_commitment_level: Option<CommitmentLevel>,
+    #[serde(skip)]
+    pub send_max_retries: Option<usize>,
+    /// Don't use [`BLOCKHASH_CACHE`], set with [`ExecuteOptions::force_fresh_blockhash`].
+    ///
+    /// Identical transactions sent within [`BLOCKHASH_TTL`] of each other get the same
+    /// blockhash, hence the same signature, and the cluster only processes one of them.
+    /// Set this when a flow sends the same transaction repeatedly, e.g. a transfer in a
+    /// loop.
+    #[serde(skip)]
+    pub force_fresh_blockhash: bool,
+    /// Confirmation strategy, set with [`ExecuteOptions::confirm_timeout`] and
+    /// [`ExecuteOptions::max_resends`].
+    #[serde(skip)]
+    pub confirm_timeout: Option<Duration>,
+    #[serde(skip)]
+    pub max_resends: u32,
+    /// Set with [`ExecuteOptions::confirm_strategy`].
+    #[serde(skip)]
+    pub confirm_strategy: ConfirmStrategy,
+    /// WebSocket endpoint of the RPC, required by [`ConfirmStrategy::WebSocket`].
+    #[serde(skip)]
+    pub ws_url: Option<String>,
+    /// Set with [`ExecuteOptions::fee_payer`].
+    #[serde(skip)]
+    pub fee_payer: Option<Pubkey>,
+    /// Set with [`ExecuteOptions::submission`].
+    #[serde(skip)]
+    pub submission: SubmissionMode,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DurableNonce {
+    pub account: Pubkey,
+    /// Default to fee payer.
+    pub authority: Option<Pubkey>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -474,6 +901,30 @@ impl ExecutionConfig {
             .collect::<value::Map>();
         value::from_map(map)
     }
+
+    /// Fill settings that are not set by `environment`, which was parsed with
+    /// [`ExecutionConfig::from_env`], with the deployment's defaults.
+    pub fn with_defaults(
+        mut self,
+        defaults: &SolanaConfig,
+        environment: &HashMap<String, String>,
+    ) -> Self {
+        if let Some(level) = defaults.commitment {
+            if !environment.contains_key(env::TX_COMMITMENT_LEVEL) {
+                self.tx_commitment_level = level;
+            }
+            if !environment.contains_key(env::WAIT_COMMITMENT_LEVEL) {
+                self.wait_commitment_level = level;
+            }
+        }
+        if self.confirm_timeout.is_none() {
+            self.confirm_timeout = defaults.confirm_timeout();
+        }
+        if self.send_max_retries.is_none() {
+            self.send_max_retries = defaults.send_max_retries;
+        }
+        self
+    }
 }
 
 impl Default for ExecutionConfig {
@@ -487,6 +938,260 @@ impl Default for ExecutionConfig {
             tx_commitment_level: default_tx_level(),
             wait_commitment_level: default_wait_level(),
             execute_on: ExecuteOn::default(),
+            lookup_tables: Vec::new(),
+            durable_nonce: None,
+            simulate_first: false,
+            skip_preflight: false,
+            preflight_commitment_level: None,
+            send_max_retries: None,
+            force_fresh_blockhash: false,
+            confirm_timeout: None,
+            max_resends: 0,
+            confirm_strategy: ConfirmStrategy::default(),
+            ws_url: None,
+            fee_payer: None,
+            submission: SubmissionMode::default(),
+        }
+    }
+}
+
+/// Options of a single [`execute`][crate::context::execute] request, overriding
+/// [`ExecutionConfig`] of the flow.
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExecuteOptions {
+    /// Commitment used to get blockhash, run preflight checks and confirm the transaction.
+    ///
+    /// `processed` returns fastest but the transaction might be on a fork that gets
+    /// dropped, `finalized` is safe from rollbacks but takes around 13 seconds longer
+    /// than `confirmed`. Default to `confirmed`.
+    #[serde(default)]
+    pub commitment: Option<CommitmentConfig>,
+    /// Insert `SetComputeUnitPrice` with this price, instead of
+    /// [`ExecutionConfig::priority_fee`].
+    #[serde(default)]
+    pub priority_fee_micro_lamports: Option<u64>,
+    /// Insert `SetComputeUnitLimit` with this limit, instead of
+    /// [`ExecutionConfig::compute_budget`].
+    #[serde(default)]
+    pub compute_unit_limit: Option<u32>,
+    /// Address lookup tables used to compile a v0 transaction, legacy transaction is
+    /// used if empty.
+    #[serde_as(as = "Vec<DisplayFromStr>")]
+    #[serde(default)]
+    pub lookup_tables: Vec<Pubkey>,
+    /// Use the nonce stored in this account instead of a recent blockhash, so that the
+    /// transaction doesn't expire while waiting for signatures.
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    pub nonce_account: Option<Pubkey>,
+    /// Authority of `nonce_account`, default to fee payer.
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    pub nonce_authority: Option<Pubkey>,
+    /// Simulate the signed transaction before sending, fail with program logs if the
+    /// simulation fails.
+    #[serde(default)]
+    pub simulate_first: bool,
+    /// Skip preflight checks of `sendTransaction`.
+    #[serde(default)]
+    pub skip_preflight: bool,
+    /// Commitment of preflight checks, default to the transaction's commitment.
+    #[serde(default)]
+    pub preflight_commitment: Option<CommitmentLevel>,
+    /// Maximum number of times the RPC node retries sending the transaction,
+    /// default to the node's setting.
+    #[serde(default)]
+    pub max_retries: Option<usize>,
+    /// Always get a new blockhash instead of using [`BLOCKHASH_CACHE`], needed to send the
+    /// same transaction more than once within [`BLOCKHASH_TTL`], see
+    /// [`ExecutionConfig::force_fresh_blockhash`].
+    #[serde(default)]
+    pub force_fresh_blockhash: bool,
+    /// Give up waiting for confirmation after this long, including resends.
+    #[serde_as(as = "Option<DurationSecondsWithFrac<f64>>")]
+    #[serde(default)]
+    pub confirm_timeout: Option<Duration>,
+    /// Re-sign with a new blockhash and send again up to this many times if the
+    /// transaction expired before being processed.
+    #[serde(default)]
+    pub max_resends: Option<u32>,
+    /// How to wait for confirmation, default to polling.
+    #[serde(default)]
+    pub confirm_strategy: Option<ConfirmStrategy>,
+    /// Pay fees with this account instead of the instructions' fee payer, e.g. a relayer
+    /// or sponsor account. It must be signable by the signer service.
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    pub fee_payer: Option<Pubkey>,
+    /// How to send the transaction, default to the RPC.
+    #[serde(default)]
+    pub submission: Option<SubmissionMode>,
+}
+
+/// How to send transactions.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum SubmissionMode {
+    /// `sendTransaction` of the RPC.
+    #[default]
+    Rpc,
+    /// Send as a bundle to a Jito block engine, in MEV-protected auctions.
+    JitoBundle {
+        /// Add a tip transfer to the transaction, bundles without a tip might not land.
+        #[serde(default)]
+        tip_lamports: Option<u64>,
+        /// Default to [`jito::DEFAULT_BLOCK_ENGINE_URL`].
+        #[serde(default)]
+        block_engine_url: Option<String>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfirmStrategy {
+    /// Poll signature status.
+    #[default]
+    Polling,
+    /// Use `signatureSubscribe`, fall back to polling if the WebSocket endpoint is not
+    /// available.
+    WebSocket,
+}
+
+impl ExecuteOptions {
+    /// Combine options of bundled requests, options that are set first take precedence,
+    /// except priority fee and compute unit limit which use the highest value.
+    pub fn merge(&mut self, other: &Self) {
+        self.commitment = self.commitment.or(other.commitment);
+        self.priority_fee_micro_lamports = self
+            .priority_fee_micro_lamports
+            .max(other.priority_fee_micro_lamports);
+        self.compute_unit_limit = self.compute_unit_limit.max(other.compute_unit_limit);
+        self.simulate_first |= other.simulate_first;
+        self.skip_preflight |= other.skip_preflight;
+        self.force_fresh_blockhash |= other.force_fresh_blockhash;
+        self.preflight_commitment = self.preflight_commitment.or(other.preflight_commitment);
+        self.max_retries = self.max_retries.or(other.max_retries);
+        self.confirm_timeout = self.confirm_timeout.or(other.confirm_timeout);
+        self.max_resends = self.max_resends.max(other.max_resends);
+        self.confirm_strategy = self.confirm_strategy.or(other.confirm_strategy);
+        self.fee_payer = self.fee_payer.or(other.fee_payer);
+        if self.submission.is_none() {
+            self.submission.clone_from(&other.submission);
+        }
+        if self.nonce_account.is_none() {
+            self.nonce_account = other.nonce_account;
+            self.nonce_authority = other.nonce_authority;
+        }
+        for table in &other.lookup_tables {
+            if !self.lookup_tables.contains(table) {
+                self.lookup_tables.push(*table);
+            }
+        }
+    }
+}
+
+impl ExecutionConfig {
+    pub fn with_options(mut self, options: &ExecuteOptions) -> Self {
+        if let Some(commitment) = options.commitment {
+            self.tx_commitment_level = commitment.commitment;
+            self.wait_commitment_level = commitment.commitment;
+        }
+        if let Some(fee) = options.priority_fee_micro_lamports {
+            self.priority_fee = InsertionBehavior::Value(fee);
+        }
+        if let Some(limit) = options.compute_unit_limit {
+            self.compute_budget = InsertionBehavior::Value(limit as u64);
+        }
+        if !options.lookup_tables.is_empty() {
+            self.lookup_tables = options.lookup_tables.clone();
+        }
+        self.simulate_first |= options.simulate_first;
+        self.skip_preflight |= options.skip_preflight;
+        self.force_fresh_blockhash |= options.force_fresh_blockhash;
+        if let Some(level) = options.preflight_commitment {
+            self.preflight_commitment_level = Some(level);
+        }
+        if let Some(max_retries) = options.max_retries {
+            self.send_max_retries = Some(max_retries);
+        }
+        if let Some(timeout) = options.confirm_timeout {
+            self.confirm_timeout = Some(timeout);
+        }
+        if let Some(max_resends) = options.max_resends {
+            self.max_resends = max_resends;
+        }
+        if let Some(strategy) = options.confirm_strategy {
+            self.confirm_strategy = strategy;
+        }
+        if let Some(fee_payer) = options.fee_payer {
+            self.fee_payer = Some(fee_payer);
+        }
+        if let Some(submission) = &options.submission {
+            self.submission = submission.clone();
+        }
+        if let Some(account) = options.nonce_account {
+            self.durable_nonce = Some(DurableNonce {
+                account,
+                authority: options.nonce_authority,
+            });
+        }
+        self
+    }
+
+    async fn latest_blockhash(&self, rpc: &RpcClient) -> Result<Hash, ClientError> {
+        if self.force_fresh_blockhash {
+            Ok(rpc
+                .get_latest_blockhash_with_commitment(commitment(self.tx_commitment_level))
+                .await?
+                .0)
+        } else {
+            BLOCKHASH_CACHE.get(rpc, self.tx_commitment_level).await
+        }
+    }
+
+    pub fn send_config(&self) -> RpcSendTransactionConfig {
+        RpcSendTransactionConfig {
+            skip_preflight: self.skip_preflight,
+            preflight_commitment: Some(
+                self.preflight_commitment_level
+                    .unwrap_or(self.tx_commitment_level),
+            ),
+            max_retries: self.send_max_retries,
+            ..<_>::default()
+        }
+    }
+
+    async fn confirm(
+        &self,
+        rpc: &RpcClient,
+        signature: &Signature,
+        blockhash: Hash,
+        deadline: Option<Instant>,
+    ) -> Result<ConfirmStatus, ClientError> {
+        let lifetime = self.tx_lifetime(blockhash);
+        let commitment = commitment(self.wait_commitment_level);
+        match (self.confirm_strategy, &self.ws_url) {
+            (ConfirmStrategy::WebSocket, Some(ws_url)) => {
+                subscribe_confirmation(rpc, ws_url, signature, lifetime, commitment, deadline).await
+            }
+            (ConfirmStrategy::WebSocket, None) => {
+                tracing::warn!("no WebSocket endpoint, polling signature status instead");
+                poll_confirmation(rpc, signature, lifetime, commitment, deadline).await
+            }
+            (ConfirmStrategy::Polling, _) => {
+                poll_confirmation(rpc, signature, lifetime, commitment, deadline).await
+            }
+        }
+    }
+
+    fn tx_lifetime(&self, blockhash: Hash) -> TxLifetime {
+        match &self.durable_nonce {
+            Some(nonce) => TxLifetime::Nonce {
+                account: nonce.account,
+                nonce: blockhash,
+            },
+            None => TxLifetime::Blockhash(blockhash),
         }
     }
 }
@@ -495,6 +1200,194 @@ fn commitment(commitment: CommitmentLevel) -> CommitmentConfig {
     CommitmentConfig { commitment }
 }
 
+/// How long a blockhash is reused, blockhashes are valid for about 60 seconds.
+pub const BLOCKHASH_TTL: Duration = Duration::from_secs(2);
+
+/// Reuse recent blockhash to reduce RPC calls when sending many transactions.
+pub static BLOCKHASH_CACHE: Lazy<BlockhashCache> = Lazy::new(|| BlockhashCache::new(BLOCKHASH_TTL));
+
+/// Blockhash cache keyed by RPC URL and commitment level.
+pub struct BlockhashCache {
+    ttl: Duration,
+    entries: std::sync::Mutex<HashMap<(String, CommitmentLevel), BlockhashEntry>>,
+}
+
+/// Locked while the blockhash of a key is fetched, so that a slow RPC only blocks
+/// callers of the same key.
+type BlockhashEntry = Arc<futures::lock::Mutex<Option<(Hash, Instant)>>>;
+
+impl BlockhashCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: <_>::default(),
+        }
+    }
+
+    pub async fn get(&self, rpc: &RpcClient, level: CommitmentLevel) -> Result<Hash, ClientError> {
+        self.get_or_fetch((rpc.url(), level), move || async move {
+            Ok(rpc
+                .get_latest_blockhash_with_commitment(commitment(level))
+                .await?
+                .0)
+        })
+        .await
+    }
+
+    /// Return cached blockhash of `key` or call `fetch`, concurrent calls wait for the
+    /// same fetch.
+    pub async fn get_or_fetch<F, Fut, E>(
+        &self,
+        key: (String, CommitmentLevel),
+        fetch: F,
+    ) -> Result<Hash, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Hash, E>>,
+    {
+        let entry = self.entries.lock().unwrap().entry(key).or_default().clone();
+        let mut entry = entry.lock().await;
+        if let Some((hash, time)) = *entry {
+            if time.elapsed() < self.ttl {
+                return Ok(hash);
+            }
+        }
+        let hash = fetch().await?;
+        *entry = Some((hash, Instant::now()));
+        Ok(hash)
+    }
+}
+
+/// Result of a successful simulation.
+#[derive(Debug, Clone)]
+pub struct Simulation {
+    pub units_consumed: Option<u64>,
+    pub logs: Vec<String>,
+}
+
+/// Simulation error with program logs.
+#[derive(Debug, Clone)]
+pub struct SimulationFailure {
+    pub error: TransactionError,
+    pub logs: Vec<String>,
+    /// Number of instructions inserted by the executor.
+    pub inserted: usize,
+}
+
+impl SimulationFailure {
+    /// Index of the failed instruction, not counting inserted instructions.
+    pub fn failed_instruction(&self) -> Option<usize> {
+        match &self.error {
+            TransactionError::InstructionError(index, _) => {
+                (*index as usize).checked_sub(self.inserted)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Display for SimulationFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.error.fmt(f)?;
+        if !self.logs.is_empty() {
+            f.write_str("\nlogs:")?;
+            for log in &self.logs {
+                write!(f, "\n{}", log)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Simulate a signed transaction, return [`Error::TxSimFailed`] with program logs if it
+/// fails.
+pub async fn simulate(
+    rpc: &RpcClient,
+    tx: &impl SerializableTransaction,
+    commitment_level: CommitmentLevel,
+    inserted: usize,
+) -> Result<Simulation, Error> {
+    let result = rpc
+        .simulate_transaction_with_config(
+            tx,
+            RpcSimulateTransactionConfig {
+                commitment: Some(commitment(commitment_level)),
+                ..<_>::default()
+            },
+        )
+        .await
+        .map_err(|error| Error::solana(error, inserted))?
+        .value;
+    let logs = result.logs.unwrap_or_default();
+    match result.err {
+        Some(error) => Err(Error::TxSimFailed(Some(Arc::new(SimulationFailure {
+            error,
+            logs,
+            inserted,
+        })))),
+        None => Ok(Simulation {
+            units_consumed: result.units_consumed,
+            logs,
+        }),
+    }
+}
+
+/// Fetch and parse address lookup tables.
+pub async fn get_lookup_tables(
+    rpc: &RpcClient,
+    keys: &[Pubkey],
+) -> Result<Vec<AddressLookupTableAccount>, Error> {
+    let accounts = rpc
+        .get_multiple_accounts(keys)
+        .await
+        .map_err(|error| Error::solana(error, 0))?;
+    keys.iter()
+        .zip(accounts)
+        .map(|(key, account)| {
+            let account =
+                account.ok_or_else(|| Error::other(format!("lookup table {} not found", key)))?;
+            let table = AddressLookupTable::deserialize(&account.data).map_err(|error| {
+                Error::other(format!("invalid lookup table {}: {}", key, error))
+            })?;
+            Ok(AddressLookupTableAccount {
+                key: *key,
+                addresses: table.addresses.to_vec(),
+            })
+        })
+        .collect()
+}
+
+fn compile_message_v0(
+    fee_payer: &Pubkey,
+    instructions: &[Instruction],
+    lookup_tables: &[AddressLookupTableAccount],
+    blockhash: Hash,
+) -> Result<VersionedMessage, Error> {
+    Ok(VersionedMessage::V0(
+        v0::Message::try_compile(fee_payer, instructions, lookup_tables, blockhash)
+            .map_err(Error::other)?,
+    ))
+}
+
+/// Size of the transaction of a serialized message once it is signed.
+fn signed_tx_size(num_required_signatures: u8, message: &[u8]) -> usize {
+    // the signature count is a short_vec, 1 byte for less than 128 signatures
+    1 + num_required_signatures as usize * 64 + message.len()
+}
+
+/// Return [`Error::TxTooLarge`] if the transaction can't be sent in a single packet,
+/// RPC nodes reject them with a less helpful error.
+fn check_tx_size(size: usize) -> Result<(), Error> {
+    if size > PACKET_DATA_SIZE {
+        Err(Error::TxTooLarge {
+            size,
+            max: PACKET_DATA_SIZE,
+        })
+    } else {
+        Ok(())
+    }
+}
+
 pub fn build_action_reference(timestamp: i64, run_id: FlowRunId) -> Vec<u8> {
     let reference_bytes = [
         timestamp.to_le_bytes().as_ref(),
@@ -558,9 +1451,11 @@ async fn action_identity_memo(
             time: Utc::now(),
             pubkey: identity,
             message: reference_bytes.into(),
+            kind: signer::MessageKind::Transaction,
             timeout: SIGNATURE_TIMEOUT,
             flow_run_id: Some(run_id),
             signatures: None,
+            cancel: None,
         })
         .await?
         .signature;
@@ -596,6 +1491,22 @@ impl Instructions {
         self.push_signer(signer);
     }
 
+    /// Use `fee_payer` instead of the current fee payer, e.g. a relayer paying for the
+    /// user. Its signature is requested with the signer service if its keypair is not in
+    /// `signers`. The old fee payer is removed from `signers` if no instruction needs it.
+    pub fn override_fee_payer(&mut self, fee_payer: Pubkey) {
+        let old = std::mem::replace(&mut self.fee_payer, fee_payer);
+        let old_is_signer = self
+            .instructions
+            .iter()
+            .flat_map(|i| i.accounts.iter())
+            .any(|a| a.is_signer && a.pubkey == old);
+        if old != fee_payer && !old_is_signer {
+            self.signers.retain(|k| k.pubkey() != old);
+        }
+        self.push_signer(Keypair::new_adapter_wallet(fee_payer));
+    }
+
     pub fn combine(&mut self, next: Self) -> Result<(), Self> {
         if next.fee_payer != self.fee_payer {
             return Err(next);
@@ -610,22 +1521,155 @@ impl Instructions {
         Ok(())
     }
 
-    async fn insert_priority_fee(
-        &mut self,
-        rpc: &RpcClient,
-        config: &ExecutionConfig,
-    ) -> Result<usize, Error> {
-        let message = Message::new_with_blockhash(
-            &self.instructions,
-            Some(&self.fee_payer),
-            &rpc.get_latest_blockhash_with_commitment(commitment(
-                config.simulation_commitment_level,
-            ))
-            .await
-            .map_err(|error| Error::solana(error, 0))?
-            .0,
-        );
-        let count = self.instructions.len();
+    /// Append a SPL Memo instruction. Memos longer than [`MAX_MEMO_LEN`] are truncated
+    /// at a character boundary if `truncate` is set, otherwise an error is returned.
+    pub fn with_memo(&mut self, memo: String, truncate: bool) -> Result<(), Error> {
+        let mut memo = memo;
+        if memo.len() > MAX_MEMO_LEN {
+            if !truncate {
+                return Err(Error::other(format!(
+                    "memo is {} bytes, the limit is {} bytes",
+                    memo.len(),
+                    MAX_MEMO_LEN
+                )));
+            }
+            let mut len = MAX_MEMO_LEN;
+            while !memo.is_char_boundary(len) {
+                len -= 1;
+            }
+            memo.truncate(len);
+        }
+        self.instructions
+            .push(spl_memo::build_memo(memo.as_bytes(), &[]));
+        Ok(())
+    }
+
+    /// Partition the instructions into transactions of at most `max_size` bytes, to be
+    /// executed sequentially. Instructions in each range of `groups` are kept in the same
+    /// transaction, ranges must be sorted and must not overlap.
+    ///
+    /// Sizes are of legacy transactions with the instructions as they are now, leave room
+    /// for the compute budget instructions inserted by [`Instructions::execute`], e.g.
+    /// `PACKET_DATA_SIZE - 64`.
+    ///
+    /// Every transaction has the same fee payer, its signers are the fee payer and the
+    /// signers required by its own instructions.
+    pub fn split(self, max_size: usize, groups: &[Range<usize>]) -> Result<Vec<Self>, Error> {
+        let len = self.instructions.len();
+        let mut units = Vec::<Range<usize>>::new();
+        let mut start = 0;
+        for group in groups {
+            if group.start < start || group.start > group.end || group.end > len {
+                return Err(Error::other(format!(
+                    "invalid instruction group {:?}",
+                    group
+                )));
+            }
+            units.extend((start..group.start).map(|i| i..i + 1));
+            if !group.is_empty() {
+                units.push(group.clone());
+            }
+            start = group.end;
+        }
+        units.extend((start..len).map(|i| i..i + 1));
+
+        let tx_size = |range: Range<usize>| {
+            let message = Message::new(&self.instructions[range], Some(&self.fee_payer));
+            signed_tx_size(message.header.num_required_signatures, &message.serialize())
+        };
+        let mut chunks = Vec::<Range<usize>>::new();
+        for unit in units {
+            let size = tx_size(unit.clone());
+            if size > max_size {
+                return Err(Error::TxTooLarge {
+                    size,
+                    max: max_size,
+                });
+            }
+            match chunks.last_mut() {
+                Some(last) if tx_size(last.start..unit.end) <= max_size => last.end = unit.end,
+                _ => chunks.push(unit),
+            }
+        }
+
+        Ok(chunks
+            .into_iter()
+            .map(|range| {
+                let instructions = self.instructions[range].to_vec();
+                let signers = self
+                    .signers
+                    .iter()
+                    .filter(|k| {
+                        let pubkey = k.pubkey();
+                        pubkey == self.fee_payer
+                            || instructions
+                                .iter()
+                                .flat_map(|i| i.accounts.iter())
+                                .any(|a| a.is_signer && a.pubkey == pubkey)
+                    })
+                    .map(|k| k.clone_keypair())
+                    .collect();
+                Self {
+                    fee_payer: self.fee_payer,
+                    signers,
+                    instructions,
+                }
+            })
+            .collect())
+    }
+
+    /// Check that the fee payer can pay `fee` and rent of the accounts it creates.
+    async fn check_balance(&self, rpc: &RpcClient, fee: u64) -> Result<(), Error> {
+        let mut needed = fee;
+        let mut rents = HashMap::<usize, u64>::new();
+        for account in created_accounts(&self.fee_payer, &self.instructions) {
+            if account.idempotent
+                && rpc
+                    .get_account_with_commitment(&account.address, CommitmentConfig::confirmed())
+                    .await
+                    .map_err(|error| Error::solana(error, 0))?
+                    .value
+                    .is_some()
+            {
+                continue;
+            }
+            let rent = match rents.get(&account.space) {
+                Some(rent) => *rent,
+                None => {
+                    let rent = rpc
+                        .get_minimum_balance_for_rent_exemption(account.space)
+                        .await
+                        .map_err(|error| Error::solana(error, 0))?;
+                    rents.insert(account.space, rent);
+                    rent
+                }
+            };
+            needed = needed.saturating_add(account.lamports.max(rent));
+        }
+        let balance = rpc
+            .get_balance(&self.fee_payer)
+            .await
+            .map_err(|error| Error::solana(error, 0))?;
+        if balance < needed {
+            return Err(Error::InsufficientSolanaBalance { needed, balance });
+        }
+        Ok(())
+    }
+
+    async fn insert_priority_fee(
+        &mut self,
+        rpc: &RpcClient,
+        config: &ExecutionConfig,
+    ) -> Result<usize, Error> {
+        let message = Message::new_with_blockhash(
+            &self.instructions,
+            Some(&self.fee_payer),
+            &BLOCKHASH_CACHE
+                .get(rpc, config.simulation_commitment_level)
+                .await
+                .map_err(|error| Error::solana(error, 0))?,
+        );
+        let count = self.instructions.len();
 
         let mut inserted = 0;
 
@@ -680,12 +1724,25 @@ impl Instructions {
             let fee = if let InsertionBehavior::Value(x) = config.priority_fee {
                 x
             } else {
-                get_priority_fee(&message)
-                    .await
-                    .map_err(|error| {
+                match get_priority_fee(&message).await {
+                    Ok(fee) => fee,
+                    Err(error) => {
                         tracing::warn!("get_priority_fee error: {}", error);
-                    })
-                    .unwrap_or(100)
+                        let writable = message
+                            .account_keys
+                            .iter()
+                            .enumerate()
+                            .filter(|(i, _)| message.is_writable(*i))
+                            .map(|(_, pk)| *pk)
+                            .collect::<Vec<_>>();
+                        priority_fee::estimate(rpc, &writable, DEFAULT_FEE_PERCENTILE)
+                            .await
+                            .map_err(|error| {
+                                tracing::warn!("estimate priority fee error: {}", error);
+                            })
+                            .unwrap_or(100)
+                    }
+                }
             };
             tracing::info!("adding priority fee {}", fee);
             self.instructions
@@ -696,21 +1753,50 @@ impl Instructions {
         Ok(inserted)
     }
 
+    /// Get the latest blockhash, or the nonce of [`ExecutionConfig::durable_nonce`] and
+    /// insert `AdvanceNonceAccount` as the first instruction.
+    async fn get_blockhash(
+        &mut self,
+        rpc: &RpcClient,
+        config: &ExecutionConfig,
+        inserted: &mut usize,
+    ) -> Result<Hash, Error> {
+        match &config.durable_nonce {
+            Some(nonce) => {
+                let data =
+                    get_nonce_data(rpc, &nonce.account, commitment(config.tx_commitment_level))
+                        .await?;
+                let authority = nonce.authority.unwrap_or(self.fee_payer);
+                if data.authority != authority {
+                    return Err(Error::other(format!(
+                        "{} is not the authority of nonce account {}, expected {}",
+                        authority, nonce.account, data.authority
+                    )));
+                }
+                self.instructions.insert(
+                    0,
+                    system_instruction::advance_nonce_account(&nonce.account, &authority),
+                );
+                *inserted += 1;
+                Ok(data.blockhash())
+            }
+            None => config
+                .latest_blockhash(rpc)
+                .await
+                .map_err(|error| Error::solana(error, *inserted)),
+        }
+    }
+
     async fn build_message(
         &mut self,
         rpc: &RpcClient,
         config: &ExecutionConfig,
     ) -> Result<(Message, usize), Error> {
-        let inserted = self.insert_priority_fee(rpc, config).await?;
+        let mut inserted = self.insert_priority_fee(rpc, config).await?;
+        let blockhash = self.get_blockhash(rpc, config, &mut inserted).await?;
 
-        let message = Message::new_with_blockhash(
-            &self.instructions,
-            Some(&self.fee_payer),
-            &rpc.get_latest_blockhash_with_commitment(commitment(config.tx_commitment_level))
-                .await
-                .map_err(|error| Error::solana(error, inserted))?
-                .0,
-        );
+        let message =
+            Message::new_with_blockhash(&self.instructions, Some(&self.fee_payer), &blockhash);
 
         Ok((message, inserted))
     }
@@ -763,10 +1849,10 @@ impl Instructions {
         let message = Message::new_with_blockhash(
             &self.instructions,
             Some(&self.fee_payer),
-            &rpc.get_latest_blockhash_with_commitment(commitment(config.tx_commitment_level))
+            &config
+                .latest_blockhash(rpc)
                 .await
-                .map_err(|error| Error::solana(error, inserted))?
-                .0,
+                .map_err(|error| Error::solana(error, inserted))?,
         );
 
         // Sign all signatures except for action_signer
@@ -777,6 +1863,10 @@ impl Instructions {
             .map(|keypair| keypair.pubkey())
             .collect::<BTreeSet<_>>();
         let data: Bytes = message.serialize().into();
+        check_tx_size(signed_tx_size(
+            message.header.num_required_signatures,
+            &data,
+        ))?;
         let reqs = wallets
             .iter()
             .map(|&pubkey| signer::SignatureRequest {
@@ -784,9 +1874,11 @@ impl Instructions {
                 time: Utc::now(),
                 pubkey,
                 message: data.clone(),
+                kind: signer::MessageKind::Transaction,
                 timeout: SIGNATURE_TIMEOUT,
                 flow_run_id,
                 signatures: None,
+                cancel: None,
             })
             .collect::<Vec<_>>();
 
@@ -841,13 +1933,24 @@ impl Instructions {
         config: &ExecutionConfig,
     ) -> Result<(Transaction, usize), Error> {
         let (mut message, inserted) = self.build_message(rpc, config).await?;
+        check_tx_size(signed_tx_size(
+            message.header.num_required_signatures,
+            &message.serialize(),
+        ))?;
+        let fee = rpc
+            .get_fee_for_message(&message)
+            .await
+            .map_err(|error| Error::solana(error, inserted))?;
+        self.check_balance(rpc, fee).await?;
         let mut data: Bytes = message.serialize().into();
         let fee_payer_signature = {
             let keypair = self
                 .signers
                 .iter()
                 .find(|w| w.pubkey() == self.fee_payer)
-                .ok_or_else(|| Error::other("fee payer is not in signers"))?;
+                .ok_or_else(|| {
+                    Error::other(format!("fee payer {} is not in signers", self.fee_payer))
+                })?;
 
             tracing::info!("{} signing", keypair.pubkey());
             if keypair.is_adapter_wallet() {
@@ -856,14 +1959,16 @@ impl Instructions {
                     time: Utc::now(),
                     pubkey: keypair.pubkey(),
                     message: data.clone(),
+                    kind: signer::MessageKind::Transaction,
                     timeout: SIGNATURE_TIMEOUT,
                     flow_run_id,
                     signatures: None,
+                    cancel: None,
                 });
                 let resp = tokio::time::timeout(SIGNATURE_TIMEOUT, fut)
                     .await
                     .map_err(|_| Error::Timeout)?
-                    .map_err(Error::other)?;
+                    .map_err(|error| fee_payer_error(&keypair.pubkey(), error))?;
                 if let Some(new) = resp.new_message {
                     let new_message = is_same_message_logic(&data, &new)?;
                     tracing::info!("updating transaction");
@@ -888,12 +1993,10 @@ impl Instructions {
             })
             .collect::<BTreeSet<_>>();
 
-        let reqs = wallets
-            .iter()
-            .map(|&pubkey| signer::SignatureRequest {
-                id: None,
-                time: Utc::now(),
-                pubkey,
+        let signatures = signer::sign_many(
+            &signer,
+            signer::SignManyRequest {
+                pubkeys: wallets.into_iter().collect(),
                 message: data.clone(),
                 timeout: SIGNATURE_TIMEOUT,
                 flow_run_id,
@@ -904,30 +2007,16 @@ impl Instructions {
                     }]
                     .into(),
                 ),
-            })
-            .collect::<Vec<_>>();
-
-        let signature_results = tokio::time::timeout(
-            SIGNATURE_TIMEOUT,
-            signer
-                .call_all(futures::stream::iter(reqs))
-                .try_collect::<Vec<_>>(),
+            },
         )
-        .await
-        .map_err(|_| Error::Timeout)??;
+        .await;
+        let signatures = all_signatures(signatures)?;
 
         let tx = {
-            let mut presigners = wallets
+            let mut presigners = signatures
                 .iter()
-                .zip(signature_results.iter())
-                .map(|(pk, resp)| {
-                    (resp.new_message.is_none() || *resp.new_message.as_ref().unwrap() == data)
-                        .then(|| Presigner::new(pk, &resp.signature))
-                        .ok_or_else(|| {
-                            anyhow!("{} signature failed: not allowed to change transaction", pk)
-                        })
-                })
-                .collect::<Result<Vec<_>, anyhow::Error>>()?;
+                .map(|(pk, signature)| Presigner::new(pk, signature))
+                .collect::<Vec<_>>();
             presigners.push(Presigner::new(&self.fee_payer, &fee_payer_signature));
 
             let mut signers = Vec::<&dyn Signer>::with_capacity(self.signers.len());
@@ -952,13 +2041,135 @@ impl Instructions {
         Ok((tx, inserted))
     }
 
-    async fn execute_current_machine(
+    async fn build_and_sign_versioned_tx(
+        mut self,
+        rpc: &RpcClient,
+        signer: signer::Svc,
+        flow_run_id: Option<FlowRunId>,
+        config: &ExecutionConfig,
+    ) -> Result<(VersionedTransaction, usize), Error> {
+        let lookup_tables = get_lookup_tables(rpc, &config.lookup_tables).await?;
+        let mut inserted = self.insert_priority_fee(rpc, config).await?;
+        let blockhash = self.get_blockhash(rpc, config, &mut inserted).await?;
+        let message = compile_message_v0(
+            &self.fee_payer,
+            &self.instructions,
+            &lookup_tables,
+            blockhash,
+        )?;
+        check_tx_size(signed_tx_size(
+            message.header().num_required_signatures,
+            &message.serialize(),
+        ))?;
+        if let VersionedMessage::V0(message) = &message {
+            let fee = rpc
+                .get_fee_for_message(message)
+                .await
+                .map_err(|error| Error::solana(error, inserted))?;
+            self.check_balance(rpc, fee).await?;
+        }
+        let data: Bytes = message.serialize().into();
+
+        let required =
+            &message.static_account_keys()[..message.header().num_required_signatures as usize];
+        let mut wallets = Vec::new();
+        for pubkey in required {
+            let keypair = self
+                .signers
+                .iter()
+                .find(|k| k.pubkey() == *pubkey)
+                .ok_or_else(|| Error::other(format!("{} is not in signers", pubkey)))?;
+            if keypair.is_adapter_wallet() {
+                tracing::info!("{} signing", pubkey);
+                wallets.push(*pubkey);
+            }
+        }
+        let mut resp = signer::sign_many(
+            &signer,
+            signer::SignManyRequest {
+                pubkeys: wallets,
+                message: data.clone(),
+                timeout: SIGNATURE_TIMEOUT,
+                flow_run_id,
+                signatures: None,
+            },
+        )
+        .await;
+        if let Some(index) = resp
+            .failures
+            .iter()
+            .position(|(pubkey, _)| *pubkey == self.fee_payer)
+        {
+            let (pubkey, error) = resp.failures.swap_remove(index);
+            return Err(fee_payer_error(&pubkey, error));
+        }
+        let mut adapter_signatures = all_signatures(resp)?;
+        let signatures = required
+            .iter()
+            .map(|pubkey| match adapter_signatures.remove(pubkey) {
+                Some(signature) => signature,
+                None => self
+                    .signers
+                    .iter()
+                    .find(|k| k.pubkey() == *pubkey)
+                    .expect("checked above")
+                    .sign_message(&data),
+            })
+            .collect::<Vec<_>>();
+
+        Ok((
+            VersionedTransaction {
+                signatures,
+                message,
+            },
+            inserted,
+        ))
+    }
+
+    async fn send_versioned(
         self,
         rpc: &RpcClient,
         signer: signer::Svc,
         flow_run_id: Option<FlowRunId>,
         config: &ExecutionConfig,
-    ) -> Result<Signature, Error> {
+        deadline: Option<Instant>,
+    ) -> Result<(Signature, ConfirmStatus, usize), Error> {
+        let (tx, inserted) = self
+            .build_and_sign_versioned_tx(rpc, signer, flow_run_id, config)
+            .await?;
+
+        submit(rpc, &tx, inserted, config, deadline).await
+    }
+
+    fn push_jito_tip(&mut self, config: &ExecutionConfig) {
+        if let SubmissionMode::JitoBundle {
+            tip_lamports: Some(tip),
+            ..
+        } = &config.submission
+        {
+            if *tip > 0 {
+                self.instructions
+                    .push(jito::tip_instruction(&self.fee_payer, *tip));
+            }
+        }
+    }
+
+    async fn send_current_machine(
+        mut self,
+        rpc: &RpcClient,
+        signer: signer::Svc,
+        flow_run_id: Option<FlowRunId>,
+        config: &ExecutionConfig,
+        deadline: Option<Instant>,
+    ) -> Result<(Signature, ConfirmStatus, usize), Error> {
+        self.push_jito_tip(config);
+
+        if !config.lookup_tables.is_empty() {
+            return self
+                .send_versioned(rpc, signer, flow_run_id, config, deadline)
+                .await;
+        }
+
         let (tx, inserted) = self
             .build_and_sign_tx(rpc, signer, flow_run_id, config)
             .await?;
@@ -968,28 +2179,53 @@ impl Instructions {
         // TODO: is it correct to use FeatureSet::all_enabled()?
         verify_precompiles(&tx, &FeatureSet::all_enabled())?;
 
-        let signature = rpc
-            .send_transaction_with_config(
-                &tx,
-                RpcSendTransactionConfig {
-                    preflight_commitment: Some(config.tx_commitment_level),
-                    ..<_>::default()
-                },
-            )
-            .await
-            .map_err(move |error| Error::solana(error, inserted))?;
-        tracing::info!("submitted {}", signature);
-
-        confirm_transaction(
-            rpc,
-            &signature,
-            tx.get_recent_blockhash(),
-            commitment(config.wait_commitment_level),
-        )
-        .await
-        .map_err(move |error| Error::solana(error, inserted))?;
+        submit(rpc, &tx, inserted, config, deadline).await
+    }
 
-        Ok(signature)
+    /// Send and confirm the transaction, re-sign it with a new blockhash and send again
+    /// if it expired, up to [`ExecutionConfig::max_resends`] times.
+    ///
+    /// Return [`Error::Timeout`] if [`ExecutionConfig::confirm_timeout`] has passed or
+    /// all resends expired.
+    async fn execute_current_machine(
+        self,
+        rpc: &RpcClient,
+        signer: signer::Svc,
+        flow_run_id: Option<FlowRunId>,
+        config: &ExecutionConfig,
+    ) -> Result<Signature, Error> {
+        let deadline = config
+            .confirm_timeout
+            .map(|timeout| Instant::now() + timeout);
+        let mut attempt = 0;
+        loop {
+            let (signature, status, inserted) = self
+                .clone()
+                .send_current_machine(rpc, signer.clone(), flow_run_id, config, deadline)
+                .await?;
+            match status {
+                ConfirmStatus::Confirmed => return Ok(signature),
+                ConfirmStatus::TimedOut => return Err(Error::Timeout),
+                ConfirmStatus::Expired if config.max_resends == 0 => {
+                    return Err(Error::solana(unable_to_confirm(), inserted))
+                }
+                ConfirmStatus::Expired if attempt >= config.max_resends => {
+                    return Err(Error::Timeout)
+                }
+                ConfirmStatus::Expired => {
+                    if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                        return Err(Error::Timeout);
+                    }
+                    attempt += 1;
+                    tracing::info!(
+                        "{} expired, sending again ({}/{})",
+                        signature,
+                        attempt,
+                        config.max_resends
+                    );
+                }
+            }
+        }
     }
 
     async fn execute_solana_action(
@@ -1016,14 +2252,16 @@ impl Instructions {
             time: Utc::now(),
             pubkey: action_config.action_signer,
             message: tx.message_data().into(),
+            kind: signer::MessageKind::Transaction,
             timeout: Duration::from_secs(0),
             flow_run_id,
             signatures: list_signatures(&tx),
+            cancel: None,
         };
-        if let Err(error) = signer.call_mut(req).await {
-            if !matches!(error, signer::Error::Timeout) {
-                return Err(Error::other(error));
-            }
+        match signer.call_mut(req).await {
+            Ok(_) | Err(signer::Error::Timeout) => {}
+            Err(signer::Error::Canceled) => return Err(Error::Canceled(None)),
+            Err(error) => return Err(Error::other(error)),
         }
         let signature = confirm_action_transaction(
             rpc,
@@ -1035,13 +2273,53 @@ impl Instructions {
         Ok(signature)
     }
 
+    /// Build the transaction that [`Instructions::execute`] would send, without signing
+    /// or sending it. Returns the base64-encoded transaction, signatures are left empty.
+    pub async fn dry_run(
+        mut self,
+        rpc: &RpcClient,
+        config: ExecutionConfig,
+    ) -> Result<String, Error> {
+        if let Some(fee_payer) = config.fee_payer {
+            self.override_fee_payer(fee_payer);
+        }
+        self.push_jito_tip(&config);
+        let data = if config.lookup_tables.is_empty() {
+            let (message, _) = self.build_message(rpc, &config).await?;
+            bincode::serialize(&Transaction::new_unsigned(message))
+        } else {
+            let lookup_tables = get_lookup_tables(rpc, &config.lookup_tables).await?;
+            let mut inserted = self.insert_priority_fee(rpc, &config).await?;
+            let blockhash = self.get_blockhash(rpc, &config, &mut inserted).await?;
+            let message = compile_message_v0(
+                &self.fee_payer,
+                &self.instructions,
+                &lookup_tables,
+                blockhash,
+            )?;
+            bincode::serialize(&VersionedTransaction {
+                signatures: vec![
+                    Signature::default();
+                    message.header().num_required_signatures as usize
+                ],
+                message,
+            })
+        }
+        .map_err(Error::other)?;
+        check_tx_size(data.len())?;
+        Ok(BASE64_STANDARD.encode(data))
+    }
+
     pub async fn execute(
-        self,
+        mut self,
         rpc: &RpcClient,
         signer: signer::Svc,
         flow_run_id: Option<FlowRunId>,
         config: ExecutionConfig,
     ) -> Result<Signature, Error> {
+        if let Some(fee_payer) = config.fee_payer {
+            self.override_fee_payer(fee_payer);
+        }
         match &config.execute_on {
             ExecuteOn::CurrentMachine => {
                 self.execute_current_machine(rpc, signer, flow_run_id, &config)
@@ -1064,6 +2342,24 @@ mod tests {
     };
     use base64::prelude::*;
     use solana_sdk::{pubkey, system_instruction::transfer};
+    use std::num::NonZeroU64;
+
+    #[test]
+    fn test_latest_blockhash() {
+        let latest = LatestBlockhash {
+            blockhash: Hash::new_unique(),
+            last_valid_block_height: 100,
+        };
+        assert!(latest.is_valid_at(100));
+        assert!(!latest.is_valid_at(101));
+        assert_eq!(
+            serde_json::to_value(latest).unwrap(),
+            serde_json::json!({
+                "blockhash": latest.blockhash.to_string(),
+                "last_valid_block_height": 100,
+            })
+        );
+    }
 
     #[test]
     fn test_compare_msg_logic() {
@@ -1076,6 +2372,29 @@ mod tests {
         .unwrap();
     }
 
+    #[test]
+    fn test_with_defaults() {
+        let defaults = SolanaConfig {
+            commitment: Some(CommitmentLevel::Finalized),
+            confirm_timeout_in_secs: NonZeroU64::new(30),
+            send_max_retries: Some(2),
+        };
+        let environment = [(WAIT_COMMITMENT_LEVEL.to_owned(), "processed".to_owned())]
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+        let c = ExecutionConfig::from_env(&environment)
+            .unwrap()
+            .with_defaults(&defaults, &environment);
+        assert_eq!(c.tx_commitment_level, CommitmentLevel::Finalized);
+        assert_eq!(c.wait_commitment_level, CommitmentLevel::Processed);
+        assert_eq!(c.confirm_timeout, Some(Duration::from_secs(30)));
+        assert_eq!(c.send_max_retries, Some(2));
+
+        let c = ExecutionConfig::default().with_defaults(&SolanaConfig::default(), &environment);
+        assert_eq!(c.tx_commitment_level, default_tx_level());
+        assert_eq!(c.confirm_timeout, None);
+    }
+
     #[test]
     fn test_parse_config() {
         fn t<const N: usize>(kv: [(&str, &str); N], result: ExecutionConfig) {
@@ -1121,6 +2440,495 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_execute_options() {
+        let options: ExecuteOptions =
+            serde_json::from_str(r#"{ "commitment": { "commitment": "finalized" } }"#).unwrap();
+        let config = ExecutionConfig::default().with_options(&options);
+        assert_eq!(config.tx_commitment_level, CommitmentLevel::Finalized);
+        assert_eq!(config.wait_commitment_level, CommitmentLevel::Finalized);
+
+        let config = ExecutionConfig::default().with_options(&ExecuteOptions::default());
+        assert_eq!(config.tx_commitment_level, CommitmentLevel::Confirmed);
+        assert_eq!(config.wait_commitment_level, CommitmentLevel::Confirmed);
+        assert_eq!(config.priority_fee, InsertionBehavior::Auto);
+    }
+
+    #[test]
+    fn test_lookup_tables_size() {
+        let payer = Pubkey::new_unique();
+        let recipients = (0..20).map(|_| Pubkey::new_unique()).collect::<Vec<_>>();
+        let mut instructions = recipients
+            .iter()
+            .map(|to| transfer(&payer, to, 1000))
+            .collect::<Vec<_>>();
+        instructions.push(spl_memo::build_memo(&[b'a'; 200], &[]));
+        let blockhash = Hash::new_unique();
+
+        let legacy = Transaction::new_unsigned(Message::new_with_blockhash(
+            &instructions,
+            Some(&payer),
+            &blockhash,
+        ));
+        let size = bincode::serialize(&legacy).unwrap().len();
+        assert!(size > PACKET_DATA_SIZE);
+        assert_eq!(
+            signed_tx_size(
+                legacy.message.header.num_required_signatures,
+                &legacy.message.serialize()
+            ),
+            size
+        );
+        let error = check_tx_size(size).unwrap_err().to_string();
+        assert!(error.contains(&format!("{} bytes", size)));
+        assert!(error.contains(&format!("{} bytes over", size - PACKET_DATA_SIZE)));
+
+        let table = AddressLookupTableAccount {
+            key: Pubkey::new_unique(),
+            addresses: recipients,
+        };
+        let message = compile_message_v0(&payer, &instructions, &[table], blockhash).unwrap();
+        let tx = VersionedTransaction {
+            signatures: vec![
+                Signature::default();
+                message.header().num_required_signatures as usize
+            ],
+            message,
+        };
+        let size = bincode::serialize(&tx).unwrap().len();
+        assert!(size <= PACKET_DATA_SIZE);
+        check_tx_size(size).unwrap();
+    }
+
+    #[test]
+    fn test_with_memo() {
+        let mut ins = Instructions::default();
+        ins.with_memo("hello".to_owned(), false).unwrap();
+        assert_eq!(ins.instructions[0].program_id, spl_memo::ID);
+        assert_eq!(ins.instructions[0].data, b"hello");
+
+        let long = "é".repeat(MAX_MEMO_LEN);
+        assert!(ins.with_memo(long.clone(), false).is_err());
+        ins.with_memo(long, true).unwrap();
+        let data = &ins.instructions[1].data;
+        assert_eq!(data.len(), MAX_MEMO_LEN);
+        assert!(std::str::from_utf8(data).is_ok());
+    }
+
+    #[test]
+    fn test_split() {
+        let payer = Keypair::new();
+        let other = Keypair::new();
+        let mut instructions = (0..30)
+            .map(|_| transfer(&payer.pubkey(), &Pubkey::new_unique(), 1000))
+            .collect::<Vec<_>>();
+        instructions.push(transfer(&other.pubkey(), &payer.pubkey(), 1000));
+        let ins = Instructions {
+            fee_payer: payer.pubkey(),
+            signers: [payer.clone_keypair(), other.clone_keypair()].into(),
+            instructions,
+        };
+
+        let txs = ins.split(PACKET_DATA_SIZE - 64, &[20..31]).unwrap();
+        assert_eq!(
+            txs.iter()
+                .map(|tx| tx.instructions.len())
+                .collect::<Vec<_>>(),
+            [20, 11]
+        );
+        assert_eq!(txs[0].signers.len(), 1);
+        assert_eq!(txs[1].signers.len(), 2);
+        for tx in &txs {
+            let message = Message::new(&tx.instructions, Some(&tx.fee_payer));
+            let size = signed_tx_size(message.header.num_required_signatures, &message.serialize());
+            assert!(size <= PACKET_DATA_SIZE - 64);
+        }
+
+        let ins = Instructions {
+            fee_payer: payer.pubkey(),
+            signers: [payer.clone_keypair()].into(),
+            instructions: txs.into_iter().flat_map(|tx| tx.instructions).collect(),
+        };
+        assert!(matches!(
+            ins.split(PACKET_DATA_SIZE, &[0..31]),
+            Err(Error::TxTooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_nonce_account() {
+        use solana_sdk::{
+            account::Account,
+            nonce::state::{self, Data, State, Versions},
+        };
+
+        let nonce_account = Pubkey::new_unique();
+        let authority = Pubkey::new_unique();
+        let new_account = |state: State| Account {
+            lamports: 1_000_000,
+            data: bincode::serialize(&Versions::new(state)).unwrap(),
+            owner: solana_sdk::system_program::ID,
+            executable: false,
+            rent_epoch: 0,
+        };
+
+        let error =
+            parse_nonce_account(&nonce_account, &new_account(State::Uninitialized)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("nonce account {} is not initialized", nonce_account)
+        );
+
+        let durable_nonce = state::DurableNonce::from_blockhash(&Hash::new_unique());
+        let data = parse_nonce_account(
+            &nonce_account,
+            &new_account(State::Initialized(Data::new(
+                authority,
+                durable_nonce,
+                5000,
+            ))),
+        )
+        .unwrap();
+        assert_eq!(data.authority, authority);
+        assert_eq!(data.blockhash(), *durable_nonce.as_hash());
+    }
+
+    #[tokio::test]
+    async fn test_blockhash_cache() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let cache = BlockhashCache::new(BLOCKHASH_TTL);
+        let counter = AtomicUsize::new(0);
+        let calls = &counter;
+        let key = || {
+            (
+                SolanaNet::Devnet.url().to_owned(),
+                CommitmentLevel::Confirmed,
+            )
+        };
+        let fetch = move || async move {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Ok::<_, Infallible>(Hash::new_unique())
+        };
+        // 50 transactions in a burst
+        let hashes =
+            futures::future::try_join_all((0..50).map(|_| cache.get_or_fetch(key(), fetch)))
+                .await
+                .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(hashes.iter().all(|h| *h == hashes[0]));
+
+        let other = cache
+            .get_or_fetch(
+                (
+                    SolanaNet::Devnet.url().to_owned(),
+                    CommitmentLevel::Finalized,
+                ),
+                fetch,
+            )
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_ne!(other, hashes[0]);
+
+        // a hung RPC doesn't block other keys
+        let cache = BlockhashCache::new(BLOCKHASH_TTL);
+        let hung = std::pin::pin!(cache.get_or_fetch(key(), || {
+            std::future::pending::<Result<Hash, Infallible>>()
+        }));
+        let other = std::pin::pin!(cache.get_or_fetch(
+            ("http://127.0.0.1:1".to_owned(), CommitmentLevel::Confirmed),
+            fetch,
+        ));
+        assert!(matches!(
+            futures::future::select(hung, other).await,
+            futures::future::Either::Right((Ok(_), _))
+        ));
+    }
+
+    #[test]
+    fn test_send_config() {
+        let config = ExecutionConfig::default().send_config();
+        assert!(!config.skip_preflight);
+        assert_eq!(
+            config.preflight_commitment,
+            Some(CommitmentLevel::Confirmed)
+        );
+        assert_eq!(config.max_retries, None);
+
+        let config = ExecutionConfig::default()
+            .with_options(&ExecuteOptions {
+                skip_preflight: true,
+                preflight_commitment: Some(CommitmentLevel::Processed),
+                max_retries: Some(0),
+                ..<_>::default()
+            })
+            .send_config();
+        assert!(config.skip_preflight);
+        assert_eq!(
+            config.preflight_commitment,
+            Some(CommitmentLevel::Processed)
+        );
+        assert_eq!(config.max_retries, Some(0));
+    }
+
+    #[test]
+    fn test_override_fee_payer() {
+        let user = Keypair::new();
+        let relayer = Pubkey::new_unique();
+        let mut ins = Instructions {
+            fee_payer: user.pubkey(),
+            signers: [user.clone_keypair()].into(),
+            instructions: [transfer(&Pubkey::new_unique(), &Pubkey::new_unique(), 1)].into(),
+        };
+        ins.override_fee_payer(relayer);
+        assert_eq!(ins.fee_payer, relayer);
+        assert_eq!(ins.signers.len(), 1);
+        assert!(ins.signers[0].is_adapter_wallet());
+
+        // user still needs to sign the transfer
+        let mut ins = Instructions {
+            fee_payer: user.pubkey(),
+            signers: [user.clone_keypair()].into(),
+            instructions: [transfer(&user.pubkey(), &Pubkey::new_unique(), 1)].into(),
+        };
+        ins.override_fee_payer(relayer);
+        let message =
+            Message::new_with_blockhash(&ins.instructions, Some(&ins.fee_payer), &Hash::default());
+        assert_eq!(message.account_keys[0], relayer);
+        assert_eq!(ins.signers.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_sign_transaction() {
+        let payer = Keypair::new();
+        let presigned = Keypair::new();
+        let wallet = Keypair::new();
+        let instructions = [
+            transfer(&presigned.pubkey(), &Pubkey::new_unique(), 1),
+            transfer(&wallet.pubkey(), &Pubkey::new_unique(), 1),
+        ];
+        let message =
+            Message::new_with_blockhash(&instructions, Some(&payer.pubkey()), &Hash::new_unique());
+        let message = VersionedMessage::Legacy(message);
+        let data = message.serialize();
+        let mut signatures = vec![Signature::default(); 3];
+        let index = message
+            .static_account_keys()
+            .iter()
+            .position(|k| *k == presigned.pubkey())
+            .unwrap();
+        signatures[index] = presigned.sign_message(&data);
+        let tx = VersionedTransaction {
+            signatures,
+            message,
+        };
+
+        let wallet_pubkey = wallet.pubkey();
+        let signer = signer::Svc::from_service(
+            tower::service_fn(move |req: signer::SignatureRequest| {
+                assert_eq!(req.pubkey, wallet_pubkey);
+                assert_eq!(req.signatures.as_ref().map(Vec::len), Some(1));
+                let signature = wallet.sign_message(&req.message);
+                async move {
+                    Ok(signer::SignatureResponse {
+                        signature,
+                        new_message: None,
+                    })
+                }
+            }),
+            signer::Error::Worker,
+            32,
+        );
+        let keypairs = [
+            payer.clone_keypair(),
+            Keypair::new_adapter_wallet(wallet_pubkey),
+        ];
+        let tx = sign_transaction(tx, &keypairs, &signer, None)
+            .await
+            .unwrap();
+        assert!(tx.verify_with_results().into_iter().all(|ok| ok));
+    }
+
+    #[tokio::test]
+    async fn test_check_balance_rent() {
+        use solana_client::rpc_request::RpcRequest;
+
+        let rpc = |balance: u64| {
+            RpcClient::new_mock_with_mocks(
+                "succeeds".to_owned(),
+                [
+                    (
+                        RpcRequest::GetMinimumBalanceForRentExemption,
+                        serde_json::json!(1_461_600),
+                    ),
+                    (
+                        RpcRequest::GetBalance,
+                        serde_json::json!({ "context": { "slot": 1 }, "value": balance }),
+                    ),
+                ]
+                .into(),
+            )
+        };
+        let payer = Pubkey::new_unique();
+        let ins = Instructions {
+            fee_payer: payer,
+            signers: Vec::new(),
+            // funded with less than rent, the fee payer pays the rest
+            instructions: vec![system_instruction::create_account(
+                &payer,
+                &Pubkey::new_unique(),
+                10,
+                200,
+                &Pubkey::new_unique(),
+            )],
+        };
+        match ins.check_balance(&rpc(1_000_000), 5000).await {
+            Err(Error::InsufficientSolanaBalance { needed, balance }) => {
+                assert_eq!(needed, 5000 + 1_461_600);
+                assert_eq!(balance, 1_000_000);
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+        ins.check_balance(&rpc(2_000_000), 5000).await.unwrap();
+    }
+
+    #[test]
+    fn test_created_accounts() {
+        let payer = Pubkey::new_unique();
+        let new = Pubkey::new_unique();
+        let instructions = [
+            transfer(&payer, &new, 1000),
+            system_instruction::create_account(&payer, &new, 10, 200, &Pubkey::new_unique()),
+            // paid by another account
+            system_instruction::create_account(
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                10,
+                200,
+                &Pubkey::new_unique(),
+            ),
+            Instruction::new_with_bytes(
+                ASSOCIATED_TOKEN_PROGRAM_ID,
+                &[1],
+                vec![
+                    AccountMeta::new(payer, true),
+                    AccountMeta::new(new, false),
+                    AccountMeta::new_readonly(payer, false),
+                    AccountMeta::new_readonly(Pubkey::new_unique(), false),
+                    AccountMeta::new_readonly(system_program::ID, false),
+                    AccountMeta::new_readonly(TOKEN_2022_PROGRAM_ID, false),
+                ],
+            ),
+        ];
+        assert_eq!(
+            created_accounts(&payer, &instructions),
+            [
+                CreatedAccount {
+                    address: new,
+                    space: 200,
+                    lamports: 10,
+                    idempotent: false,
+                },
+                CreatedAccount {
+                    address: new,
+                    space: TOKEN_2022_ACCOUNT_SIZE,
+                    lamports: 0,
+                    idempotent: true,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_submission_mode() {
+        let options: ExecuteOptions = serde_json::from_value(serde_json::json!({
+            "submission": { "mode": "jito_bundle", "tip_lamports": 1000 },
+        }))
+        .unwrap();
+        let config = ExecutionConfig::default().with_options(&options);
+        assert_eq!(
+            config.submission,
+            SubmissionMode::JitoBundle {
+                tip_lamports: Some(1000),
+                block_engine_url: None,
+            }
+        );
+        assert_eq!(ExecutionConfig::default().submission, SubmissionMode::Rpc);
+    }
+
+    #[test]
+    fn test_confirm_options() {
+        let options: ExecuteOptions = serde_json::from_value(serde_json::json!({
+            "confirm_timeout": 1.5,
+            "max_resends": 2,
+        }))
+        .unwrap();
+        let config = ExecutionConfig::default().with_options(&options);
+        assert_eq!(config.confirm_timeout, Some(Duration::from_millis(1500)));
+        assert_eq!(config.max_resends, 2);
+
+        let mut merged = ExecuteOptions::default();
+        merged.merge(&options);
+        merged.merge(&ExecuteOptions {
+            max_resends: Some(5),
+            ..<_>::default()
+        });
+        assert_eq!(merged.max_resends, Some(5));
+        assert_eq!(merged.confirm_timeout, Some(Duration::from_millis(1500)));
+    }
+
+    #[test]
+    fn test_simulation_failure() {
+        use solana_sdk::instruction::InstructionError;
+
+        let failure = SimulationFailure {
+            error: TransactionError::InstructionError(3, InstructionError::Custom(1)),
+            logs: vec![
+                "Program 11111111111111111111111111111111 invoke [1]".to_owned(),
+                "Program 11111111111111111111111111111111 failed: custom program error: 0x1"
+                    .to_owned(),
+            ],
+            inserted: 2,
+        };
+        assert_eq!(failure.failed_instruction(), Some(1));
+        let error = Error::TxSimFailed(Some(Arc::new(failure))).to_string();
+        assert!(error.starts_with("transaction simulation failed: "));
+        assert!(error.ends_with("failed: custom program error: 0x1"));
+        assert_eq!(
+            Error::TxSimFailed(None).to_string(),
+            "transaction simulation failed"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_priority_fee_options() {
+        let from = Keypair::new();
+        let to = Pubkey::new_unique();
+
+        let rpc = RpcClient::new(SolanaNet::Devnet.url().to_owned());
+        let mut ins = Instructions {
+            fee_payer: from.pubkey(),
+            signers: [from.clone_keypair()].into(),
+            instructions: [transfer(&from.pubkey(), &to, 100000)].into(),
+        };
+        let config = ExecutionConfig::default().with_options(&ExecuteOptions {
+            priority_fee_micro_lamports: Some(5000),
+            compute_unit_limit: Some(1000),
+            ..<_>::default()
+        });
+        let inserted = ins.insert_priority_fee(&rpc, &config).await.unwrap();
+        assert_eq!(inserted, 2);
+        assert_eq!(
+            ins.instructions[0],
+            ComputeBudgetInstruction::set_compute_unit_price(5000)
+        );
+        assert_eq!(
+            ins.instructions[1],
+            ComputeBudgetInstruction::set_compute_unit_limit(1000)
+        );
+    }
+
     #[tokio::test]
     async fn test_build_message() {
         let from = Keypair::new();
@@ -1152,4 +2960,144 @@ mod tests {
             .unwrap();
         assert_eq!(inserted, 1);
     }
+
+    #[tokio::test]
+    async fn test_dry_run() {
+        let from = Keypair::new();
+        let to = Pubkey::new_unique();
+
+        let rpc = RpcClient::new(SolanaNet::Devnet.url().to_owned());
+        let ins = Instructions {
+            fee_payer: from.pubkey(),
+            signers: [from.clone_keypair()].into(),
+            instructions: [transfer(&from.pubkey(), &to, 100000)].into(),
+        };
+        let tx = ins
+            .dry_run(
+                &rpc,
+                ExecutionConfig {
+                    priority_fee: InsertionBehavior::No,
+                    ..<_>::default()
+                },
+            )
+            .await
+            .unwrap();
+        let tx: Transaction = bincode::deserialize(&BASE64_STANDARD.decode(tx).unwrap()).unwrap();
+        assert_eq!(tx.message.account_keys[0], from.pubkey());
+        assert_eq!(tx.signatures, [Signature::default()]);
+    }
+
+    #[test]
+    fn test_signature_status() {
+        use solana_transaction_status::{TransactionConfirmationStatus, TransactionStatus};
+
+        let status = |err, confirmation_status| {
+            Some(TransactionStatus {
+                slot: 1,
+                confirmations: None,
+                status: Ok(()),
+                err,
+                confirmation_status,
+            })
+        };
+        assert_eq!(SignatureStatus::from(None), SignatureStatus::NotFound);
+        assert_eq!(
+            SignatureStatus::from(status(None, Some(TransactionConfirmationStatus::Confirmed))),
+            SignatureStatus::Confirmed
+        );
+        // old RPC nodes don't return confirmation status
+        assert_eq!(
+            SignatureStatus::from(status(None, None)),
+            SignatureStatus::Finalized
+        );
+        assert_eq!(
+            SignatureStatus::from(status(
+                Some(TransactionError::AccountNotFound),
+                Some(TransactionConfirmationStatus::Finalized)
+            )),
+            SignatureStatus::Failed {
+                error: TransactionError::AccountNotFound
+            }
+        );
+        assert_eq!(
+            serde_json::to_value(SignatureStatus::Processed).unwrap(),
+            serde_json::json!({ "status": "processed" })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_landed_at() {
+        let rpc = RpcClient::new(SolanaNet::Devnet.url().to_owned());
+        assert_eq!(landed_at(&rpc, &Signature::default()).await, (None, None));
+    }
+
+    #[test]
+    fn test_error_logs() {
+        use solana_client::{
+            client_error::ClientErrorKind,
+            rpc_request::{RpcError, RpcResponseErrorData},
+        };
+
+        let sim: solana_client::rpc_response::RpcSimulateTransactionResult =
+            serde_json::from_value(serde_json::json!({
+                "err": { "InstructionError": [1, { "Custom": 6000 }] },
+                "logs": [
+                    "Program 11111111111111111111111111111111 invoke [1]",
+                    "Program log: AnchorError occurred.",
+                ],
+            }))
+            .unwrap();
+        let error = Error::solana(
+            ClientErrorKind::RpcError(RpcError::RpcResponseError {
+                code: -32002,
+                message: "Transaction simulation failed: Error processing Instruction 1: custom program error: 0x1770".to_owned(),
+                data: RpcResponseErrorData::SendTransactionPreflightFailure(sim),
+            })
+            .into(),
+            0,
+        );
+        assert_eq!(error.logs().len(), 2);
+        assert_eq!(
+            error.program_log_messages().collect::<Vec<_>>(),
+            ["AnchorError occurred."]
+        );
+        assert_eq!(error.custom_error(), Some(6000));
+    }
+
+    #[test]
+    fn test_sol_conversion() {
+        assert_eq!(lamports_to_sol(1_000_000).to_string(), "0.001");
+        assert_eq!(
+            lamports_to_sol(u64::MAX).to_string(),
+            "18446744073.709551615"
+        );
+        assert_eq!(sol_to_lamports("0.001".parse().unwrap()), Some(1_000_000));
+        assert_eq!(sol_to_lamports("0.0000000019".parse().unwrap()), Some(1));
+        assert_eq!(sol_to_lamports(lamports_to_sol(u64::MAX)), Some(u64::MAX));
+        assert_eq!(sol_to_lamports("-1".parse().unwrap()), None);
+        assert_eq!(sol_to_lamports("18446744074".parse().unwrap()), None);
+        assert_eq!(
+            Error::InsufficientSolanaBalance {
+                needed: 1_500_000,
+                balance: 1_000_000,
+            }
+            .to_string(),
+            "insufficient solana balance, needed 0.0015 SOL; have 0.001 SOL"
+        );
+
+        #[derive(Serialize, Deserialize)]
+        struct Amount {
+            #[serde(with = "sol")]
+            amount: u64,
+        }
+        let value = value::to_value(&Amount { amount: 1_000_000 }).unwrap();
+        assert_eq!(
+            value,
+            Value::Map(value::map! { "amount" => value::Decimal::new(1, 3) })
+        );
+        assert_eq!(
+            value::from_value::<Amount>(value).unwrap().amount,
+            1_000_000
+        );
+    }
 }
@@ -0,0 +1,95 @@
+//! Decode custom errors of Anchor programs with their IDL.
+//!
+//! Register [`AnchorIdls`] as an extension of the [`Context`][crate::Context], failed
+//! transactions of [`Context::execute`][crate::Context::execute] are then returned as
+//! [`execute::Error::Anchor`][crate::context::execute::Error::Anchor].
+
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use std::{collections::HashMap, str::FromStr};
+
+/// An error of the `errors` list in an Anchor IDL.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct IdlError {
+    pub code: u32,
+    pub name: String,
+    #[serde(default)]
+    pub msg: Option<String>,
+}
+
+/// Errors defined by programs, keyed by program ID.
+#[derive(Default, Debug, Clone)]
+pub struct AnchorIdls {
+    programs: HashMap<Pubkey, HashMap<u32, IdlError>>,
+}
+
+impl AnchorIdls {
+    /// Add errors of an IDL, only the `errors` field is read.
+    pub fn insert_idl(&mut self, program_id: Pubkey, idl: &[u8]) -> Result<(), serde_json::Error> {
+        #[derive(Deserialize)]
+        struct Idl {
+            #[serde(default)]
+            errors: Vec<IdlError>,
+        }
+        let idl = serde_json::from_slice::<Idl>(idl)?;
+        self.insert(program_id, idl.errors);
+        Ok(())
+    }
+
+    pub fn insert(&mut self, program_id: Pubkey, errors: impl IntoIterator<Item = IdlError>) {
+        self.programs
+            .entry(program_id)
+            .or_default()
+            .extend(errors.into_iter().map(|e| (e.code, e)));
+    }
+
+    pub fn get(&self, program_id: &Pubkey, code: u32) -> Option<&IdlError> {
+        self.programs.get(program_id)?.get(&code)
+    }
+}
+
+/// Program returning the error of a failed transaction, from its last
+/// `Program <id> failed: ...` log.
+pub fn failed_program(logs: &[String]) -> Option<Pubkey> {
+    logs.iter().rev().find_map(|log| {
+        let (id, _) = log.strip_prefix("Program ")?.split_once(" failed: ")?;
+        Pubkey::from_str(id).ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let program_id = Pubkey::new_unique();
+        let idl = serde_json::json!({
+            "version": "0.1.0",
+            "name": "counter",
+            "instructions": [],
+            "errors": [
+                { "code": 6000, "name": "Overflow", "msg": "counter overflowed" },
+                { "code": 6001, "name": "Unauthorized" }
+            ]
+        });
+        let mut idls = AnchorIdls::default();
+        idls.insert_idl(program_id, idl.to_string().as_bytes())
+            .unwrap();
+        assert_eq!(idls.get(&program_id, 6000).unwrap().name, "Overflow");
+        assert_eq!(idls.get(&program_id, 6001).unwrap().msg, None);
+        assert_eq!(idls.get(&program_id, 6002), None);
+        assert_eq!(idls.get(&Pubkey::new_unique(), 6000), None);
+
+        let logs = [
+            format!("Program {} invoke [1]", program_id),
+            "Program log: Instruction: Increment".to_owned(),
+            format!(
+                "Program {} failed: custom program error: 0x1770",
+                program_id
+            ),
+        ];
+        assert_eq!(failed_program(&logs), Some(program_id));
+        assert_eq!(failed_program(&logs[..2]), None);
+    }
+}
@@ -0,0 +1,150 @@
+//! Send RPC requests to multiple endpoints, switching to the next one when an endpoint
+//! fails.
+//!
+//! [`FailoverSender`] is used as the transport of
+//! [`RpcClient`][solana_client::nonblocking::rpc_client::RpcClient], so all methods of the
+//! client, including the ones used by [`Instructions::execute`][super::Instructions],
+//! fail over transparently.
+
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind, Result},
+    http_sender::HttpSender,
+    rpc_custom_error::JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY,
+    rpc_request::{RpcError, RpcRequest},
+    rpc_sender::{RpcSender, RpcTransportStats},
+};
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+pub struct FailoverSender {
+    senders: Vec<HttpSender>,
+    round_robin: bool,
+    /// Index of the endpoint to try first.
+    current: AtomicUsize,
+}
+
+impl FailoverSender {
+    /// # Panics
+    ///
+    /// Panics if `urls` is empty.
+    pub fn new<I, S>(urls: I, timeout: Duration, round_robin: bool) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let senders = urls
+            .into_iter()
+            .map(|url| HttpSender::new_with_timeout(url, timeout))
+            .collect::<Vec<_>>();
+        assert!(!senders.is_empty(), "no RPC endpoint");
+        Self {
+            senders,
+            round_robin,
+            current: AtomicUsize::new(0),
+        }
+    }
+
+    fn first(&self) -> usize {
+        if self.round_robin {
+            self.current.fetch_add(1, Ordering::Relaxed) % self.senders.len()
+        } else {
+            self.current.load(Ordering::Relaxed) % self.senders.len()
+        }
+    }
+}
+
+/// Whether the error is caused by the endpoint rather than by the request.
+pub fn should_failover(error: &ClientError) -> bool {
+    match error.kind() {
+        ClientErrorKind::Io(_) => true,
+        ClientErrorKind::Reqwest(error) => {
+            error.is_connect()
+                || error.is_timeout()
+                || error
+                    .status()
+                    .is_some_and(|s| s.is_server_error() || s.as_u16() == 429)
+        }
+        ClientErrorKind::RpcError(RpcError::RpcResponseError { code, .. }) => {
+            *code == JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY
+        }
+        _ => false,
+    }
+}
+
+#[async_trait::async_trait]
+impl RpcSender for FailoverSender {
+    async fn send(
+        &self,
+        request: RpcRequest,
+        params: serde_json::Value,
+    ) -> Result<serde_json::Value> {
+        let first = self.first();
+        let len = self.senders.len();
+        let mut index = first;
+        loop {
+            let result = self.senders[index].send(request, params.clone()).await;
+            let next = (index + 1) % len;
+            match result {
+                Err(error) if next != first && should_failover(&error) => {
+                    tracing::warn!(
+                        "{} failed: {}, trying {}",
+                        self.senders[index].url(),
+                        error,
+                        self.senders[next].url()
+                    );
+                    if !self.round_robin {
+                        // stay on the working endpoint for the next requests
+                        let _ = self.current.compare_exchange(
+                            index,
+                            next,
+                            Ordering::Relaxed,
+                            Ordering::Relaxed,
+                        );
+                    }
+                    index = next;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        let mut stats = RpcTransportStats::default();
+        for sender in &self.senders {
+            let s = sender.get_transport_stats();
+            stats.request_count += s.request_count;
+            stats.elapsed_time += s.elapsed_time;
+            stats.rate_limited_time += s.rate_limited_time;
+        }
+        stats
+    }
+
+    fn url(&self) -> String {
+        self.senders[self.current.load(Ordering::Relaxed) % self.senders.len()].url()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_client::{
+        nonblocking::rpc_client::RpcClient, rpc_client::RpcClientConfig,
+        rpc_response::RpcVersionInfo,
+    };
+
+    #[tokio::test]
+    async fn test_failover() {
+        // nothing listens on port 1
+        let sender = FailoverSender::new(
+            ["http://127.0.0.1:1", "https://api.devnet.solana.com"],
+            Duration::from_secs(30),
+            false,
+        );
+        let client = RpcClient::new_sender(sender, RpcClientConfig::default());
+        let version: RpcVersionInfo = client.get_version().await.unwrap();
+        assert!(!version.solana_core.is_empty());
+        assert_eq!(client.url(), "https://api.devnet.solana.com");
+    }
+}
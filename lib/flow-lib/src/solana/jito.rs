@@ -0,0 +1,153 @@
+//! Submit transactions as [Jito](https://docs.jito.wtf/lowlatencytxnsend/) bundles.
+
+use anyhow::{anyhow, bail};
+use base64::prelude::*;
+use serde::Deserialize;
+use serde_json::json;
+use solana_sdk::{instruction::Instruction, pubkey::Pubkey, system_instruction};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub const DEFAULT_BLOCK_ENGINE_URL: &str = "https://mainnet.block-engine.jito.wtf";
+
+/// Accounts that receive tips, from `getTipAccounts`.
+pub const TIP_ACCOUNTS: [Pubkey; 8] = [
+    solana_sdk::pubkey!("96gYZGLnJYVFmbjzopPSU6QiEV5fGqZNyN9nmNhvrZU5"),
+    solana_sdk::pubkey!("HFqU5x63VTqvQss8hp11i4wVV8bD44PvwucfZ2bU7gRe"),
+    solana_sdk::pubkey!("Cw8CFyM9FkoMi7K7Crf6HNQqf4uEMzpKw6QNghXLvLkY"),
+    solana_sdk::pubkey!("ADaUMid9yfUytqMBgopwjb2DTLSokTSzL1zt6iGPaS49"),
+    solana_sdk::pubkey!("DfXygSm4jCyNCybVYYK6DwvWqjKee8pbDmJGcLWNDXjh"),
+    solana_sdk::pubkey!("ADuUkR4vqLUMWXxW9gh6D6L8pMSawimctcNZ5pGwDcEt"),
+    solana_sdk::pubkey!("DttWaMuVvTiduZRnguLF7jNxTgiMBZ1hyAumKUiL2KRL"),
+    solana_sdk::pubkey!("3AVi9Tg9Uo68tJfuvoKvqKNWKkC5wPdSSdeBnizKZ6jT"),
+];
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Transfer `lamports` from `payer` to a random tip account.
+pub fn tip_instruction(payer: &Pubkey, lamports: u64) -> Instruction {
+    // spread tips over the accounts to reduce write lock contention
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    let tip_account = TIP_ACCOUNTS[nanos as usize % TIP_ACCOUNTS.len()];
+    system_instruction::transfer(payer, &tip_account, lamports)
+}
+
+#[derive(Deserialize)]
+struct RpcResponse<T> {
+    result: Option<T>,
+    error: Option<RpcError>,
+}
+
+#[derive(Deserialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+async fn call<T: serde::de::DeserializeOwned>(
+    http: &reqwest::Client,
+    block_engine_url: &str,
+    method: &str,
+    params: serde_json::Value,
+) -> Result<T, anyhow::Error> {
+    let body = json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": method,
+        "params": params,
+    });
+    let url = format!("{}/api/v1/bundles", block_engine_url.trim_end_matches('/'));
+    let resp = http
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(serde_json::to_vec(&body)?)
+        .send()
+        .await?;
+    let status = resp.status();
+    let bytes = resp.bytes().await?;
+    let resp = serde_json::from_slice::<RpcResponse<T>>(&bytes)
+        .map_err(|_| anyhow!("{}: {} {}", method, status, String::from_utf8_lossy(&bytes)))?;
+    match (resp.result, resp.error) {
+        (Some(result), _) => Ok(result),
+        (None, Some(error)) => bail!("{}: {} ({})", method, error.message, error.code),
+        (None, None) => bail!("{}: empty response", method),
+    }
+}
+
+/// Send signed transactions as a bundle, returns the bundle ID.
+pub async fn send_bundle(
+    http: &reqwest::Client,
+    block_engine_url: &str,
+    transactions: &[Vec<u8>],
+) -> Result<String, anyhow::Error> {
+    let transactions = transactions
+        .iter()
+        .map(|tx| BASE64_STANDARD.encode(tx))
+        .collect::<Vec<_>>();
+    call(
+        http,
+        block_engine_url,
+        "sendBundle",
+        json!([transactions, { "encoding": "base64" }]),
+    )
+    .await
+}
+
+#[derive(Deserialize, Debug)]
+struct InflightStatuses {
+    value: Vec<InflightStatus>,
+}
+
+#[derive(Deserialize, Debug)]
+struct InflightStatus {
+    status: String,
+    landed_slot: Option<u64>,
+}
+
+/// Poll `getInflightBundleStatuses` until the bundle lands, returns the slot.
+pub async fn wait_for_bundle(
+    http: &reqwest::Client,
+    block_engine_url: &str,
+    bundle_id: &str,
+    deadline: Instant,
+) -> Result<Option<u64>, anyhow::Error> {
+    loop {
+        let statuses: InflightStatuses = call(
+            http,
+            block_engine_url,
+            "getInflightBundleStatuses",
+            json!([[bundle_id]]),
+        )
+        .await?;
+        // the bundle is unknown for a short while after sending
+        if let Some(status) = statuses.value.first() {
+            match status.status.as_str() {
+                "Landed" => return Ok(status.landed_slot),
+                "Failed" => bail!("bundle {} failed", bundle_id),
+                "Invalid" if Instant::now() >= deadline => {
+                    bail!("bundle {} is invalid or expired", bundle_id)
+                }
+                _ => {}
+            }
+        }
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tip_instruction() {
+        let payer = Pubkey::new_unique();
+        let ins = tip_instruction(&payer, 1000);
+        assert_eq!(ins.accounts[0].pubkey, payer);
+        assert!(TIP_ACCOUNTS.contains(&ins.accounts[1].pubkey));
+    }
+}
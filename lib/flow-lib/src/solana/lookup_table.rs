@@ -0,0 +1,76 @@
+//! Create and extend [address lookup tables](https://docs.solana.com/developing/lookup-tables),
+//! to be used with [`ExecutionConfig::lookup_tables`][super::ExecutionConfig::lookup_tables].
+//!
+//! A table and its new addresses can only be used from the slot after the transaction
+//! that created or extended it, transactions using it in the same slot fail.
+
+use super::Error;
+use solana_address_lookup_table_program::instruction;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{
+    clock::Slot, commitment_config::CommitmentConfig, instruction::Instruction, pubkey::Pubkey,
+};
+
+/// Addresses added by each extend instruction, so that a transaction with one of them
+/// stays under the size limit.
+pub const MAX_ADDRESSES_PER_EXTEND: usize = 20;
+
+/// Create a table of `authority`, rent is paid by `payer`. `recent_slot` must be a
+/// recent slot, it is part of the table's address.
+///
+/// Returns the instruction and the table address.
+pub fn create_at(authority: &Pubkey, payer: &Pubkey, recent_slot: Slot) -> (Instruction, Pubkey) {
+    instruction::create_lookup_table(*authority, *payer, recent_slot)
+}
+
+/// Same as [`create_at`], using the latest finalized slot.
+pub async fn create(
+    rpc: &RpcClient,
+    authority: &Pubkey,
+    payer: &Pubkey,
+) -> Result<(Instruction, Pubkey), Error> {
+    let slot = rpc
+        .get_slot_with_commitment(CommitmentConfig::finalized())
+        .await
+        .map_err(|error| Error::solana(error, 0))?;
+    Ok(create_at(authority, payer, slot))
+}
+
+/// Add `addresses` to `table`, split into instructions of
+/// [`MAX_ADDRESSES_PER_EXTEND`] addresses. `authority` must sign, `payer` pays for
+/// the extra rent.
+pub fn extend(
+    table: &Pubkey,
+    authority: &Pubkey,
+    payer: &Pubkey,
+    addresses: &[Pubkey],
+) -> Vec<Instruction> {
+    addresses
+        .chunks(MAX_ADDRESSES_PER_EXTEND)
+        .map(|chunk| {
+            instruction::extend_lookup_table(*table, *authority, Some(*payer), chunk.to_vec())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_extend() {
+        let authority = Pubkey::new_unique();
+        let payer = Pubkey::new_unique();
+        let (ins, table) = create_at(&authority, &payer, 100);
+        assert_eq!(
+            table,
+            instruction::derive_lookup_table_address(&authority, 100).0
+        );
+        assert_eq!(ins.program_id, solana_address_lookup_table_program::id());
+
+        let addresses = (0..45).map(|_| Pubkey::new_unique()).collect::<Vec<_>>();
+        let ins = extend(&table, &authority, &payer, &addresses);
+        assert_eq!(ins.len(), 3);
+        assert!(ins.iter().all(|i| i.accounts[0].pubkey == table));
+    }
+}
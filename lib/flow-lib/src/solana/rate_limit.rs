@@ -0,0 +1,92 @@
+//! Limit the rate of RPC requests sent by [`RpcClient`][solana_client::nonblocking::rpc_client::RpcClient].
+
+use crate::{config::RateLimitConfig, utils::TowerClient};
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind, Result},
+    rpc_request::RpcRequest,
+    rpc_sender::{RpcSender, RpcTransportStats},
+};
+use std::{sync::Arc, time::Duration};
+
+type Svc = TowerClient<(RpcRequest, serde_json::Value), serde_json::Value, ClientError>;
+
+/// Wrap a [`RpcSender`] with [`tower::limit::RateLimit`], requests over the limit wait
+/// in the buffer until they can be sent.
+pub struct RateLimitedSender {
+    inner: Arc<dyn RpcSender + Send + Sync>,
+    svc: Svc,
+}
+
+impl RateLimitedSender {
+    pub fn new<S>(inner: S, config: &RateLimitConfig) -> Self
+    where
+        S: RpcSender + Send + Sync + 'static,
+    {
+        let inner: Arc<dyn RpcSender + Send + Sync> = Arc::new(inner);
+        let (num, per) = rate(config);
+        let sender = inner.clone();
+        let svc = tower::ServiceBuilder::new()
+            .rate_limit(num, per)
+            .service_fn(move |(request, params): (RpcRequest, serde_json::Value)| {
+                let sender = sender.clone();
+                async move { sender.send(request, params).await }
+            });
+        let svc = Svc::from_service(svc, worker_error, 1024);
+        Self { inner, svc }
+    }
+}
+
+fn worker_error(error: tower::BoxError) -> ClientError {
+    ClientErrorKind::Custom(error.to_string()).into()
+}
+
+/// Allow `burst` requests per `burst / requests_per_second` seconds, this averages to
+/// `requests_per_second` while allowing bursts.
+fn rate(config: &RateLimitConfig) -> (u64, Duration) {
+    let rps = config.requests_per_second.get();
+    let burst = config.burst.unwrap_or(config.requests_per_second).get();
+    (
+        burst as u64,
+        Duration::from_secs_f64(burst as f64 / rps as f64),
+    )
+}
+
+#[async_trait::async_trait]
+impl RpcSender for RateLimitedSender {
+    async fn send(
+        &self,
+        request: RpcRequest,
+        params: serde_json::Value,
+    ) -> Result<serde_json::Value> {
+        self.svc.call_ref((request, params)).await
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        self.inner.get_transport_stats()
+    }
+
+    fn url(&self) -> String {
+        self.inner.url()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::num::NonZeroU32;
+
+    #[test]
+    fn test_rate() {
+        let config = RateLimitConfig {
+            requests_per_second: NonZeroU32::new(10).unwrap(),
+            burst: None,
+        };
+        assert_eq!(rate(&config), (10, Duration::from_secs(1)));
+
+        let config = RateLimitConfig {
+            requests_per_second: NonZeroU32::new(10).unwrap(),
+            burst: NonZeroU32::new(5),
+        };
+        assert_eq!(rate(&config), (5, Duration::from_millis(500)));
+    }
+}
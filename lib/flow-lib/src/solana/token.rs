@@ -0,0 +1,371 @@
+//! Build SPL token instructions, for both the token program and Token-2022.
+
+use super::{Instructions, KeypairExt};
+use anyhow::{anyhow, bail, ensure};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{
+    account::Account,
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    signer::{keypair::Keypair, Signer},
+    system_program,
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use value::Decimal;
+
+pub const TOKEN_PROGRAM_ID: Pubkey =
+    solana_sdk::pubkey!("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA");
+pub const TOKEN_2022_PROGRAM_ID: Pubkey =
+    solana_sdk::pubkey!("TokenzQdBNbLqP5VEhdkAS5EtTipov7jfLV3eA8vNfYRZ");
+pub const ASSOCIATED_TOKEN_PROGRAM_ID: Pubkey =
+    solana_sdk::pubkey!("ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL");
+
+/// Size of a mint without extensions.
+const MINT_SIZE: usize = 82;
+/// After `mint_authority: COption<Pubkey>` and `supply: u64`.
+const MINT_DECIMALS_OFFSET: usize = 44;
+const MINT_IS_INITIALIZED_OFFSET: usize = 45;
+
+/// `TransferChecked` of the token program.
+const TRANSFER_CHECKED: u8 = 12;
+/// `CreateIdempotent` of the associated token account program.
+const CREATE_IDEMPOTENT: u8 = 1;
+
+pub fn get_associated_token_address(
+    owner: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+) -> Pubkey {
+    Pubkey::find_program_address(
+        &[owner.as_ref(), token_program.as_ref(), mint.as_ref()],
+        &ASSOCIATED_TOKEN_PROGRAM_ID,
+    )
+    .0
+}
+
+/// Create the associated token account of `owner`, succeeds if it already exists.
+pub fn create_associated_token_account_idempotent(
+    payer: &Pubkey,
+    owner: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+) -> Instruction {
+    let address = get_associated_token_address(owner, mint, token_program);
+    Instruction {
+        program_id: ASSOCIATED_TOKEN_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(*payer, true),
+            AccountMeta::new(address, false),
+            AccountMeta::new_readonly(*owner, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(system_program::ID, false),
+            AccountMeta::new_readonly(*token_program, false),
+        ],
+        data: vec![CREATE_IDEMPOTENT],
+    }
+}
+
+pub fn transfer_checked(
+    token_program: &Pubkey,
+    source: &Pubkey,
+    mint: &Pubkey,
+    destination: &Pubkey,
+    authority: &Pubkey,
+    amount: u64,
+    decimals: u8,
+) -> Instruction {
+    let mut data = Vec::with_capacity(10);
+    data.push(TRANSFER_CHECKED);
+    data.extend_from_slice(&amount.to_le_bytes());
+    data.push(decimals);
+    Instruction {
+        program_id: *token_program,
+        accounts: vec![
+            AccountMeta::new(*source, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new(*destination, false),
+            AccountMeta::new_readonly(*authority, true),
+        ],
+        data,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MintInfo {
+    /// Owner of the mint, the token program or Token-2022.
+    pub token_program: Pubkey,
+    pub decimals: u8,
+}
+
+pub fn parse_mint(mint: &Pubkey, account: &Account) -> Result<MintInfo, anyhow::Error> {
+    if account.owner != TOKEN_PROGRAM_ID && account.owner != TOKEN_2022_PROGRAM_ID {
+        bail!("{} is not a token mint, owner is {}", mint, account.owner);
+    }
+    // Token-2022 mints have extensions after the base mint
+    ensure!(
+        account.data.len() >= MINT_SIZE,
+        "{} is not a token mint",
+        mint
+    );
+    ensure!(
+        account.data[MINT_IS_INITIALIZED_OFFSET] == 1,
+        "mint {} is not initialized",
+        mint
+    );
+    Ok(MintInfo {
+        token_program: account.owner,
+        decimals: account.data[MINT_DECIMALS_OFFSET],
+    })
+}
+
+pub async fn get_mint(rpc: &RpcClient, mint: &Pubkey) -> Result<MintInfo, anyhow::Error> {
+    let account = rpc
+        .get_account_with_commitment(mint, rpc.commitment())
+        .await?
+        .value
+        .ok_or_else(|| anyhow!("mint {} not found", mint))?;
+    parse_mint(mint, &account)
+}
+
+/// Cache of [`get_mint`] results, decimals and the token program of a mint never change.
+///
+/// Clones share the same cache.
+#[derive(Clone, Default, Debug)]
+pub struct MintCache {
+    mints: Arc<Mutex<HashMap<Pubkey, MintInfo>>>,
+}
+
+impl MintCache {
+    pub fn get(&self, mint: &Pubkey) -> Option<MintInfo> {
+        self.mints.lock().unwrap().get(mint).copied()
+    }
+
+    pub fn insert(&self, mint: Pubkey, info: MintInfo) {
+        self.mints.lock().unwrap().insert(mint, info);
+    }
+
+    pub async fn get_or_fetch(
+        &self,
+        rpc: &RpcClient,
+        mint: &Pubkey,
+    ) -> Result<MintInfo, anyhow::Error> {
+        if let Some(info) = self.get(mint) {
+            return Ok(info);
+        }
+        let info = get_mint(rpc, mint).await?;
+        self.insert(*mint, info);
+        Ok(info)
+    }
+}
+
+/// Associated token account of an owner, see [`get_or_create_ata`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssociatedTokenAccount {
+    pub address: Pubkey,
+    pub token_program: Pubkey,
+    /// Instruction creating the account, `None` if it already exists.
+    pub create: Option<Instruction>,
+}
+
+/// Derive the associated token account of `owner`, with the token program owning `mint`.
+///
+/// If the account doesn't exist, [`AssociatedTokenAccount::create`] is a `CreateIdempotent`
+/// instruction paid by `payer`, to run before instructions using the account.
+pub async fn get_or_create_ata(
+    rpc: &RpcClient,
+    payer: &Pubkey,
+    owner: &Pubkey,
+    mint: &Pubkey,
+) -> Result<AssociatedTokenAccount, anyhow::Error> {
+    let token_program = get_mint(rpc, mint).await?.token_program;
+    let address = get_associated_token_address(owner, mint, &token_program);
+    let account = rpc
+        .get_account_with_commitment(&address, rpc.commitment())
+        .await?
+        .value;
+    let create = match account {
+        None => Some(create_associated_token_account_idempotent(
+            payer,
+            owner,
+            mint,
+            &token_program,
+        )),
+        Some(account) if account.owner == token_program => None,
+        Some(account) => bail!(
+            "associated token account {} is owned by {}, expected {}",
+            address,
+            account.owner,
+            token_program
+        ),
+    };
+    Ok(AssociatedTokenAccount {
+        address,
+        token_program,
+        create,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenAmount {
+    /// In base units.
+    Raw(u64),
+    /// Using the decimals of the mint, e.g. `1.5` of a mint with 6 decimals is `1500000`.
+    Ui(Decimal),
+}
+
+impl TokenAmount {
+    /// Returns `None` if the amount is negative or too large, fractions of a base unit are
+    /// rounded down.
+    pub fn to_raw(&self, decimals: u8) -> Option<u64> {
+        match *self {
+            TokenAmount::Raw(amount) => Some(amount),
+            TokenAmount::Ui(amount) => {
+                if amount.is_sign_negative() && !amount.is_zero() {
+                    return None;
+                }
+                let unit = Decimal::from(10u64.checked_pow(decimals as u32)?);
+                u64::try_from(amount.checked_mul(unit)?.floor()).ok()
+            }
+        }
+    }
+}
+
+/// Instructions of [`transfer`], with the mint already fetched.
+pub fn transfer_instructions(
+    fee_payer: &Pubkey,
+    sender: &Pubkey,
+    recipient: &Pubkey,
+    mint: &Pubkey,
+    info: MintInfo,
+    amount: TokenAmount,
+) -> Result<Vec<Instruction>, anyhow::Error> {
+    let amount = amount
+        .to_raw(info.decimals)
+        .ok_or_else(|| anyhow!("invalid amount {:?}", amount))?;
+    let program = &info.token_program;
+    let source = get_associated_token_address(sender, mint, program);
+    let destination = get_associated_token_address(recipient, mint, program);
+    Ok(vec![
+        create_associated_token_account_idempotent(fee_payer, recipient, mint, program),
+        transfer_checked(
+            program,
+            &source,
+            mint,
+            &destination,
+            sender,
+            amount,
+            info.decimals,
+        ),
+    ])
+}
+
+/// Transfer tokens between the associated token accounts of `sender` and `recipient`.
+///
+/// The recipient's account is created by `fee_payer` if it doesn't exist, with
+/// `CreateIdempotent` so that the transfer doesn't fail if it already exists when the
+/// transaction lands.
+pub async fn transfer(
+    rpc: &RpcClient,
+    fee_payer: &Keypair,
+    sender: &Keypair,
+    recipient: &Pubkey,
+    mint: &Pubkey,
+    amount: TokenAmount,
+) -> Result<Instructions, anyhow::Error> {
+    let info = get_mint(rpc, mint).await?;
+    let instructions = transfer_instructions(
+        &fee_payer.pubkey(),
+        &sender.pubkey(),
+        recipient,
+        mint,
+        info,
+        amount,
+    )?;
+    let mut signers = vec![fee_payer.clone_keypair()];
+    if sender.pubkey() != fee_payer.pubkey() {
+        signers.push(sender.clone_keypair());
+    }
+    Ok(Instructions {
+        fee_payer: fee_payer.pubkey(),
+        signers,
+        instructions,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_raw() {
+        let ui = |s: &str| TokenAmount::Ui(s.parse().unwrap());
+        assert_eq!(ui("1.5").to_raw(6), Some(1_500_000));
+        assert_eq!(ui("0.0000019").to_raw(6), Some(1));
+        assert_eq!(ui("-1").to_raw(6), None);
+        assert_eq!(ui("1").to_raw(20), None);
+        assert_eq!(TokenAmount::Raw(10).to_raw(6), Some(10));
+    }
+
+    #[tokio::test]
+    async fn test_mint_cache() {
+        let cache = MintCache::default();
+        let mint = Pubkey::new_unique();
+        let info = MintInfo {
+            token_program: TOKEN_2022_PROGRAM_ID,
+            decimals: 9,
+        };
+        cache.clone().insert(mint, info);
+        // served without calling the RPC
+        let rpc = RpcClient::new("http://127.0.0.1:1".to_owned());
+        assert_eq!(cache.get_or_fetch(&rpc, &mint).await.unwrap(), info);
+        assert!(cache
+            .get_or_fetch(&rpc, &Pubkey::new_unique())
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn test_transfer_instructions() {
+        let fee_payer = Pubkey::new_unique();
+        let sender = Pubkey::new_unique();
+        let recipient = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let mut data = vec![0; MINT_SIZE];
+        data[MINT_DECIMALS_OFFSET] = 6;
+        data[MINT_IS_INITIALIZED_OFFSET] = 1;
+        let account = Account {
+            lamports: 1,
+            data,
+            owner: TOKEN_PROGRAM_ID,
+            executable: false,
+            rent_epoch: 0,
+        };
+        let info = parse_mint(&mint, &account).unwrap();
+        let instructions = transfer_instructions(
+            &fee_payer,
+            &sender,
+            &recipient,
+            &mint,
+            info,
+            TokenAmount::Ui("2".parse().unwrap()),
+        )
+        .unwrap();
+        let destination = get_associated_token_address(&recipient, &mint, &TOKEN_PROGRAM_ID);
+        assert_eq!(instructions[0].accounts[1].pubkey, destination);
+        assert_eq!(instructions[0].data, [CREATE_IDEMPOTENT]);
+        assert_eq!(instructions[1].program_id, TOKEN_PROGRAM_ID);
+        assert_eq!(instructions[1].accounts[2].pubkey, destination);
+        assert_eq!(
+            instructions[1].data,
+            [
+                [TRANSFER_CHECKED].as_slice(),
+                &2_000_000u64.to_le_bytes(),
+                &[6]
+            ]
+            .concat()
+        );
+    }
+}
@@ -7,20 +7,33 @@ use nom::{
     character::complete::{char, u64},
     IResult,
 };
+use serde::Serialize;
+use serde_with::{serde_as, DisplayFromStr};
 use solana_client::{
     client_error::{ClientError, ClientErrorKind},
     nonblocking::rpc_client::RpcClient,
-    rpc_request::{RpcError, RpcResponseErrorData},
+    rpc_request::{RpcError, RpcResponseErrorData, TokenAccountsFilter},
     rpc_response::RpcSimulateTransactionResult,
 };
 use solana_sdk::{
+    account::Account,
     clock::{Slot, UnixTimestamp},
+    commitment_config::CommitmentConfig,
     feature_set::FeatureSet,
+    hash::Hash,
+    instruction::InstructionError,
+    native_token::LAMPORTS_PER_SOL,
+    nonce::state::{Data as NonceData, State as NonceState, Versions as NonceVersions},
     precompiles::verify_if_precompile,
+    pubkey::Pubkey,
     signature::Signature,
-    transaction::Transaction,
+    system_program,
+    transaction::{Transaction, TransactionError},
 };
-use solana_transaction_status::{EncodedTransaction, TransactionBinaryEncoding};
+use solana_transaction_status::{
+    EncodedTransaction, TransactionBinaryEncoding, TransactionConfirmationStatus, TransactionStatus,
+};
+use value::Decimal;
 
 pub fn find_failed_instruction(err: &ClientError) -> Option<usize> {
     if let ClientErrorKind::RpcError(RpcError::RpcResponseError { message, .. }) = &err.kind {
@@ -116,6 +129,29 @@ pub fn verbose_solana_error(err: &ClientError) -> String {
     }
 }
 
+/// Program logs of a failed preflight simulation.
+pub fn program_logs(err: &ClientError) -> Vec<String> {
+    match &err.kind {
+        ClientErrorKind::RpcError(RpcError::RpcResponseError {
+            data:
+                RpcResponseErrorData::SendTransactionPreflightFailure(RpcSimulateTransactionResult {
+                    logs: Some(logs),
+                    ..
+                }),
+            ..
+        }) => logs.clone(),
+        _ => Vec::new(),
+    }
+}
+
+/// Code of `InstructionError::Custom`, e.g. an Anchor error code.
+pub fn custom_error_code(error: &TransactionError) -> Option<u32> {
+    match error {
+        TransactionError::InstructionError(_, InstructionError::Custom(code)) => Some(*code),
+        _ => None,
+    }
+}
+
 pub struct TransactionWithMeta {
     pub slot: Slot,
     pub transaction: Transaction,
@@ -172,3 +208,205 @@ pub fn verify_precompiles(tx: &Transaction, feature_set: &FeatureSet) -> Result<
     }
     Ok(())
 }
+
+/// Token amount in base units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenBalance {
+    pub amount: u64,
+    pub decimals: u8,
+}
+
+impl TokenBalance {
+    pub fn ui_amount(&self) -> f64 {
+        self.amount as f64 / 10f64.powi(self.decimals as i32)
+    }
+}
+
+/// Sum of `mint` balances in all token accounts of `owner`, works with both Token and
+/// Token-2022 programs. Returns `0` if `owner` has no token account.
+pub async fn get_token_balance(
+    rpc: &RpcClient,
+    owner: &Pubkey,
+    mint: &Pubkey,
+) -> Result<TokenBalance, ClientError> {
+    let accounts = rpc
+        .get_token_accounts_by_owner(owner, TokenAccountsFilter::Mint(*mint))
+        .await?;
+    if accounts.is_empty() {
+        let supply = rpc.get_token_supply(mint).await?;
+        return Ok(TokenBalance {
+            amount: 0,
+            decimals: supply.decimals,
+        });
+    }
+    let mut balance = TokenBalance {
+        amount: 0,
+        decimals: 0,
+    };
+    for keyed in accounts {
+        // accounts are returned with `jsonParsed` encoding
+        let account = serde_json::to_value(&keyed.account).map_err(ClientErrorKind::from)?;
+        let token_amount = &account["data"]["parsed"]["info"]["tokenAmount"];
+        let amount = token_amount["amount"]
+            .as_str()
+            .and_then(|s| s.parse::<u64>().ok());
+        let decimals = token_amount["decimals"].as_u64();
+        let (Some(amount), Some(decimals)) = (amount, decimals) else {
+            return Err(
+                ClientErrorKind::Custom(format!("invalid token account {}", keyed.pubkey)).into(),
+            );
+        };
+        balance.amount = balance.amount.saturating_add(amount);
+        balance.decimals = decimals as u8;
+    }
+    Ok(balance)
+}
+
+/// A blockhash and the last block height at which transactions using it can be
+/// processed, see [`get_latest_blockhash`].
+#[serde_as]
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatestBlockhash {
+    #[serde_as(as = "DisplayFromStr")]
+    pub blockhash: Hash,
+    pub last_valid_block_height: u64,
+}
+
+impl LatestBlockhash {
+    /// Whether a transaction using the blockhash can still be processed at `block_height`.
+    pub fn is_valid_at(&self, block_height: u64) -> bool {
+        block_height <= self.last_valid_block_height
+    }
+}
+
+/// Get the latest blockhash, for transactions that are signed or sent later and need
+/// to know when they expire.
+pub async fn get_latest_blockhash(
+    rpc: &RpcClient,
+    commitment: CommitmentConfig,
+) -> Result<LatestBlockhash, ClientError> {
+    let (blockhash, last_valid_block_height) =
+        rpc.get_latest_blockhash_with_commitment(commitment).await?;
+    Ok(LatestBlockhash {
+        blockhash,
+        last_valid_block_height,
+    })
+}
+
+/// Status of a transaction, see [`get_signature_statuses`].
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum SignatureStatus {
+    /// Not processed, or too old to be in the RPC node's status cache.
+    NotFound,
+    Processed,
+    Confirmed,
+    Finalized,
+    Failed {
+        error: TransactionError,
+    },
+}
+
+impl From<Option<TransactionStatus>> for SignatureStatus {
+    fn from(status: Option<TransactionStatus>) -> Self {
+        match status {
+            None => Self::NotFound,
+            Some(TransactionStatus {
+                err: Some(error), ..
+            }) => Self::Failed { error },
+            Some(status) => match status.confirmation_status() {
+                TransactionConfirmationStatus::Processed => Self::Processed,
+                TransactionConfirmationStatus::Confirmed => Self::Confirmed,
+                TransactionConfirmationStatus::Finalized => Self::Finalized,
+            },
+        }
+    }
+}
+
+/// Maximum number of signatures in a `getSignatureStatuses` request.
+const MAX_SIGNATURE_STATUSES: usize = 256;
+
+/// Get statuses of `signatures` with `getSignatureStatuses`, one request per 256
+/// signatures. Statuses are returned in the same order.
+pub async fn get_signature_statuses(
+    rpc: &RpcClient,
+    signatures: &[Signature],
+) -> Result<Vec<SignatureStatus>, ClientError> {
+    let mut statuses = Vec::with_capacity(signatures.len());
+    for chunk in signatures.chunks(MAX_SIGNATURE_STATUSES) {
+        let resp = rpc.get_signature_statuses(chunk).await?;
+        statuses.extend(resp.value.into_iter().map(SignatureStatus::from));
+    }
+    Ok(statuses)
+}
+
+/// Get the current state of an initialized nonce account.
+pub async fn get_nonce_data(
+    rpc: &RpcClient,
+    nonce_account: &Pubkey,
+    commitment: CommitmentConfig,
+) -> Result<NonceData, anyhow::Error> {
+    let account = rpc
+        .get_account_with_commitment(nonce_account, commitment)
+        .await?
+        .value
+        .ok_or_else(|| anyhow!("nonce account {} not found", nonce_account))?;
+    parse_nonce_account(nonce_account, &account)
+}
+
+pub fn parse_nonce_account(
+    nonce_account: &Pubkey,
+    account: &Account,
+) -> Result<NonceData, anyhow::Error> {
+    if account.owner != system_program::ID {
+        bail!("{} is not a nonce account", nonce_account);
+    }
+    let versions = bincode::deserialize::<NonceVersions>(&account.data)
+        .map_err(|_| anyhow!("{} is not a nonce account", nonce_account))?;
+    match versions.state() {
+        NonceState::Initialized(data) => Ok(data.clone()),
+        NonceState::Uninitialized => bail!("nonce account {} is not initialized", nonce_account),
+    }
+}
+
+/// Convert lamports to SOL without rounding, e.g. `1000000` is `0.001`.
+pub fn lamports_to_sol(lamports: u64) -> Decimal {
+    Decimal::from_i128_with_scale(lamports as i128, 9).normalize()
+}
+
+/// Convert SOL to lamports, fractions of a lamport are rounded down.
+///
+/// Returns `None` if `sol` is negative or too large.
+pub fn sol_to_lamports(sol: Decimal) -> Option<u64> {
+    if sol.is_sign_negative() && !sol.is_zero() {
+        return None;
+    }
+    let lamports = sol.checked_mul(Decimal::from(LAMPORTS_PER_SOL))?.floor();
+    u64::try_from(lamports).ok()
+}
+
+/// Serialize a lamports field as a SOL amount, for nodes that take or return SOL:
+///
+/// ```ignore
+/// #[serde(with = "flow_lib::solana::sol")]
+/// amount: u64,
+/// ```
+pub mod sol {
+    use super::{lamports_to_sol, sol_to_lamports};
+
+    pub fn serialize<S>(lamports: &u64, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        value::decimal::serialize(&lamports_to_sol(*lamports), s)
+    }
+
+    pub fn deserialize<'de, D>(d: D) -> Result<u64, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let sol = value::decimal::deserialize(d)?;
+        sol_to_lamports(sol)
+            .ok_or_else(|| serde::de::Error::custom(format!("invalid SOL amount: {}", sol)))
+    }
+}
@@ -1,17 +1,29 @@
-use super::{parse_action_memo, parse_rpc_memo_field};
+use super::{get_nonce_data, parse_action_memo, parse_rpc_memo_field};
+use crate::utils::Backoff;
 use anyhow::{anyhow, ensure};
+use futures::{future::Either, stream::BoxStream, StreamExt};
+use solana_account_decoder::UiAccountEncoding;
 use solana_client::{
-    client_error, nonblocking::rpc_client::RpcClient,
-    rpc_client::GetConfirmedSignaturesForAddress2Config, rpc_request::RpcError,
+    client_error,
+    nonblocking::{
+        pubsub_client::{PubsubClient, PubsubClientError},
+        rpc_client::RpcClient,
+    },
+    rpc_client::GetConfirmedSignaturesForAddress2Config,
+    rpc_config::{RpcAccountInfoConfig, RpcSignatureSubscribeConfig},
+    rpc_request::RpcError,
+    rpc_response::RpcSignatureResult,
 };
 use solana_sdk::{
-    clock::MAX_HASH_AGE_IN_SECONDS,
+    account::Account,
+    clock::{Slot, MAX_HASH_AGE_IN_SECONDS},
     commitment_config::{CommitmentConfig, CommitmentLevel},
     hash::Hash,
     pubkey::Pubkey,
     signature::Signature,
 };
 use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
 
 pub const ACTION_CONFIRM_TIMEOUT: Duration = Duration::from_secs(60 * 3);
 
@@ -75,14 +87,92 @@ pub async fn confirm_action_transaction(
     }
 }
 
-// https://docs.rs/solana-rpc-client/2.0.3/src/solana_rpc_client/nonblocking/rpc_client.rs.html#1059-1064
-// removed progress bar
+/// How long a transaction stays valid.
+#[derive(Debug, Clone, Copy)]
+pub enum TxLifetime {
+    /// Valid until the blockhash expires.
+    Blockhash(Hash),
+    /// Valid until the nonce account is advanced.
+    Nonce { account: Pubkey, nonce: Hash },
+}
+
+impl TxLifetime {
+    async fn is_valid(&self, rpc: &RpcClient) -> Result<bool, client_error::ClientError> {
+        match self {
+            TxLifetime::Blockhash(blockhash) => {
+                rpc.is_blockhash_valid(blockhash, CommitmentConfig::processed())
+                    .await
+            }
+            TxLifetime::Nonce { account, nonce } => {
+                let data = get_nonce_data(rpc, account, CommitmentConfig::processed())
+                    .await
+                    .map_err(|error| RpcError::ForUser(error.to_string()))?;
+                Ok(data.blockhash() == *nonce)
+            }
+        }
+    }
+}
+
 pub async fn confirm_transaction(
     rpc: &RpcClient,
     signature: &Signature,
     recent_blockhash: &Hash,
     commitment: CommitmentConfig,
 ) -> Result<(), client_error::ClientError> {
+    confirm_transaction_with_lifetime(
+        rpc,
+        signature,
+        TxLifetime::Blockhash(*recent_blockhash),
+        commitment,
+    )
+    .await
+}
+
+/// Result of [`poll_confirmation`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfirmStatus {
+    /// Reached the requested commitment.
+    Confirmed,
+    /// Not processed and the transaction's lifetime has ended, it can be re-signed with
+    /// a new blockhash and sent again.
+    Expired,
+    /// Not confirmed before the deadline.
+    TimedOut,
+}
+
+pub async fn confirm_transaction_with_lifetime(
+    rpc: &RpcClient,
+    signature: &Signature,
+    lifetime: TxLifetime,
+    commitment: CommitmentConfig,
+) -> Result<(), client_error::ClientError> {
+    match poll_confirmation(rpc, signature, lifetime, commitment, None).await? {
+        ConfirmStatus::Confirmed => Ok(()),
+        ConfirmStatus::Expired | ConfirmStatus::TimedOut => Err(unable_to_confirm()),
+    }
+}
+
+pub fn unable_to_confirm() -> client_error::ClientError {
+    RpcError::ForUser(
+        "unable to confirm transaction. \
+                                      This can happen in situations such as transaction expiration \
+                                      and insufficient fee-payer funds"
+            .to_string(),
+    )
+    .into()
+}
+
+// https://docs.rs/solana-rpc-client/2.0.3/src/solana_rpc_client/nonblocking/rpc_client.rs.html#1059-1064
+// removed progress bar
+/// Wait for the transaction to reach `commitment`, giving up once `deadline` has passed.
+pub async fn poll_confirmation(
+    rpc: &RpcClient,
+    signature: &Signature,
+    lifetime: TxLifetime,
+    commitment: CommitmentConfig,
+    deadline: Option<Instant>,
+) -> Result<ConfirmStatus, client_error::ClientError> {
+    let timed_out = || deadline.is_some_and(|deadline| Instant::now() >= deadline);
     let mut confirmations = 0;
 
     let now = Instant::now();
@@ -93,30 +183,23 @@ pub async fn confirm_transaction(
             .get_signature_status_with_commitment(signature, CommitmentConfig::processed())
             .await?;
         if status.is_none() {
-            let blockhash_not_found = !rpc
-                .is_blockhash_valid(recent_blockhash, CommitmentConfig::processed())
-                .await?;
+            let blockhash_not_found = !lifetime.is_valid(rpc).await?;
             if blockhash_not_found && now.elapsed() >= confirm_transaction_initial_timeout {
                 break (signature, status);
             }
+            if timed_out() {
+                return Ok(ConfirmStatus::TimedOut);
+            }
         } else {
             break (signature, status);
         }
 
         tokio::time::sleep(Duration::from_millis(500)).await;
     };
-    if let Some(result) = status {
-        if let Err(err) = result {
-            return Err(err.into());
-        }
-    } else {
-        return Err(RpcError::ForUser(
-            "unable to confirm transaction. \
-                                      This can happen in situations such as transaction expiration \
-                                      and insufficient fee-payer funds"
-                .to_string(),
-        )
-        .into());
+    match status {
+        Some(Err(err)) => return Err(err.into()),
+        Some(Ok(())) => {}
+        None => return Ok(ConfirmStatus::Expired),
     }
     let now = Instant::now();
     loop {
@@ -127,9 +210,12 @@ pub async fn confirm_transaction(
             .await?
             .is_some()
         {
-            return Ok(());
+            return Ok(ConfirmStatus::Confirmed);
         }
 
+        if timed_out() {
+            return Ok(ConfirmStatus::TimedOut);
+        }
         tokio::time::sleep(Duration::from_millis(500)).await;
         confirmations = rpc
             .get_num_blocks_since_signature_confirmation(signature)
@@ -145,6 +231,181 @@ pub async fn confirm_transaction(
     }
 }
 
+/// How often [`subscribe_confirmation`] checks if the transaction expired.
+const EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Same as [`poll_confirmation`], but wait for a `signatureSubscribe` notification instead
+/// of polling signature status. Fall back to polling if `ws_url` is not available.
+pub async fn subscribe_confirmation(
+    rpc: &RpcClient,
+    ws_url: &str,
+    signature: &Signature,
+    lifetime: TxLifetime,
+    commitment: CommitmentConfig,
+    deadline: Option<Instant>,
+) -> Result<ConfirmStatus, client_error::ClientError> {
+    let client = match PubsubClient::new(ws_url).await {
+        Ok(client) => client,
+        Err(error) => {
+            tracing::warn!("{}: {}, polling signature status instead", ws_url, error);
+            return poll_confirmation(rpc, signature, lifetime, commitment, deadline).await;
+        }
+    };
+    let config = RpcSignatureSubscribeConfig {
+        commitment: Some(commitment),
+        enable_received_notification: Some(false),
+    };
+    let (mut stream, unsubscribe) = match client.signature_subscribe(signature, Some(config)).await
+    {
+        Ok(subscription) => subscription,
+        Err(error) => {
+            tracing::warn!(
+                "signatureSubscribe: {}, polling signature status instead",
+                error
+            );
+            return poll_confirmation(rpc, signature, lifetime, commitment, deadline).await;
+        }
+    };
+
+    let result = loop {
+        match tokio::time::timeout(EXPIRY_CHECK_INTERVAL, stream.next()).await {
+            Ok(Some(resp)) => match resp.value {
+                RpcSignatureResult::ProcessedSignature(result) => match result.err {
+                    Some(err) => break Some(Err(err.into())),
+                    None => break Some(Ok(ConfirmStatus::Confirmed)),
+                },
+                RpcSignatureResult::ReceivedSignature(_) => {}
+            },
+            // subscription closed
+            Ok(None) => break None,
+            Err(_) => {
+                // the notification might be sent before we subscribed, or never sent if
+                // the transaction expired
+                if rpc
+                    .get_signature_status_with_commitment(signature, commitment)
+                    .await?
+                    .is_some()
+                {
+                    break Some(Ok(ConfirmStatus::Confirmed));
+                }
+                let processed = rpc
+                    .get_signature_status_with_commitment(signature, CommitmentConfig::processed())
+                    .await?;
+                match processed {
+                    Some(Err(err)) => break Some(Err(err.into())),
+                    Some(Ok(())) => {}
+                    None => {
+                        if !lifetime.is_valid(rpc).await? {
+                            break Some(Ok(ConfirmStatus::Expired));
+                        }
+                    }
+                }
+                if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    break Some(Ok(ConfirmStatus::TimedOut));
+                }
+            }
+        }
+    };
+    drop(stream);
+    unsubscribe().await;
+
+    match result {
+        Some(result) => result,
+        None => poll_confirmation(rpc, signature, lifetime, commitment, deadline).await,
+    }
+}
+
+/// New state of an account, see [`subscribe_account`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountUpdate {
+    pub slot: Slot,
+    pub account: Account,
+}
+
+/// Updates buffered when the stream is not polled, the connection waits when it is full.
+const ACCOUNT_UPDATE_BUFFER: usize = 16;
+
+const RECONNECT_BACKOFF: Backoff = Backoff::new(
+    Duration::from_millis(500),
+    Duration::from_secs(30),
+    u32::MAX,
+);
+
+/// Stream changes of `pubkey`'s account with `accountSubscribe`.
+///
+/// The subscription runs in a task, which reconnects and subscribes again when the
+/// connection drops; changes made while reconnecting are not sent. The task unsubscribes
+/// once the stream is dropped.
+pub fn subscribe_account(
+    ws_url: String,
+    pubkey: Pubkey,
+    commitment: CommitmentConfig,
+) -> BoxStream<'static, AccountUpdate> {
+    let (tx, rx) = mpsc::channel(ACCOUNT_UPDATE_BUFFER);
+    tokio::spawn(async move {
+        let config = RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            commitment: Some(commitment),
+            ..<_>::default()
+        };
+        let mut attempt = 0;
+        loop {
+            match forward_account_updates(&ws_url, &pubkey, config.clone(), &tx).await {
+                Ok(()) => attempt = 0,
+                Err(error) => {
+                    tracing::warn!("accountSubscribe {}: {}", pubkey, error);
+                    attempt += 1;
+                }
+            }
+            let delay = RECONNECT_BACKOFF
+                .delay(attempt)
+                .unwrap_or(RECONNECT_BACKOFF.max_delay);
+            let sleep = std::pin::pin!(tokio::time::sleep(delay));
+            let closed = std::pin::pin!(tx.closed());
+            if let Either::Right(_) = futures::future::select(sleep, closed).await {
+                break;
+            }
+        }
+    });
+    futures::stream::unfold(rx, |mut rx| async move {
+        let update = rx.recv().await?;
+        Some((update, rx))
+    })
+    .boxed()
+}
+
+/// Send notifications of one connection to `tx`, until the connection or `tx` is closed.
+async fn forward_account_updates(
+    ws_url: &str,
+    pubkey: &Pubkey,
+    config: RpcAccountInfoConfig,
+    tx: &mpsc::Sender<AccountUpdate>,
+) -> Result<(), PubsubClientError> {
+    let client = PubsubClient::new(ws_url).await?;
+    let (mut stream, unsubscribe) = client.account_subscribe(pubkey, Some(config)).await?;
+    loop {
+        let closed = std::pin::pin!(tx.closed());
+        let resp = match futures::future::select(stream.next(), closed).await {
+            Either::Left((Some(resp), _)) => resp,
+            _ => break,
+        };
+        let Some(account) = resp.value.decode::<Account>() else {
+            tracing::warn!("accountSubscribe {}: can't decode account", pubkey);
+            continue;
+        };
+        let update = AccountUpdate {
+            slot: resp.context.slot,
+            account,
+        };
+        if tx.send(update).await.is_err() {
+            break;
+        }
+    }
+    drop(stream);
+    unsubscribe().await;
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use solana_sdk::pubkey;
@@ -0,0 +1,92 @@
+//! Delays between retries, shared by services and commands so that they back off the
+//! same way.
+
+use std::time::Duration;
+
+/// Exponential backoff with full jitter.
+///
+/// The delay before retry `n` (starting from 0) is `base * 2^n`, capped at `max_delay`.
+/// With jitter, the delay is a random duration between zero and that value, so that
+/// clients failing at the same time don't retry at the same time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Backoff {
+    pub base: Duration,
+    pub max_delay: Duration,
+    /// Number of retries, `0` disables retrying.
+    pub max_attempts: u32,
+    pub jitter: bool,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new(Duration::from_millis(200), Duration::from_secs(30), 3)
+    }
+}
+
+impl Backoff {
+    pub const fn new(base: Duration, max_delay: Duration, max_attempts: u32) -> Self {
+        Self {
+            base,
+            max_delay,
+            max_attempts,
+            jitter: true,
+        }
+    }
+
+    pub const fn with_jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    pub const fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// Delay before retry `attempt` without jitter, the upper bound of [`Backoff::delay`].
+    pub fn max_delay_of(&self, attempt: u32) -> Duration {
+        self.base
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay)
+    }
+
+    /// Delay before retry `attempt`, `None` if there are no retries left.
+    pub fn delay(&self, attempt: u32) -> Option<Duration> {
+        if attempt >= self.max_attempts {
+            return None;
+        }
+        let max = self.max_delay_of(attempt);
+        Some(if self.jitter {
+            max.mul_f64(rand::random::<f64>())
+        } else {
+            max
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay() {
+        let backoff =
+            Backoff::new(Duration::from_millis(100), Duration::from_secs(1), 6).with_jitter(false);
+        let delays = (0..7).map(|n| backoff.delay(n)).collect::<Vec<_>>();
+        assert_eq!(
+            delays,
+            [100, 200, 400, 800, 1000, 1000]
+                .map(|ms| Some(Duration::from_millis(ms)))
+                .into_iter()
+                .chain([None])
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(backoff.max_delay_of(u32::MAX), Duration::from_secs(1));
+
+        let backoff = backoff.with_jitter(true);
+        for n in 0..6 {
+            assert!(backoff.delay(n).unwrap() <= backoff.max_delay_of(n));
+        }
+        assert_eq!(backoff.with_max_attempts(0).delay(0), None);
+    }
+}
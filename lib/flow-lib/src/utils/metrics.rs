@@ -0,0 +1,49 @@
+//! Metrics of service calls, recorded with the [`metrics`](https://docs.rs/metrics) facade
+//! when the `metrics` feature is enabled, otherwise these functions do nothing.
+//!
+//! Install a recorder, e.g. `metrics-exporter-prometheus`, to export them:
+//! - `flow_service_calls_total`: counter, label `service`.
+//! - `flow_service_errors_total`: counter, labels `service`, `error`.
+//! - `flow_service_call_duration_seconds`: histogram, label `service`.
+//!
+//! The `metrics-flow-run-id` feature adds a `flow_run_id` label when the call is part of a
+//! flow run, this makes a new series for every run.
+
+use crate::FlowRunId;
+use std::time::Duration;
+
+/// Short name of an error variant, used as the `error` label.
+pub trait ErrorLabel {
+    fn label(&self) -> &'static str;
+}
+
+impl ErrorLabel for anyhow::Error {
+    fn label(&self) -> &'static str {
+        "error"
+    }
+}
+
+/// Record a finished call of `service`.
+#[cfg_attr(not(feature = "metrics-flow-run-id"), allow(unused_variables))]
+pub fn record_call<T, E: ErrorLabel>(
+    service: &'static str,
+    flow_run_id: Option<FlowRunId>,
+    elapsed: Duration,
+    result: &Result<T, E>,
+) {
+    #[cfg(feature = "metrics")]
+    {
+        let mut labels = vec![("service", service.to_owned())];
+        #[cfg(feature = "metrics-flow-run-id")]
+        if let Some(flow_run_id) = flow_run_id {
+            labels.push(("flow_run_id", flow_run_id.to_string()));
+        }
+        ::metrics::counter!("flow_service_calls_total", &labels).increment(1);
+        ::metrics::histogram!("flow_service_call_duration_seconds", &labels)
+            .record(elapsed.as_secs_f64());
+        if let Err(error) = result {
+            labels.push(("error", error.label().to_owned()));
+            ::metrics::counter!("flow_service_errors_total", &labels).increment(1);
+        }
+    }
+}
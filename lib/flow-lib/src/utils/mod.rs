@@ -1,8 +1,11 @@
 use std::{future::Future, pin::Pin};
 
+pub mod backoff;
 pub mod extensions;
+pub mod metrics;
 pub mod tower_client;
 
+pub use backoff::Backoff;
 pub use extensions::Extensions;
 pub use tower_client::TowerClient;
 